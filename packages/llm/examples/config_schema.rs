@@ -0,0 +1,12 @@
+//! Prints the JSON Schema for `Config` documents
+//!
+//! The published copy lives at `config.schema.json`; regenerate it with
+//! `cargo run --example config_schema > config.schema.json`.
+
+use wavs_llm::Config;
+
+fn main() {
+    let schema =
+        serde_json::to_string_pretty(&Config::json_schema()).expect("schema serializes to JSON");
+    println!("{}", schema);
+}
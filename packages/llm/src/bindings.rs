@@ -0,0 +1,7 @@
+//! Bindings for the host interfaces in `wit/world.wit`
+
+wit_bindgen::generate!({
+    path: "wit",
+    world: "wavs:llm/llm-host",
+    generate_all,
+});
@@ -0,0 +1,348 @@
+//! Response cache
+//!
+//! Identical requests (same provider, model, messages, tools and options)
+//! map to the same key, so re-processing a trigger after an aggregator retry
+//! returns the stored answer instead of calling the model again. Inside a
+//! component responses are kept in a `wasi:keyvalue` bucket; [`MemoryCache`]
+//! serves native tests.
+
+use crate::client::Message;
+use crate::consensus::canonical_json;
+use crate::errors::LlmError;
+use crate::providers::{ChatCompletion, Provider, Usage};
+use alloy_primitives::keccak256;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::bindings::wasi::keyvalue::store;
+
+/// Byte storage behind a [`ResponseCache`]
+pub trait CacheStore {
+    /// Read the value stored under `key`
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, LlmError>;
+
+    /// Store `value` under `key`, replacing any previous value
+    fn set(&self, key: &str, value: &[u8]) -> Result<(), LlmError>;
+
+    /// Remove `key` if present
+    fn delete(&self, key: &str) -> Result<(), LlmError>;
+}
+
+/// A `wasi:keyvalue` bucket provided by the WAVS host
+pub struct KeyValueCache {
+    bucket: store::Bucket,
+}
+
+impl KeyValueCache {
+    /// Open the bucket with the given identifier
+    pub fn open(identifier: &str) -> Result<Self, LlmError> {
+        if cfg!(not(target_arch = "wasm32")) {
+            return Err(LlmError::ConfigError(
+                "wasi:keyvalue is only available inside a WASI component".to_string(),
+            ));
+        }
+
+        let bucket = store::open(identifier).map_err(keyvalue_error)?;
+        Ok(Self { bucket })
+    }
+}
+
+impl CacheStore for KeyValueCache {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, LlmError> {
+        self.bucket.get(key).map_err(keyvalue_error)
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> Result<(), LlmError> {
+        self.bucket.set(key, value).map_err(keyvalue_error)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), LlmError> {
+        self.bucket.delete(key).map_err(keyvalue_error)
+    }
+}
+
+fn keyvalue_error(error: store::Error) -> LlmError {
+    let reason = match error {
+        store::Error::NoSuchStore => "no such store".to_string(),
+        store::Error::AccessDenied => "access denied".to_string(),
+        store::Error::Other(msg) => msg,
+    };
+    LlmError::CacheError(format!("keyvalue: {}", reason))
+}
+
+impl<T: CacheStore + ?Sized> CacheStore for Arc<T> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, LlmError> {
+        (**self).get(key)
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> Result<(), LlmError> {
+        (**self).set(key, value)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), LlmError> {
+        (**self).delete(key)
+    }
+}
+
+/// In-process storage, for tests and native tools
+#[derive(Default)]
+pub struct MemoryCache {
+    entries: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryCache {
+    /// Create an empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of stored entries
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Whether nothing is stored
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Vec<u8>>> {
+        // A panic while holding the lock cannot leave the map half-updated
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl CacheStore for MemoryCache {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>, LlmError> {
+        Ok(self.lock().get(key).cloned())
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> Result<(), LlmError> {
+        self.lock().insert(key.to_string(), value.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), LlmError> {
+        self.lock().remove(key);
+        Ok(())
+    }
+}
+
+/// A stored response with its lifetime
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    /// Unix time the response was stored, in seconds
+    created_at: u64,
+    /// Unix time after which the entry is ignored, if it expires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    message: Message,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    usage: Option<Usage>,
+}
+
+/// Caches chat completions by request
+///
+/// Cloning is cheap and shares the underlying store.
+#[derive(Clone)]
+pub struct ResponseCache {
+    store: Arc<dyn CacheStore + Send + Sync>,
+    ttl: Option<Duration>,
+    prefix: String,
+}
+
+impl std::fmt::Debug for ResponseCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ResponseCache")
+            .field("ttl", &self.ttl)
+            .field("prefix", &self.prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ResponseCache {
+    /// Cache responses in the given store, without expiry
+    pub fn new(store: impl CacheStore + Send + Sync + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+            ttl: None,
+            prefix: "llm:".to_string(),
+        }
+    }
+
+    /// Cache responses in a `wasi:keyvalue` bucket
+    pub fn keyvalue(bucket: &str) -> Result<Self, LlmError> {
+        Ok(Self::new(KeyValueCache::open(bucket)?))
+    }
+
+    /// Cache responses in memory
+    pub fn in_memory() -> Self {
+        Self::new(MemoryCache::new())
+    }
+
+    /// Ignore stored responses older than `ttl`
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Prefix for every key, to share a bucket between components
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// Cache key for a provider request body
+    ///
+    /// The body already carries the model, messages, tools and options in the
+    /// provider's layout; the provider kind and endpoint are added so the same
+    /// request to different servers is kept apart. API keys are not part of it.
+    pub fn key(&self, provider: &Provider, body: &Value) -> String {
+        let material = format!(
+            "{:?}\n{}\n{}",
+            provider.kind,
+            provider.chat_url(),
+            canonical_json(body)
+        );
+        format!("{}{}", self.prefix, keccak256(material.as_bytes()))
+    }
+
+    /// Look up a stored completion, dropping it if it has expired
+    pub fn get(&self, key: &str) -> Result<Option<ChatCompletion>, LlmError> {
+        let Some(bytes) = self.store.get(key)? else {
+            return Ok(None);
+        };
+
+        let entry: CacheEntry = match serde_json::from_slice(&bytes) {
+            Ok(entry) => entry,
+            // Unreadable entries (e.g. from an older version) count as misses
+            Err(_) => {
+                self.store.delete(key)?;
+                return Ok(None);
+            }
+        };
+
+        if entry.expires_at.is_some_and(|expires| now() >= expires) {
+            self.store.delete(key)?;
+            return Ok(None);
+        }
+
+        Ok(Some(ChatCompletion {
+            message: entry.message,
+            usage: entry.usage,
+        }))
+    }
+
+    /// Store a completion under `key`
+    pub fn put(&self, key: &str, completion: &ChatCompletion) -> Result<(), LlmError> {
+        let created_at = now();
+        let entry = CacheEntry {
+            created_at,
+            expires_at: self.ttl.map(|ttl| created_at.saturating_add(ttl.as_secs())),
+            message: completion.message.clone(),
+            usage: completion.usage,
+        };
+        let bytes = serde_json::to_vec(&entry)
+            .map_err(|e| LlmError::CacheError(format!("Failed to serialize entry: {}", e)))?;
+        self.store.set(key, &bytes)
+    }
+}
+
+/// Current Unix time in seconds
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn completion(text: &str) -> ChatCompletion {
+        ChatCompletion {
+            message: Message::assistant(text),
+            usage: Some(Usage {
+                prompt_tokens: 10,
+                completion_tokens: 2,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_key_is_stable_and_specific() {
+        let cache = ResponseCache::in_memory();
+        let provider = Provider::default();
+        let body = json!({"model": "llama3.2", "messages": [{"role": "user", "content": "hi"}]});
+        let reordered: Value = serde_json::from_str(
+            r#"{"messages": [{"content": "hi", "role": "user"}], "model": "llama3.2"}"#,
+        )
+        .unwrap();
+
+        let key = cache.key(&provider, &body);
+        assert!(key.starts_with("llm:0x"));
+        assert_eq!(key, cache.key(&provider, &reordered));
+
+        let other_model = json!({"model": "qwen", "messages": body["messages"]});
+        assert_ne!(key, cache.key(&provider, &other_model));
+        assert_ne!(key, cache.key(&Provider::openai("http://vllm:8000"), &body));
+        assert!(cache
+            .clone()
+            .with_prefix("agent:")
+            .key(&provider, &body)
+            .starts_with("agent:"));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let cache = ResponseCache::in_memory();
+        assert!(cache.get("llm:missing").unwrap().is_none());
+
+        cache.put("llm:a", &completion("4")).unwrap();
+        let hit = cache.get("llm:a").unwrap().unwrap();
+        assert_eq!(hit.message.content.as_deref(), Some("4"));
+        assert_eq!(hit.usage.unwrap().total_tokens(), 12);
+    }
+
+    #[test]
+    fn test_expired_entries_are_dropped() {
+        let store = Arc::new(MemoryCache::new());
+        let cache = ResponseCache {
+            store: store.clone(),
+            ttl: None,
+            prefix: "llm:".to_string(),
+        };
+
+        let expired = CacheEntry {
+            created_at: 1,
+            expires_at: Some(2),
+            message: Message::assistant("stale"),
+            usage: None,
+        };
+        store
+            .set("llm:old", &serde_json::to_vec(&expired).unwrap())
+            .unwrap();
+        store.set("llm:junk", b"not json").unwrap();
+
+        assert!(cache.get("llm:old").unwrap().is_none());
+        assert!(cache.get("llm:junk").unwrap().is_none());
+        assert!(store.is_empty());
+
+        let cache = cache.with_ttl(Duration::from_secs(60));
+        cache.put("llm:fresh", &completion("ok")).unwrap();
+        assert!(cache.get("llm:fresh").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_keyvalue_requires_component() {
+        assert!(matches!(
+            ResponseCache::keyvalue("llm-cache"),
+            Err(LlmError::ConfigError(_))
+        ));
+    }
+}
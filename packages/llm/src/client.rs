@@ -1,17 +1,31 @@
+use crate::cache::ResponseCache;
 use crate::config::{Config, LlmOptions};
-use crate::contracts::Transaction;
+use crate::consensus::{self, Consensus, ConsensusOptions};
+use crate::contracts::{Contract, Transaction};
 use crate::errors::LlmError;
+use crate::image::Image;
+use crate::policy::PolicyEngine;
+use crate::providers::{ChatCompletion, Provider, ProviderKind, Usage};
+use crate::reader::ContractReader;
+use crate::retry::{parse_retry_after, RetryPolicy};
+use crate::schema;
+use crate::simulation::Simulator;
+use crate::streaming::{StreamControl, StreamDecoder, StreamEvent};
+use crate::tokens::{self, ApproxTokenizer, ContextStrategy, Tokenizer};
 use crate::tools::{CustomToolHandler, Tool, ToolCall, Tools};
+use crate::toolset::Toolset;
 use schemars::JsonSchema;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
+use std::borrow::Cow;
 use std::marker::PhantomData;
-use wstd::http::{IntoBody, Method, Request, Response};
+use std::rc::Rc;
+use wstd::http::{Body, IntoBody, Method, Request, Response};
 use wstd::io::AsyncRead;
 use wstd::runtime::block_on;
 
 /// Represents a message in a chat conversation
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Message {
     /// The role of the message sender
     pub role: String,
@@ -31,6 +45,10 @@ pub struct Message {
     /// Name of the tool (for tool responses)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
+
+    /// Images for vision models to look at
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<Image>,
 }
 
 impl Message {
@@ -42,6 +60,7 @@ impl Message {
             tool_calls: None,
             tool_call_id: None,
             name: None,
+            images: Vec::new(),
         }
     }
 
@@ -53,6 +72,7 @@ impl Message {
             tool_calls: None,
             tool_call_id: None,
             name: None,
+            images: Vec::new(),
         }
     }
 
@@ -64,6 +84,7 @@ impl Message {
             tool_calls: None,
             tool_call_id: None,
             name: None,
+            images: Vec::new(),
         }
     }
 
@@ -75,8 +96,23 @@ impl Message {
             tool_calls: None,
             tool_call_id: Some(tool_call_id),
             name: Some(name),
+            images: Vec::new(),
         }
     }
+
+    /// Create a user message with images
+    pub fn user_with_images(content: impl Into<String>, images: Vec<Image>) -> Self {
+        Self {
+            images,
+            ..Self::user(content)
+        }
+    }
+
+    /// Attach an image to the message
+    pub fn with_image(mut self, image: Image) -> Self {
+        self.images.push(image);
+        self
+    }
 }
 
 // Allow converting a single string into a Message vector (user message)
@@ -128,9 +164,12 @@ impl<const N: usize> IntoMessages for [Message; N] {
 }
 
 /// The main LLM client with simplified API
+#[derive(Clone)]
 pub struct LLMClient {
     model: String,
     config: LlmOptions,
+    cache: Option<ResponseCache>,
+    retry: RetryPolicy,
 }
 
 impl LLMClient {
@@ -139,6 +178,8 @@ impl LLMClient {
         Self {
             model: model.into(),
             config: LlmOptions::default(),
+            cache: None,
+            retry: RetryPolicy::default(),
         }
     }
 
@@ -171,17 +212,105 @@ impl LLMClient {
             llm_config = llm_config.with_seed(seed as u32);
         }
 
+        if let Some(context_window) = config.get("context_window").and_then(|v| v.as_u64()) {
+            llm_config = llm_config.with_context_window(context_window as u32);
+        }
+
+        if let Some(top_k) = config.get("top_k").and_then(|v| v.as_u64()) {
+            llm_config = llm_config.with_top_k(top_k as u32);
+        }
+
+        if let Some(min_p) = config.get("min_p").and_then(|v| v.as_f64()) {
+            llm_config = llm_config.with_min_p(min_p as f32);
+        }
+
+        if let Some(penalty) = config.get("repeat_penalty").and_then(|v| v.as_f64()) {
+            llm_config = llm_config.with_repeat_penalty(penalty as f32);
+        }
+
+        // A single stop sequence or a list of them
+        match config.get("stop") {
+            Some(Value::String(stop)) => llm_config = llm_config.with_stop(vec![stop.clone()]),
+            Some(Value::Array(stops)) => {
+                let stops = stops
+                    .iter()
+                    .filter_map(|v| v.as_str().map(String::from))
+                    .collect();
+                llm_config = llm_config.with_stop(stops);
+            }
+            _ => {}
+        }
+
+        // Ollama reads a bare number as seconds
+        match config.get("keep_alive") {
+            Some(Value::String(keep_alive)) => llm_config = llm_config.with_keep_alive(keep_alive),
+            Some(Value::Number(seconds)) => {
+                llm_config = llm_config.with_keep_alive(format!("{}s", seconds))
+            }
+            _ => {}
+        }
+
+        if let Some(extra) = config.get("extra").and_then(|v| v.as_object()) {
+            for (key, value) in extra {
+                llm_config = llm_config.with_extra(key, value.clone());
+            }
+        }
+
+        if let Some(provider) = Self::provider_from_json(&config)? {
+            llm_config = llm_config.with_provider(provider);
+        }
+
+        let retry = match config.get("retry") {
+            None | Some(Value::Null) => RetryPolicy::default(),
+            Some(value) => serde_json::from_value(value.clone())
+                .map_err(|e| LlmError::ConfigError(format!("Invalid retry policy: {}", e)))?,
+        };
+
         Ok(Self {
             model,
             config: llm_config,
+            cache: None,
+            retry,
         })
     }
 
+    /// Read the provider from either a `provider` kind string or object,
+    /// with flat `base_url`, `api_key` and `api_key_env` overrides
+    fn provider_from_json(config: &Value) -> Result<Option<Provider>, LlmError> {
+        let mut provider = match config.get("provider") {
+            None | Some(Value::Null) => None,
+            Some(Value::String(kind)) => {
+                let kind: ProviderKind = serde_json::from_value(Value::String(kind.clone()))
+                    .map_err(|_| LlmError::ConfigError(format!("Unknown provider: {}", kind)))?;
+                Some(Provider::new(kind))
+            }
+            Some(value) => Some(
+                serde_json::from_value(value.clone())
+                    .map_err(|e| LlmError::ConfigError(format!("Invalid provider: {}", e)))?,
+            ),
+        };
+
+        let field = |name: &str| config.get(name).and_then(|v| v.as_str());
+        if let Some(base_url) = field("base_url") {
+            provider = Some(provider.unwrap_or_default().with_base_url(base_url));
+        }
+        if let Some(api_key) = field("api_key") {
+            provider = Some(provider.unwrap_or_default().with_api_key(api_key));
+        }
+        if let Some(var) = field("api_key_env") {
+            provider = Some(provider.unwrap_or_default().with_api_key_env(var));
+        }
+
+        Ok(provider)
+    }
+
     /// Creates a new LLM client with custom configuration
     pub fn with_config(model: impl Into<String>, config: LlmOptions) -> Self {
         Self {
             model: model.into(),
             config,
+            cache: None,
+            retry: RetryPolicy::default(),
         }
     }
 
@@ -195,9 +324,243 @@ impl LLMClient {
         &self.config
     }
 
+    /// Get the provider requests are sent to
+    pub fn get_provider(&self) -> Provider {
+        self.config.provider.clone().unwrap_or_default()
+    }
+
+    /// Use a different provider for all requests from this client
+    pub fn with_provider(mut self, provider: Provider) -> Self {
+        self.config.provider = Some(provider);
+        self
+    }
+
+    /// Serve identical requests from a response cache
+    ///
+    /// Streamed requests always go to the provider.
+    pub fn with_cache(mut self, cache: ResponseCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Retry failed requests from this client according to `policy`
+    ///
+    /// Requests can override it with `with_retry_policy` or `with_retries`.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Get the retry policy requests start from
+    pub fn get_retry_policy(&self) -> &RetryPolicy {
+        &self.retry
+    }
+
+    /// A copy of this client that samples with the given seed
+    pub(crate) fn with_seed(&self, seed: u32) -> Self {
+        Self {
+            model: self.model.clone(),
+            config: self.config.clone().with_seed(seed),
+            cache: self.cache.clone(),
+            retry: self.retry.clone(),
+        }
+    }
+
+    /// Build the HTTP request POSTing `body` to one of the provider's endpoints
+    fn json_request(
+        provider: &Provider,
+        url: &str,
+        body: &Value,
+    ) -> Result<Request<impl Body>, LlmError> {
+        let mut builder = Request::builder().method(Method::POST).uri(url);
+        for (name, value) in provider.headers() {
+            builder = builder.header(name, value);
+        }
+
+        builder
+            .body(
+                serde_json::to_vec(body)
+                    .map_err(|e| {
+                        LlmError::RequestError(format!("Failed to serialize request: {}", e))
+                    })?
+                    .into_body(),
+            )
+            .map_err(|e| LlmError::RequestError(format!("Failed to build request: {}", e)))
+    }
+
+    /// POST a body to one of the provider's endpoints and return the raw response body
+    pub(crate) async fn post_json(
+        &self,
+        provider: &Provider,
+        url: &str,
+        body: &Value,
+    ) -> Result<Vec<u8>, LlmError> {
+        let request = Self::json_request(provider, url, body)?;
+
+        let mut http_response = wstd::http::Client::new()
+            .send(request)
+            .await
+            .map_err(|e| LlmError::RequestError(format!("HTTP request failed: {}", e)))?;
+
+        if http_response.status() != 200 {
+            return Err(status_error(&mut http_response).await);
+        }
+
+        let mut body = Vec::new();
+        http_response
+            .body_mut()
+            .read_to_end(&mut body)
+            .await
+            .map_err(|e| LlmError::RequestError(format!("Failed to read response body: {}", e)))?;
+
+        Ok(body)
+    }
+
+    /// Build the provider body for a conversation
+    ///
+    /// Returns whether tools were described in the prompt, in which case
+    /// tool calls have to be parsed out of the reply text.
+    fn prepare_chat(
+        &self,
+        provider: &Provider,
+        messages: &[Message],
+        tools: Option<&[Tool]>,
+        format: Option<&Value>,
+    ) -> Result<(Value, bool), LlmError> {
+        // Validate messages
+        if messages.is_empty() {
+            return Err(LlmError::InvalidInput(
+                "Messages cannot be empty".to_string(),
+            ));
+        }
+
+        let tools = tools.filter(|t| !t.is_empty());
+
+        // Models without native tool calling get the tools described in the prompt
+        let prompted_tools = tools.filter(|_| !provider.capabilities().native_tools);
+        let body = match prompted_tools {
+            Some(tools) => {
+                let mut prompted = messages.to_vec();
+                let insert_at = prompted.iter().take_while(|m| m.role == "system").count();
+                prompted.insert(
+                    insert_at,
+                    Message::system(Tools::prompted_tool_instructions(tools)),
+                );
+                provider.chat_body(&self.model, &prompted, None, &self.config, format)
+            }
+            None => provider.chat_body(&self.model, messages, tools, &self.config, format),
+        };
+
+        Ok((body, prompted_tools.is_some()))
+    }
+
+    /// Send a chat request through the configured provider and parse the reply
+    async fn send_chat(
+        &self,
+        messages: &[Message],
+        tools: Option<&[Tool]>,
+        format: Option<&Value>,
+    ) -> Result<ChatCompletion, LlmError> {
+        let provider = self.get_provider();
+        let (body, prompted_tools) = self.prepare_chat(&provider, messages, tools, format)?;
+
+        let cache_key = self.cache.as_ref().map(|cache| cache.key(&provider, &body));
+        if let (Some(cache), Some(key)) = (&self.cache, &cache_key) {
+            match cache.get(key) {
+                Ok(Some(completion)) => return Ok(completion),
+                Ok(None) => {}
+                Err(e) => eprintln!("Cache lookup failed: {}", e),
+            }
+        }
+
+        let response = self
+            .post_json(&provider, &provider.chat_url(), &body)
+            .await?;
+        let mut completion = provider.parse_chat_response(&response)?;
+
+        if prompted_tools {
+            Self::parse_prompted_tool_calls(&mut completion.message);
+        }
+
+        if let (Some(cache), Some(key)) = (&self.cache, &cache_key) {
+            if let Err(e) = cache.put(key, &completion) {
+                eprintln!("Failed to cache response: {}", e);
+            }
+        }
+
+        Ok(completion)
+    }
+
+    /// Fill in tool calls a prompted model wrote into its reply text
+    fn parse_prompted_tool_calls(message: &mut Message) {
+        if let Some(content) = &message.content {
+            message.tool_calls = Tools::parse_prompted_tool_calls(content);
+        }
+    }
+
+    /// Stream a chat request, passing each decoded event to `on_event`
+    async fn stream_chat(
+        &self,
+        messages: &[Message],
+        tools: Option<&[Tool]>,
+        mut on_event: impl FnMut(&StreamEvent) -> StreamControl,
+    ) -> Result<ChatCompletion, LlmError> {
+        let provider = self.get_provider();
+        let (mut body, prompted_tools) = self.prepare_chat(&provider, messages, tools, None)?;
+        body["stream"] = json!(true);
+        if provider.kind == ProviderKind::OpenAi {
+            body["stream_options"] = json!({ "include_usage": true });
+        }
+
+        let request = Self::json_request(&provider, &provider.chat_url(), &body)?;
+        let mut decoder = StreamDecoder::new(provider.kind);
+
+        let mut http_response = wstd::http::Client::new()
+            .send(request)
+            .await
+            .map_err(|e| LlmError::RequestError(format!("HTTP request failed: {}", e)))?;
+
+        if http_response.status() != 200 {
+            return Err(status_error(&mut http_response).await);
+        }
+
+        let mut chunk = [0u8; 4096];
+        while !decoder.is_done() {
+            let read = http_response
+                .body_mut()
+                .read(&mut chunk)
+                .await
+                .map_err(|e| {
+                    LlmError::RequestError(format!("Failed to read response stream: {}", e))
+                })?;
+            if read == 0 {
+                break;
+            }
+
+            let events = decoder.feed(&chunk[..read])?;
+            if events
+                .iter()
+                .any(|event| on_event(event) == StreamControl::Stop)
+            {
+                break;
+            }
+        }
+
+        let (mut completion, events) = decoder.finish()?;
+        for event in &events {
+            on_event(event);
+        }
+
+        if prompted_tools {
+            Self::parse_prompted_tool_calls(&mut completion.message);
+        }
+
+        Ok(completion)
+    }
+
     /// Chat - handles everything from simple completion to complex conversations
     pub fn chat(&self, messages: impl IntoMessages) -> ChatRequest<'_> {
-        ChatRequest::new(self, messages.into_messages())
+        ChatRequest::new(Cow::Borrowed(self), messages.into_messages())
     }
 
     /// Chat with structured/typed response
@@ -205,27 +568,152 @@ impl LLMClient {
     where
         T: JsonSchema + DeserializeOwned,
     {
-        StructuredChatRequest::new(self, messages.into_messages())
+        StructuredChatRequest::new(Cow::Borrowed(self), messages.into_messages())
+    }
+}
+
+/// Default number of model calls an agent run may make
+pub const DEFAULT_MAX_ITERATIONS: usize = 10;
+
+/// Default number of correction round-trips for a structured response that fails validation
+pub const DEFAULT_MAX_REPAIRS: u32 = 1;
+
+/// Why an agent run stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    /// The model answered without requesting more tools
+    Completed,
+    /// The iteration limit was reached while the model still wanted tools
+    MaxIterations,
+    /// The token budget was used up while the model still wanted tools
+    TokenBudget,
+}
+
+/// Outcome of a multi-turn tool-calling run
+#[derive(Debug, Clone)]
+pub struct AgentRun {
+    /// The model's final text answer (empty if the run stopped early)
+    pub answer: String,
+    /// Full transcript: the prompt, every assistant turn and every tool result
+    pub messages: Vec<Message>,
+    /// Number of model calls made
+    pub iterations: usize,
+    /// Token usage summed over all model calls
+    pub usage: Usage,
+    /// Why the run ended
+    pub stop_reason: StopReason,
+}
+
+impl AgentRun {
+    /// Whether the model finished on its own
+    pub fn is_complete(&self) -> bool {
+        self.stop_reason == StopReason::Completed
+    }
+
+    /// All tool result messages in the order they were produced
+    pub fn tool_results(&self) -> impl Iterator<Item = &Message> {
+        self.messages.iter().filter(|m| m.role == "tool")
+    }
+
+    /// Transactions produced by tool calls during the run
+    pub fn transactions(&self) -> Vec<Transaction> {
+        self.tool_results()
+            .filter_map(|m| m.content.as_deref())
+            .filter_map(|content| serde_json::from_str(content).ok())
+            .collect()
+    }
+}
+
+/// Progress of a tool-calling run
+struct AgentLoop {
+    messages: Vec<Message>,
+    iterations: usize,
+    usage: Usage,
+}
+
+impl AgentLoop {
+    fn new(messages: Vec<Message>) -> Self {
+        Self {
+            messages,
+            iterations: 0,
+            usage: Usage::default(),
+        }
+    }
+
+    /// Count a model reply; returns it if it asks for tools, otherwise adds it to the transcript
+    fn record(&mut self, completion: ChatCompletion) -> Option<Message> {
+        self.iterations += 1;
+        if let Some(call_usage) = completion.usage {
+            self.usage += call_usage;
+        }
+
+        let response = completion.message;
+        if response
+            .tool_calls
+            .as_ref()
+            .is_some_and(|calls| !calls.is_empty())
+        {
+            Some(response)
+        } else {
+            self.messages.push(response);
+            None
+        }
+    }
+
+    fn finish(self, stop_reason: StopReason) -> AgentRun {
+        let answer = match stop_reason {
+            StopReason::Completed => self
+                .messages
+                .last()
+                .and_then(|m| m.content.clone())
+                .unwrap_or_default(),
+            _ => String::new(),
+        };
+        AgentRun {
+            answer,
+            messages: self.messages,
+            iterations: self.iterations,
+            usage: self.usage,
+            stop_reason,
+        }
     }
 }
 
 /// Builder for chat requests
 pub struct ChatRequest<'a> {
-    client: &'a LLMClient,
+    client: Cow<'a, LLMClient>,
     messages: Vec<Message>,
     tools: Option<Vec<Tool>>,
-    retries: u32,
+    retry: RetryPolicy,
     custom_handlers: Vec<Box<dyn CustomToolHandler>>,
+    /// Contracts that contract tool calls may target
+    contracts: Vec<Contract>,
+    /// Dry-runs every proposed transaction
+    simulator: Option<Simulator>,
+    /// Checked against every tool result
+    policy: Option<PolicyEngine>,
+    max_iterations: usize,
+    token_budget: Option<u32>,
+    tokenizer: Box<dyn Tokenizer>,
+    context_strategy: ContextStrategy,
 }
 
 impl<'a> ChatRequest<'a> {
-    fn new(client: &'a LLMClient, messages: Vec<Message>) -> Self {
+    fn new(client: Cow<'a, LLMClient>, messages: Vec<Message>) -> Self {
         Self {
+            retry: client.retry.clone(),
             client,
             messages,
             tools: None,
-            retries: 0,
             custom_handlers: Vec::new(),
+            contracts: Vec::new(),
+            simulator: None,
+            policy: None,
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+            token_budget: None,
+            tokenizer: Box::new(ApproxTokenizer),
+            context_strategy: ContextStrategy::default(),
         }
     }
 
@@ -236,19 +724,69 @@ impl<'a> ChatRequest<'a> {
     }
 
     /// Add tools from smart contracts (auto-generated from ABIs)
-    pub fn with_contract_tools(mut self, contracts: &[crate::contracts::Contract]) -> Self {
+    ///
+    /// Transactions the model proposes through these tools are validated
+    /// against the same contracts.
+    pub fn with_contract_tools(mut self, contracts: &[Contract]) -> Self {
         let mut all_tools = self.tools.unwrap_or_default();
         for contract in contracts {
             all_tools.extend(Tools::tools_from_contract(contract));
         }
         self.tools = Some(all_tools);
+        self.contracts.extend_from_slice(contracts);
         self
     }
 
-    /// Add a full config (automatically includes contract tools)
+    /// Add read tools for the reader's contracts and execute them with `eth_call`
+    pub fn with_contract_reader(mut self, reader: ContractReader) -> Self {
+        let mut all_tools = self.tools.unwrap_or_default();
+        all_tools.extend(reader.tools());
+        self.tools = Some(all_tools);
+        self.custom_handlers.push(Box::new(reader));
+        self
+    }
+
+    /// Add the toolset's typed tools and handle their calls
+    pub fn with_toolset(mut self, toolset: Toolset) -> Self {
+        let mut all_tools = self.tools.unwrap_or_default();
+        all_tools.extend(toolset.tools());
+        self.tools = Some(all_tools);
+        self.custom_handlers.push(Box::new(toolset));
+        self
+    }
+
+    /// Simulate every proposed transaction before the model sees it
+    ///
+    /// Transactions that would revert are returned to the model with the
+    /// decoded reason; the rest get a gas estimate.
+    pub fn with_simulator(mut self, simulator: Simulator) -> Self {
+        self.simulator = Some(simulator);
+        self
+    }
+
+    /// Check every tool result against a policy before the model sees it
+    ///
+    /// Denied transactions are returned to the model with the broken rules.
+    pub fn with_policy(mut self, policy: impl Into<PolicyEngine>) -> Self {
+        self.policy = Some(policy.into());
+        self
+    }
+
+    /// Add a full config (automatically includes contract tools, read tools
+    /// when the config has an `rpc_url`, simulation when it also has a
+    /// `sender`, and the config's policy; see [`PolicyEngine::from_config`])
     pub fn with_config(mut self, config: &Config) -> Self {
         // Add contract tools
         self = self.with_contract_tools(&config.contracts);
+        if let Some(reader) = ContractReader::from_config(config) {
+            self = self.with_contract_reader(reader);
+        }
+        if let Some(simulator) = Simulator::from_config(config) {
+            self = self.with_simulator(simulator);
+        }
+        if let Some(policy) = PolicyEngine::from_config(config) {
+            self = self.with_policy(policy);
+        }
 
         // Add any configured system messages
         if !config.messages.is_empty() {
@@ -266,207 +804,375 @@ impl<'a> ChatRequest<'a> {
         self
     }
 
-    /// Set the number of retries
+    /// Set the number of retries, keeping the rest of the retry policy
     pub fn with_retries(mut self, retries: u32) -> Self {
-        self.retries = retries;
+        self.retry.max_retries = retries;
+        self
+    }
+
+    /// Retry failed requests according to `policy` instead of the client's
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
         self
     }
 
     /// Add custom tool handlers for execution
     pub fn with_custom_handlers(mut self, handlers: Vec<Box<dyn CustomToolHandler>>) -> Self {
-        self.custom_handlers = handlers;
+        self.custom_handlers.extend(handlers);
+        self
+    }
+
+    /// Limit how many model calls a tool-calling run may make
+    pub fn with_max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Stop a tool-calling run once this many tokens have been used in total
+    pub fn with_token_budget(mut self, tokens: u32) -> Self {
+        self.token_budget = Some(tokens);
+        self
+    }
+
+    /// Count prompt tokens with this tokenizer instead of the built-in approximation
+    pub fn with_tokenizer(mut self, tokenizer: impl Tokenizer + 'static) -> Self {
+        self.tokenizer = Box::new(tokenizer);
+        self
+    }
+
+    /// Choose how older messages are shortened when the prompt outgrows the context window
+    pub fn with_context_strategy(mut self, strategy: ContextStrategy) -> Self {
+        self.context_strategy = strategy;
         self
     }
 
     /// Send the request and return the full Message response
+    ///
+    /// Blocks on the WASI runtime; inside an async function use [`ChatRequest::send_async`].
     pub fn send(self) -> Result<Message, LlmError> {
-        let mut attempts = 0;
-        let max_attempts = self.retries + 1;
+        block_on(self.into_owned().send_async())
+    }
 
-        loop {
-            match self.try_send() {
-                Ok(response) => return Ok(response),
-                Err(e) if attempts < max_attempts - 1 => {
-                    attempts += 1;
-                    eprintln!(
-                        "Request failed (attempt {}/{}): {}",
-                        attempts, max_attempts, e
-                    );
-                    continue;
-                }
-                Err(e) => return Err(e),
+    /// Send the request and return the full Message response, without blocking
+    pub async fn send_async(self) -> Result<Message, LlmError> {
+        self.send_messages(&self.messages).await.map(|c| c.message)
+    }
+
+    /// Send the request and return the response together with the provider's token usage
+    pub fn send_with_usage(self) -> Result<ChatCompletion, LlmError> {
+        block_on(self.into_owned().send_with_usage_async())
+    }
+
+    /// Async version of [`ChatRequest::send_with_usage`]
+    pub async fn send_with_usage_async(self) -> Result<ChatCompletion, LlmError> {
+        self.send_messages(&self.messages).await
+    }
+
+    /// Detach the request from the client's borrow, so it can move into the runtime
+    fn into_owned(self) -> ChatRequest<'static> {
+        ChatRequest {
+            client: Cow::Owned(self.client.into_owned()),
+            messages: self.messages,
+            tools: self.tools,
+            retry: self.retry,
+            custom_handlers: self.custom_handlers,
+            contracts: self.contracts,
+            simulator: self.simulator,
+            policy: self.policy,
+            max_iterations: self.max_iterations,
+            token_budget: self.token_budget,
+            tokenizer: self.tokenizer,
+            context_strategy: self.context_strategy,
+        }
+    }
+
+    /// Send the given conversation with this request's tools and retry settings
+    async fn send_messages(&self, messages: &[Message]) -> Result<ChatCompletion, LlmError> {
+        let messages = self.fit_context(messages).await?;
+        let messages = &messages;
+        self.retry.run_async(|| self.try_send(messages)).await
+    }
+
+    /// Shorten the conversation to fit `context_window - max_tokens`, if a context window is set
+    ///
+    /// System messages and the latest turn are always kept; the oldest other
+    /// messages are dropped or, with [`ContextStrategy::Summarize`], replaced by
+    /// a summary when it fits.
+    async fn fit_context(&self, messages: &[Message]) -> Result<Vec<Message>, LlmError> {
+        let Some(budget) = tokens::prompt_budget(self.client.get_config()) else {
+            return Ok(messages.to_vec());
+        };
+
+        let tools = self.tools.as_deref();
+        let (kept, dropped) = tokens::fit_to_budget(&*self.tokenizer, messages, tools, budget)?;
+        if dropped.is_empty() || self.context_strategy == ContextStrategy::Trim {
+            return Ok(kept);
+        }
+
+        let summary = match self.summarize(&dropped).await {
+            Ok(summary) => summary,
+            Err(e) => {
+                eprintln!(
+                    "Failed to summarize earlier messages, trimming instead: {}",
+                    e
+                );
+                return Ok(kept);
             }
+        };
+
+        let mut summarized = kept.clone();
+        let insert_at = summarized.iter().take_while(|m| m.role == "system").count();
+        summarized.insert(
+            insert_at,
+            Message::system(format!("Summary of the earlier conversation:\n{}", summary)),
+        );
+
+        if tokens::prompt_tokens(&*self.tokenizer, &summarized, tools) <= budget {
+            Ok(summarized)
+        } else {
+            Ok(kept)
         }
     }
 
+    /// Ask the model for a short summary of messages that no longer fit
+    async fn summarize(&self, messages: &[Message]) -> Result<String, LlmError> {
+        let request = [
+            Message::system(
+                "Summarize the following conversation in a few sentences. \
+                 Keep every fact, figure, address and decision that later turns may rely on.",
+            ),
+            Message::user(tokens::transcript(messages)),
+        ];
+        let request = &request;
+        let completion = self
+            .retry
+            .run_async(|| self.client.send_chat(request, None, None))
+            .await?;
+        completion
+            .message
+            .content
+            .filter(|summary| !summary.trim().is_empty())
+            .ok_or_else(|| LlmError::ApiError("Empty summary".to_string()))
+    }
+
+    /// Ask the same question once per seed and return the answer a quorum agrees on
+    ///
+    /// Answers are compared by their normalized text (see
+    /// [`normalize_text`](crate::consensus::normalize_text)), so differences in
+    /// case, whitespace or a trailing full stop do not split the vote. A sample
+    /// that fails after its retries counts as a missing vote.
+    pub fn consensus(self, options: ConsensusOptions) -> Result<Consensus<String>, LlmError> {
+        block_on(self.into_owned().consensus_async(options))
+    }
+
+    /// Async version of [`ChatRequest::consensus`]
+    pub async fn consensus_async(
+        self,
+        options: ConsensusOptions,
+    ) -> Result<Consensus<String>, LlmError> {
+        let messages = self.fit_context(&self.messages).await?;
+        let (messages, request) = (&messages, &self);
+        consensus::run(&self.client, &options, |sampler| async move {
+            let sampler = &sampler;
+            let completion = request
+                .retry
+                .run_async(|| sampler.send_chat(messages, request.tools.as_deref(), None))
+                .await?;
+            let text = completion.message.content.unwrap_or_default();
+            Ok((consensus::normalize_text(&text), text))
+        })
+        .await
+    }
+
+    /// Stream the response, calling `on_event` for every content delta,
+    /// completed tool call and usage report as it arrives
+    ///
+    /// Return [`StreamControl::Stop`] from the callback to cut generation short,
+    /// e.g. to enforce a time or size limit. The message assembled up to that
+    /// point is returned either way. Retries are not applied to streams.
+    ///
+    /// The callback runs inside the WASI runtime, so it must own its state
+    /// (use a `move` closure) and can log through the host directly.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use wavs_llm::{LLMClient, StreamControl, StreamEvent};
+    ///
+    /// let client = LLMClient::new("llama3.2");
+    /// let mut received = 0;
+    /// let completion = client
+    ///     .chat("Summarize the last 100 blocks")
+    ///     .stream(move |event| {
+    ///         if let StreamEvent::ContentDelta(text) = event {
+    ///             received += text.len();
+    ///         }
+    ///         if received > 4_000 {
+    ///             StreamControl::Stop
+    ///         } else {
+    ///             StreamControl::Continue
+    ///         }
+    ///     })
+    ///     .unwrap();
+    /// println!("{:?}", completion.message.content);
+    /// ```
+    pub fn stream(
+        self,
+        on_event: impl FnMut(&StreamEvent) -> StreamControl + 'static,
+    ) -> Result<ChatCompletion, LlmError> {
+        block_on(self.into_owned().stream_async(on_event))
+    }
+
+    /// Async version of [`ChatRequest::stream`]; the callback may borrow local state
+    pub async fn stream_async(
+        self,
+        on_event: impl FnMut(&StreamEvent) -> StreamControl,
+    ) -> Result<ChatCompletion, LlmError> {
+        let messages = self.fit_context(&self.messages).await?;
+        self.client
+            .stream_chat(&messages, self.tools.as_deref(), on_event)
+            .await
+    }
+
     /// Convenience method for just getting text content
     pub fn text(self) -> Result<String, LlmError> {
-        let message = self.send()?;
+        block_on(self.into_owned().text_async())
+    }
+
+    /// Async version of [`ChatRequest::text`]
+    pub async fn text_async(self) -> Result<String, LlmError> {
+        let message = self.send_async().await?;
         message
             .content
-            .ok_or_else(|| LlmError::ApiError("No text content in response".to_string()))
+            .ok_or_else(|| LlmError::Incomplete("No text content in response".to_string()))
     }
 
-    /// Execute tool calls automatically and return final response
+    /// Execute tool calls automatically and return the model's final answer
+    ///
+    /// Fails if the iteration or token budget runs out before the model is done.
+    /// Use [`ChatRequest::run_agent`] to get the transcript as well.
     pub fn execute_tools(self) -> Result<String, LlmError> {
-        let messages = self.messages.clone();
-        let mut iterations = 0;
-        const MAX_ITERATIONS: usize = 10;
-
-        // Extract what we need before moving self
-        let client = self.client;
-        let tools = self.tools.clone();
-        let retries = self.retries;
-
-        #[allow(clippy::never_loop)]
-        loop {
-            iterations += 1;
-            if iterations > MAX_ITERATIONS {
-                return Err(LlmError::ApiError(
-                    "Maximum tool execution iterations reached".to_string(),
-                ));
-            }
-
-            // Create a new request for this iteration (without custom handlers since we can't clone them)
-            let request = ChatRequest {
-                client,
-                messages: messages.clone(),
-                tools: tools.clone(),
-                retries,
-                custom_handlers: Vec::new(), // Can't clone trait objects, so use empty vec
-            };
+        Self::agent_answer(self.run_agent()?)
+    }
 
-            let response = request.send()?;
-
-            // Check if there are tool calls to process
-            if let Some(tool_calls) = &response.tool_calls {
-                if !tool_calls.is_empty() {
-                    // Process the tool calls
-                    let tool_results = Tools::process_tool_calls(
-                        client,
-                        messages.clone(),
-                        response.clone(),
-                        tool_calls.clone(),
-                        None, // Custom handlers not available after first iteration
-                    )
-                    .map_err(LlmError::ApiError)?;
-
-                    // The tool_results is a single String containing the final result
-                    // We can return it directly
-                    return Ok(tool_results);
-                }
-            }
+    /// Async version of [`ChatRequest::execute_tools`]
+    ///
+    /// Tools run through [`CustomToolHandler::execute_async`], so handlers
+    /// that wait on the network do not block the runtime.
+    pub async fn execute_tools_async(self) -> Result<String, LlmError> {
+        Self::agent_answer(self.run_agent_async().await?)
+    }
 
-            // No more tool calls, return the final text
-            return response.content.ok_or_else(|| {
-                LlmError::ApiError("No text content in final response".to_string())
-            });
+    fn agent_answer(run: AgentRun) -> Result<String, LlmError> {
+        match run.stop_reason {
+            StopReason::Completed => Ok(run.answer),
+            StopReason::MaxIterations => Err(LlmError::Incomplete(
+                "Maximum tool execution iterations reached".to_string(),
+            )),
+            StopReason::TokenBudget => Err(LlmError::Incomplete(
+                "Token budget exhausted before the model finished".to_string(),
+            )),
         }
     }
 
-    fn try_send(&self) -> Result<Message, LlmError> {
-        // Validate messages
-        if self.messages.is_empty() {
-            return Err(LlmError::InvalidInput(
-                "Messages cannot be empty".to_string(),
-            ));
-        }
+    /// Run the model in a loop, executing requested tools and feeding their
+    /// results back until it answers without tools or a budget runs out
+    ///
+    /// Only the model calls run inside the WASI runtime. Tools run between
+    /// them through [`CustomToolHandler::execute`], so handlers may start the
+    /// runtime themselves with `block_on`.
+    pub fn run_agent(self) -> Result<AgentRun, LlmError> {
+        let request = Rc::new(self.into_owned());
+        request.run_agent_with(|messages| {
+            let (request, messages) = (Rc::clone(&request), messages.to_vec());
+            block_on(async move { request.send_messages(&messages).await })
+        })
+    }
 
-        // Build the request body
-        let mut body = serde_json::json!({
-            "model": self.client.model,
-            "messages": self.messages,
-            "stream": false,
-        });
+    /// The blocking tool loop, getting each model reply from `send`
+    fn run_agent_with(
+        &self,
+        mut send: impl FnMut(&[Message]) -> Result<ChatCompletion, LlmError>,
+    ) -> Result<AgentRun, LlmError> {
+        let capabilities = self.client.get_provider().capabilities();
+        let mut run = AgentLoop::new(self.messages.clone());
 
-        // Add configuration options
-        if let Some(temp) = self.client.config.temperature {
-            body["temperature"] = serde_json::json!(temp);
-        }
-        if let Some(max_tokens) = self.client.config.max_tokens {
-            body["max_tokens"] = serde_json::json!(max_tokens);
-        }
-        if let Some(top_p) = self.client.config.top_p {
-            body["top_p"] = serde_json::json!(top_p);
-        }
-        if let Some(seed) = self.client.config.seed {
-            body["seed"] = serde_json::json!(seed);
-        }
+        loop {
+            let Some(response) = run.record(send(&run.messages)?) else {
+                return Ok(run.finish(StopReason::Completed));
+            };
 
-        // Add tools if provided
-        if let Some(tools) = &self.tools {
-            if !tools.is_empty() {
-                body["tools"] = serde_json::json!(tools);
+            run.messages.extend(Tools::process_tool_calls(
+                &response,
+                &capabilities,
+                Some(&self.custom_handlers),
+                &self.contracts,
+                self.simulator.as_ref(),
+                self.policy.as_ref(),
+            ));
+
+            if let Some(stop_reason) = self.stop_reason(&run) {
+                return Ok(run.finish(stop_reason));
             }
         }
+    }
 
-        // Make the HTTP request
-        let request = Request::builder()
-            .method(Method::POST)
-            .uri("http://localhost:11434/api/chat")
-            .header("Content-Type", "application/json")
-            .body(
-                serde_json::to_vec(&body)
-                    .map_err(|e| {
-                        LlmError::RequestError(format!("Failed to serialize request: {}", e))
-                    })?
-                    .into_body(),
-            )
-            .map_err(|e| LlmError::RequestError(format!("Failed to build request: {}", e)))?;
-
-        let response: Response<Vec<u8>> = block_on(async {
-            let mut http_response = wstd::http::Client::new()
-                .send(request)
-                .await
-                .map_err(|e| LlmError::RequestError(format!("HTTP request failed: {}", e)))?;
+    /// Async version of [`ChatRequest::run_agent`]
+    pub async fn run_agent_async(self) -> Result<AgentRun, LlmError> {
+        let capabilities = self.client.get_provider().capabilities();
+        let mut run = AgentLoop::new(self.messages.clone());
 
-            let mut body = Vec::new();
-            http_response
-                .body_mut()
-                .read_to_end(&mut body)
-                .await
-                .map_err(|e| {
-                    LlmError::RequestError(format!("Failed to read response body: {}", e))
-                })?;
+        loop {
+            let completion = self.send_messages(&run.messages).await?;
+            let Some(response) = run.record(completion) else {
+                return Ok(run.finish(StopReason::Completed));
+            };
 
-            Response::builder()
-                .status(http_response.status())
-                .body(body)
-                .map_err(|e| LlmError::RequestError(format!("Failed to build response: {}", e)))
-        })?;
-
-        if response.status() != 200 {
-            let error_body = String::from_utf8_lossy(response.body());
-            return Err(LlmError::ApiError(format!(
-                "API returned status {}: {}",
-                response.status(),
-                error_body
-            )));
+            run.messages.extend(
+                Tools::process_tool_calls_async(
+                    &response,
+                    &capabilities,
+                    Some(&self.custom_handlers),
+                    &self.contracts,
+                    self.simulator.as_ref(),
+                    self.policy.as_ref(),
+                )
+                .await,
+            );
+
+            if let Some(stop_reason) = self.stop_reason(&run) {
+                return Ok(run.finish(stop_reason));
+            }
         }
+    }
 
-        // Parse the response
-        #[derive(Deserialize)]
-        struct OllamaResponse {
-            message: Message,
-            #[allow(dead_code)]
-            model: String,
-            #[allow(dead_code)]
-            created_at: String,
+    /// Why the run has to stop after its latest tool results, if it does
+    fn stop_reason(&self, run: &AgentLoop) -> Option<StopReason> {
+        if run.iterations >= self.max_iterations {
+            Some(StopReason::MaxIterations)
+        } else if self
+            .token_budget
+            .is_some_and(|budget| run.usage.total_tokens() >= budget)
+        {
+            Some(StopReason::TokenBudget)
+        } else {
+            None
         }
+    }
 
-        let ollama_response: OllamaResponse = serde_json::from_slice(response.body())
-            .map_err(|e| LlmError::ParseError(format!("Failed to parse response: {}", e)))?;
-
-        Ok(ollama_response.message)
+    async fn try_send(&self, messages: &[Message]) -> Result<ChatCompletion, LlmError> {
+        self.client
+            .send_chat(messages, self.tools.as_deref(), None)
+            .await
     }
 }
 
 /// Builder for structured chat requests
 pub struct StructuredChatRequest<'a, T> {
-    client: &'a LLMClient,
+    client: Cow<'a, LLMClient>,
     messages: Vec<Message>,
     tools: Option<Vec<Tool>>,
-    retries: u32,
+    retry: RetryPolicy,
+    max_repairs: u32,
     custom_handlers: Vec<Box<dyn CustomToolHandler>>,
     _phantom: PhantomData<T>,
 }
@@ -475,12 +1181,13 @@ impl<'a, T> StructuredChatRequest<'a, T>
 where
     T: JsonSchema + DeserializeOwned,
 {
-    fn new(client: &'a LLMClient, messages: Vec<Message>) -> Self {
+    fn new(client: Cow<'a, LLMClient>, messages: Vec<Message>) -> Self {
         Self {
+            retry: client.retry.clone(),
             client,
             messages,
             tools: None,
-            retries: 0,
+            max_repairs: DEFAULT_MAX_REPAIRS,
             custom_handlers: Vec::new(),
             _phantom: PhantomData,
         }
@@ -522,9 +1229,15 @@ where
         self
     }
 
-    /// Set the number of retries
+    /// Set the number of retries, keeping the rest of the retry policy
     pub fn with_retries(mut self, retries: u32) -> Self {
-        self.retries = retries;
+        self.retry.max_retries = retries;
+        self
+    }
+
+    /// Retry failed requests according to `policy` instead of the client's
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
         self
     }
 
@@ -534,209 +1247,223 @@ where
         self
     }
 
+    /// Set how many times an invalid response is sent back to the model for correction
+    pub fn with_max_repairs(mut self, max_repairs: u32) -> Self {
+        self.max_repairs = max_repairs;
+        self
+    }
+
     /// Send the request and return the parsed structured response
-    pub fn send(self) -> Result<T, LlmError> {
-        let mut attempts = 0;
-        let max_attempts = self.retries + 1;
+    ///
+    /// The response is validated against `T`'s JSON schema. If it does not
+    /// match, the violations are fed back to the model and it gets another
+    /// try, up to the configured number of repairs.
+    pub fn send(self) -> Result<T, LlmError>
+    where
+        T: 'static,
+    {
+        block_on(self.into_owned().send_async())
+    }
 
-        loop {
-            match self.try_send() {
-                Ok(response) => return Ok(response),
-                Err(e) if attempts < max_attempts - 1 => {
-                    attempts += 1;
-                    eprintln!(
-                        "Request failed (attempt {}/{}): {}",
-                        attempts, max_attempts, e
-                    );
-                    continue;
-                }
-                Err(e) => return Err(e),
-            }
-        }
+    /// Async version of [`StructuredChatRequest::send`]
+    pub async fn send_async(self) -> Result<T, LlmError> {
+        let client = &*self.client;
+        self.retry.run_async(|| self.try_send(client)).await
     }
 
-    fn try_send(&self) -> Result<T, LlmError> {
-        // Validate messages
-        if self.messages.is_empty() {
-            return Err(LlmError::InvalidInput(
-                "Messages cannot be empty".to_string(),
-            ));
+    /// Request the structured response once per seed and return the value a quorum agrees on
+    ///
+    /// Each parsed value is compared by its canonical JSON (compact, keys
+    /// sorted), so only the content matters. A sample that fails after its
+    /// retries and repairs counts as a missing vote.
+    pub fn consensus(self, options: ConsensusOptions) -> Result<Consensus<T>, LlmError>
+    where
+        T: Serialize + 'static,
+    {
+        block_on(self.into_owned().consensus_async(options))
+    }
+
+    /// Async version of [`StructuredChatRequest::consensus`]
+    pub async fn consensus_async(self, options: ConsensusOptions) -> Result<Consensus<T>, LlmError>
+    where
+        T: Serialize,
+    {
+        let request = &self;
+        consensus::run(&self.client, &options, |sampler| async move {
+            let sampler = &sampler;
+            let value = request
+                .retry
+                .run_async(|| request.try_send(sampler))
+                .await?;
+            let json = serde_json::to_value(&value).map_err(|e| {
+                LlmError::ParseError(format!("Failed to serialize structured response: {}", e))
+            })?;
+            Ok((consensus::canonical_json(&json), value))
+        })
+        .await
+    }
+
+    /// Detach the request from the client's borrow, so it can move into the runtime
+    fn into_owned(self) -> StructuredChatRequest<'static, T> {
+        StructuredChatRequest {
+            client: Cow::Owned(self.client.into_owned()),
+            messages: self.messages,
+            tools: self.tools,
+            retry: self.retry,
+            max_repairs: self.max_repairs,
+            custom_handlers: self.custom_handlers,
+            _phantom: PhantomData,
         }
+    }
 
+    async fn try_send(&self, client: &LLMClient) -> Result<T, LlmError> {
         // Generate JSON schema for the type
         let schema = schemars::schema_for!(T);
         let schema_value = serde_json::to_value(schema)
             .map_err(|e| LlmError::ConfigError(format!("Failed to create schema: {}", e)))?;
 
-        // Build the request body with structured output format
-        let mut body = serde_json::json!({
-            "model": self.client.model,
-            "messages": self.messages,
-            "stream": false,
-            "format": schema_value,
-        });
-
-        // Add configuration options
-        if let Some(temp) = self.client.config.temperature {
-            body["temperature"] = serde_json::json!(temp);
-        }
-        if let Some(max_tokens) = self.client.config.max_tokens {
-            body["max_tokens"] = serde_json::json!(max_tokens);
-        }
-        if let Some(top_p) = self.client.config.top_p {
-            body["top_p"] = serde_json::json!(top_p);
-        }
-        if let Some(seed) = self.client.config.seed {
-            body["seed"] = serde_json::json!(seed);
-        }
-
-        // Add tools if provided
-        if let Some(tools) = &self.tools {
-            if !tools.is_empty() {
-                body["tools"] = serde_json::json!(tools);
-            }
+        // Use the provider's constrained decoding when it has one, otherwise ask for the schema
+        let constrained = client.get_provider().capabilities().json_schema_output;
+        let mut messages = self.messages.clone();
+        if !constrained {
+            messages.push(Message::system(format!(
+                "Respond only with a JSON value matching this JSON schema:\n{}",
+                schema_value
+            )));
         }
+        let format = constrained.then_some(&schema_value);
 
-        // Make the HTTP request
-        let request = Request::builder()
-            .method(Method::POST)
-            .uri("http://localhost:11434/api/chat")
-            .header("Content-Type", "application/json")
-            .body(
-                serde_json::to_vec(&body)
-                    .map_err(|e| {
-                        LlmError::RequestError(format!("Failed to serialize request: {}", e))
-                    })?
-                    .into_body(),
-            )
-            .map_err(|e| LlmError::RequestError(format!("Failed to build request: {}", e)))?;
-
-        let response: Response<Vec<u8>> = block_on(async {
-            let mut http_response = wstd::http::Client::new()
-                .send(request)
-                .await
-                .map_err(|e| LlmError::RequestError(format!("HTTP request failed: {}", e)))?;
+        let mut repairs = 0;
+        loop {
+            let message = client
+                .send_chat(&messages, self.tools.as_deref(), format)
+                .await?
+                .message;
+
+            // Extract and parse the structured content
+            let content = message
+                .content
+                .ok_or_else(|| LlmError::ApiError("No content in response".to_string()))?;
+
+            let problem = match Self::parse_and_validate(&content, &schema_value) {
+                Ok(parsed) => return Ok(parsed),
+                Err(problem) => problem,
+            };
 
-            let mut body = Vec::new();
-            http_response
-                .body_mut()
-                .read_to_end(&mut body)
-                .await
-                .map_err(|e| {
-                    LlmError::RequestError(format!("Failed to read response body: {}", e))
-                })?;
+            if repairs >= self.max_repairs {
+                return Err(LlmError::ValidationError(problem));
+            }
+            repairs += 1;
 
-            Response::builder()
-                .status(http_response.status())
-                .body(body)
-                .map_err(|e| LlmError::RequestError(format!("Failed to build response: {}", e)))
-        })?;
-
-        if response.status() != 200 {
-            let error_body = String::from_utf8_lossy(response.body());
-            return Err(LlmError::ApiError(format!(
-                "API returned status {}: {}",
-                response.status(),
-                error_body
+            messages.push(Message::assistant(content));
+            messages.push(Message::user(format!(
+                "Your response did not match the required JSON schema:\n{}\n\
+                 Reply with only the corrected JSON.",
+                problem
             )));
         }
+    }
 
-        // Parse the response
-        #[derive(Deserialize)]
-        struct OllamaResponse {
-            message: Message,
-            #[allow(dead_code)]
-            model: String,
-            #[allow(dead_code)]
-            created_at: String,
-        }
+    /// Parse `content` as `T`, describing what is wrong when it does not fit the schema
+    fn parse_and_validate(content: &str, schema: &Value) -> Result<T, String> {
+        let json_content = Self::extract_json_from_response(content).map_err(|e| e.to_string())?;
+        let value: Value = serde_json::from_str(&json_content)
+            .map_err(|e| format!("Response is not valid JSON: {}", e))?;
 
-        let ollama_response: OllamaResponse = serde_json::from_slice(response.body())
-            .map_err(|e| LlmError::ParseError(format!("Failed to parse response: {}", e)))?;
+        schema::validate(&value, schema).map_err(|v| schema::describe_violations(&v))?;
 
-        // Extract and parse the structured content
-        let content = ollama_response
-            .message
-            .content
-            .ok_or_else(|| LlmError::ApiError("No content in response".to_string()))?;
+        serde_json::from_value(value).map_err(|e| format!("- $: {}", e))
+    }
 
-        // Try to parse the content as the expected type
-        // First, try to extract JSON from the response
-        let json_content = Self::extract_json_from_response(&content)?;
+    fn extract_json_from_response(response: &str) -> Result<String, LlmError> {
+        extract_json(response)
+    }
+}
 
-        serde_json::from_str(&json_content).map_err(|e| {
-            LlmError::ParseError(format!("Failed to parse structured response: {}", e))
-        })
+/// Error for a non-success response, with its body and any `Retry-After` wait
+async fn status_error(response: &mut Response<impl Body>) -> LlmError {
+    let retry_after = response
+        .headers()
+        .get("retry-after")
+        .and_then(|value| value.to_str().ok())
+        .and_then(parse_retry_after);
+    let mut body = Vec::new();
+    let _ = response.body_mut().read_to_end(&mut body).await;
+    LlmError::HttpStatus {
+        status: response.status().as_u16(),
+        retry_after,
+        body: String::from_utf8_lossy(&body).into_owned(),
     }
+}
 
-    fn extract_json_from_response(response: &str) -> Result<String, LlmError> {
-        // Try to parse as-is first
-        if (response.trim_start().starts_with('{') || response.trim_start().starts_with('['))
-            && serde_json::from_str::<Value>(response).is_ok()
-        {
-            return Ok(response.to_string());
-        }
+/// Extract the first JSON object or array from free-form model output
+pub(crate) fn extract_json(response: &str) -> Result<String, LlmError> {
+    // Try to parse as-is first
+    if (response.trim_start().starts_with('{') || response.trim_start().starts_with('['))
+        && serde_json::from_str::<Value>(response).is_ok()
+    {
+        return Ok(response.to_string());
+    }
 
-        // Look for JSON between ```json and ``` markers
-        if let Some(start) = response.find("```json") {
-            let json_start = start + 7;
-            if let Some(end) = response[json_start..].find("```") {
-                let json_str = &response[json_start..json_start + end].trim();
-                if serde_json::from_str::<Value>(json_str).is_ok() {
-                    return Ok(json_str.to_string());
-                }
+    // Look for JSON between ```json and ``` markers
+    if let Some(start) = response.find("```json") {
+        let json_start = start + 7;
+        if let Some(end) = response[json_start..].find("```") {
+            let json_str = &response[json_start..json_start + end].trim();
+            if serde_json::from_str::<Value>(json_str).is_ok() {
+                return Ok(json_str.to_string());
             }
         }
+    }
 
-        // Look for JSON between ``` and ``` markers
-        if let Some(start) = response.find("```") {
-            let json_start = start + 3;
-            if let Some(end) = response[json_start..].find("```") {
-                let json_str = &response[json_start..json_start + end].trim();
-                if (json_str.starts_with('{') || json_str.starts_with('['))
-                    && serde_json::from_str::<Value>(json_str).is_ok()
-                {
-                    return Ok(json_str.to_string());
-                }
+    // Look for JSON between ``` and ``` markers
+    if let Some(start) = response.find("```") {
+        let json_start = start + 3;
+        if let Some(end) = response[json_start..].find("```") {
+            let json_str = &response[json_start..json_start + end].trim();
+            if (json_str.starts_with('{') || json_str.starts_with('['))
+                && serde_json::from_str::<Value>(json_str).is_ok()
+            {
+                return Ok(json_str.to_string());
             }
         }
+    }
 
-        // Try to find the first { or [ and parse from there
-        let trimmed = response.trim();
-        for (i, ch) in trimmed.char_indices() {
-            if ch == '{' || ch == '[' {
-                let potential_json = &trimmed[i..];
-
-                // Find the matching closing bracket
-                let mut depth = 0;
-                let mut end_index = None;
-                let target_close = if ch == '{' { '}' } else { ']' };
-
-                for (j, c) in potential_json.char_indices() {
-                    if c == ch {
-                        depth += 1;
-                    } else if c == target_close {
-                        depth -= 1;
-                        if depth == 0 {
-                            end_index = Some(j + 1);
-                            break;
-                        }
+    // Try to find the first { or [ and parse from there
+    let trimmed = response.trim();
+    for (i, ch) in trimmed.char_indices() {
+        if ch == '{' || ch == '[' {
+            let potential_json = &trimmed[i..];
+
+            // Find the matching closing bracket
+            let mut depth = 0;
+            let mut end_index = None;
+            let target_close = if ch == '{' { '}' } else { ']' };
+
+            for (j, c) in potential_json.char_indices() {
+                if c == ch {
+                    depth += 1;
+                } else if c == target_close {
+                    depth -= 1;
+                    if depth == 0 {
+                        end_index = Some(j + 1);
+                        break;
                     }
                 }
+            }
 
-                if let Some(end) = end_index {
-                    let json_str = &potential_json[..end];
-                    if serde_json::from_str::<Value>(json_str).is_ok() {
-                        return Ok(json_str.to_string());
-                    }
+            if let Some(end) = end_index {
+                let json_str = &potential_json[..end];
+                if serde_json::from_str::<Value>(json_str).is_ok() {
+                    return Ok(json_str.to_string());
                 }
             }
         }
-
-        Err(LlmError::ParseError(
-            "No valid JSON found in response".to_string(),
-        ))
     }
+
+    Err(LlmError::ParseError(
+        "No valid JSON found in response".to_string(),
+    ))
 }
 
 /// Response from the LLM (for compatibility)
@@ -753,7 +1480,7 @@ mod tests {
     use super::*;
     use crate::config::LlmOptionsBuilder;
     use crate::contracts::Contract;
-    use crate::tools::{Function, Tool};
+    use crate::tools::{Function, Tool, ToolCallFunction};
 
     #[test]
     fn test_message_builders() {
@@ -825,6 +1552,59 @@ mod tests {
         assert_eq!(client.get_config().seed, Some(42));
     }
 
+    #[test]
+    fn test_llm_client_from_json_sampling_options() {
+        let json_str = r#"{
+            "model": "llama3.2",
+            "context_window": 8192,
+            "top_k": 40,
+            "min_p": 0.05,
+            "repeat_penalty": 1.1,
+            "stop": "</answer>",
+            "keep_alive": 300,
+            "extra": {"num_gpu": 1}
+        }"#;
+
+        let config = LLMClient::from_json(json_str).unwrap().config;
+        assert_eq!(config.context_window, Some(8192));
+        assert_eq!(config.top_k, Some(40));
+        assert_eq!(config.min_p, Some(0.05));
+        assert_eq!(config.repeat_penalty, Some(1.1));
+        assert_eq!(config.stop, Some(vec!["</answer>".to_string()]));
+        assert_eq!(config.keep_alive.as_deref(), Some("300s"));
+        assert_eq!(config.extra["num_gpu"], 1);
+    }
+
+    #[test]
+    fn test_llm_client_from_json_provider() {
+        let json_str = r#"{
+            "model": "qwen2.5",
+            "provider": "openai",
+            "base_url": "http://vllm:8000",
+            "api_key_env": "VLLM_API_KEY"
+        }"#;
+
+        let client = LLMClient::from_json(json_str).unwrap();
+        let provider = client.get_provider();
+        assert_eq!(provider.kind, ProviderKind::OpenAi);
+        assert_eq!(provider.chat_url(), "http://vllm:8000/v1/chat/completions");
+        assert_eq!(provider.api_key_env.as_deref(), Some("VLLM_API_KEY"));
+
+        let json_str = r#"{
+            "model": "claude-sonnet",
+            "provider": {"kind": "anthropic", "api_key": "secret"}
+        }"#;
+        let client = LLMClient::from_json(json_str).unwrap();
+        assert_eq!(client.get_provider().kind, ProviderKind::Anthropic);
+
+        let result = LLMClient::from_json(r#"{"model": "m", "provider": "bogus"}"#);
+        assert!(matches!(result, Err(LlmError::ConfigError(_))));
+
+        // No provider means local Ollama
+        let client = LLMClient::new("llama3.2");
+        assert_eq!(client.get_provider().kind, ProviderKind::Ollama);
+    }
+
     #[test]
     fn test_llm_client_from_json_missing_model() {
         let json_str = r#"{"temperature": 0.8}"#;
@@ -837,6 +1617,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_retry_policy() {
+        let client = LLMClient::from_json(
+            r#"{"model": "llama3.2", "retry": {"max_retries": 4, "deadline_ms": 60000}}"#,
+        )
+        .unwrap();
+        let expected = RetryPolicy::new()
+            .with_max_retries(4)
+            .with_deadline(std::time::Duration::from_secs(60));
+        assert_eq!(client.get_retry_policy(), &expected);
+
+        // Requests start from the client's policy
+        assert_eq!(client.chat("Hello").retry, expected);
+        let request = client.chat_structured::<Value>("Hello").with_retries(1);
+        assert_eq!(request.retry, expected.clone().with_max_retries(1));
+        let request = client.chat("Hello").with_retry_policy(RetryPolicy::new());
+        assert_eq!(request.retry.max_retries, 0);
+
+        assert!(matches!(
+            LLMClient::from_json(r#"{"model": "m", "retry": {"max_retries": -1}}"#),
+            Err(LlmError::ConfigError(_))
+        ));
+    }
+
     #[test]
     fn test_chat_request_builder() {
         let client = LLMClient::new("test-model");
@@ -845,12 +1649,12 @@ mod tests {
         let request = client.chat("Hello");
         assert_eq!(request.messages.len(), 1);
         assert_eq!(request.messages[0].content, Some("Hello".to_string()));
-        assert_eq!(request.retries, 0);
+        assert_eq!(request.retry.max_retries, 0);
         assert!(request.tools.is_none());
 
         // With retries
         let request = client.chat("Hello").with_retries(3);
-        assert_eq!(request.retries, 3);
+        assert_eq!(request.retry.max_retries, 3);
 
         // With multiple messages
         let messages = vec![Message::system("Be helpful"), Message::user("What is 2+2?")];
@@ -926,13 +1730,13 @@ mod tests {
         // Basic structured request
         let request = client.chat_structured::<TestResponse>("Give me a person");
         assert_eq!(request.messages.len(), 1);
-        assert_eq!(request.retries, 0);
+        assert_eq!(request.retry.max_retries, 0);
 
         // With retries
         let request = client
             .chat_structured::<TestResponse>("Give me a person")
             .with_retries(2);
-        assert_eq!(request.retries, 2);
+        assert_eq!(request.retry.max_retries, 2);
 
         // With system message
         let messages = vec![
@@ -943,6 +1747,33 @@ mod tests {
         assert_eq!(request.messages.len(), 2);
     }
 
+    #[test]
+    fn test_structured_parse_and_validate() {
+        #[derive(Debug, Deserialize, JsonSchema)]
+        struct Vote {
+            approve: bool,
+            confidence: u8,
+        }
+
+        let schema = serde_json::to_value(schemars::schema_for!(Vote)).unwrap();
+        let parse = StructuredChatRequest::<Vote>::parse_and_validate;
+
+        let vote = parse(
+            "Here you go:\n```json\n{\"approve\": true, \"confidence\": 90}\n```",
+            &schema,
+        )
+        .unwrap();
+        assert!(vote.approve);
+        assert_eq!(vote.confidence, 90);
+
+        let problem = parse(r#"{"approve": "yes", "confidence": 300}"#, &schema).unwrap_err();
+        assert!(problem.contains("$.approve: expected boolean, got string"));
+        assert!(problem.contains("$.confidence: expected a value <= 255"));
+
+        let problem = parse("I approve", &schema).unwrap_err();
+        assert!(problem.contains("No valid JSON"), "{}", problem);
+    }
+
     #[test]
     fn test_extract_json_from_response() {
         // Plain JSON
@@ -1019,11 +1850,124 @@ mod tests {
         // Test method chaining
         let request = client.chat("Hello").with_tools(tools).with_retries(3);
 
-        assert_eq!(request.retries, 3);
+        assert_eq!(request.retry.max_retries, 3);
         assert!(request.tools.is_some());
         assert_eq!(request.tools.as_ref().unwrap().len(), 1);
     }
 
+    #[test]
+    fn test_agent_budget_builder() {
+        let client = LLMClient::new("test-model");
+
+        let request = client.chat("Hello");
+        assert_eq!(request.max_iterations, DEFAULT_MAX_ITERATIONS);
+        assert!(request.token_budget.is_none());
+
+        let request = client
+            .chat("Hello")
+            .with_max_iterations(3)
+            .with_token_budget(2000);
+        assert_eq!(request.max_iterations, 3);
+        assert_eq!(request.token_budget, Some(2000));
+    }
+
+    /// Fetches its answer inside its own runtime, like handlers that do I/O
+    struct BlockingHandler;
+
+    impl CustomToolHandler for BlockingHandler {
+        fn can_handle(&self, tool_name: &str) -> bool {
+            tool_name == "get_balance"
+        }
+
+        fn execute(&self, _tool_call: &ToolCall) -> Result<String, String> {
+            Ok(block_on(async { "42".to_string() }))
+        }
+    }
+
+    #[test]
+    fn test_blocking_agent_runs_tools_outside_runtime() {
+        let client = LLMClient::new("test-model");
+        let request = client
+            .chat("What is my balance?")
+            .with_custom_handlers(vec![Box::new(BlockingHandler)]);
+
+        let mut replies = vec![
+            Message {
+                tool_calls: Some(vec![ToolCall {
+                    id: "call_1".to_string(),
+                    tool_type: "function".to_string(),
+                    function: ToolCallFunction {
+                        name: "get_balance".to_string(),
+                        arguments: "{}".to_string(),
+                    },
+                }]),
+                ..Message::assistant("")
+            },
+            Message::assistant("Your balance is 42"),
+        ]
+        .into_iter();
+
+        // Model calls start the runtime just like the real send does
+        let run = request
+            .run_agent_with(|_| {
+                let message = replies.next().unwrap();
+                block_on(async move {
+                    Ok(ChatCompletion {
+                        message,
+                        usage: None,
+                    })
+                })
+            })
+            .unwrap();
+
+        assert!(run.is_complete());
+        assert_eq!(run.answer, "Your balance is 42");
+        assert_eq!(run.iterations, 2);
+        assert!(run.messages.iter().any(|m| m
+            .content
+            .as_deref()
+            .is_some_and(|c| c.contains("42"))
+            && m.role != "assistant"));
+    }
+
+    #[test]
+    fn test_agent_run_transactions() {
+        let tx = Transaction {
+            to: "0x1234567890123456789012345678901234567890".to_string(),
+            value: "1".to_string(),
+            contract_call: None,
+            data: "0x".to_string(),
+            description: "ETH transfer".to_string(),
+            gas_estimate: None,
+        };
+        let run = AgentRun {
+            answer: "Sent".to_string(),
+            messages: vec![
+                Message::user("Send 1 wei"),
+                Message::tool_result(
+                    "call_1".to_string(),
+                    "get_balance".to_string(),
+                    "42".to_string(),
+                ),
+                Message::tool_result(
+                    "call_2".to_string(),
+                    "send_eth".to_string(),
+                    serde_json::to_string(&tx).unwrap(),
+                ),
+                Message::assistant("Sent"),
+            ],
+            iterations: 3,
+            usage: Usage::default(),
+            stop_reason: StopReason::Completed,
+        };
+
+        assert!(run.is_complete());
+        assert_eq!(run.tool_results().count(), 2);
+        let transactions = run.transactions();
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].to, tx.to);
+    }
+
     #[test]
     fn test_llm_options_builder() {
         let options = LlmOptionsBuilder::new()
@@ -1032,6 +1976,12 @@ mod tests {
             .top_p(0.9)
             .seed(42)
             .context_window(4096)
+            .top_k(40)
+            .min_p(0.05)
+            .repeat_penalty(1.1)
+            .stop(vec!["\n\n".to_string()])
+            .keep_alive("5m")
+            .extra("mirostat", json!(2))
             .build();
 
         assert_eq!(options.temperature, Some(0.7));
@@ -1039,6 +1989,12 @@ mod tests {
         assert_eq!(options.top_p, Some(0.9));
         assert_eq!(options.seed, Some(42));
         assert_eq!(options.context_window, Some(4096));
+        assert_eq!(options.top_k, Some(40));
+        assert_eq!(options.min_p, Some(0.05));
+        assert_eq!(options.repeat_penalty, Some(1.1));
+        assert_eq!(options.stop, Some(vec!["\n\n".to_string()]));
+        assert_eq!(options.keep_alive.as_deref(), Some("5m"));
+        assert_eq!(options.extra["mirostat"], 2);
     }
 
     #[test]
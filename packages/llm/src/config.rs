@@ -1,17 +1,20 @@
 use crate::client::Message;
 use crate::contracts::Contract;
 use crate::errors::AgentError;
+use crate::loader::{ConfigLoader, CONFIG_URI_VAR};
+use crate::policy::Policy;
+use crate::providers::Provider;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::env;
-use wavs_wasi_utils::http::{fetch_json, http_request_get};
-use wstd::http::HeaderValue;
-use wstd::runtime::block_on;
 
-/// Configuration options for Ollama LLM
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+/// Configuration options for LLM requests
+#[derive(Debug, Clone, Serialize, Deserialize, Default, JsonSchema)]
 pub struct LlmOptions {
     /// Temperature controls randomness (0.0-2.0)
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 0.0, max = 2.0))]
     pub temperature: Option<f32>,
 
     /// Maximum tokens to generate
@@ -20,6 +23,7 @@ pub struct LlmOptions {
 
     /// Top_p controls diversity (0.0-1.0)
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 0.0, max = 1.0))]
     pub top_p: Option<f32>,
 
     /// Seed for deterministic outputs
@@ -29,6 +33,37 @@ pub struct LlmOptions {
     /// Context window size
     #[serde(skip_serializing_if = "Option::is_none")]
     pub context_window: Option<u32>,
+
+    /// Sample only from the k most likely tokens
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+
+    /// Minimum token probability relative to the most likely token (0.0-1.0)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[schemars(range(min = 0.0, max = 1.0))]
+    pub min_p: Option<f32>,
+
+    /// Penalty for repeating tokens (1.0 disables it)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f32>,
+
+    /// Sequences that end generation when produced
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+
+    /// How long Ollama keeps the model loaded after the request, e.g. "5m" or "-1s"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_alive: Option<String>,
+
+    /// Provider-specific options passed through unchanged, e.g. Ollama's `num_gpu` or `mirostat`
+    ///
+    /// Ollama receives these inside `options`; other providers at the top level of the request.
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub extra: Map<String, Value>,
+
+    /// Inference backend to send requests to (defaults to local Ollama)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<Provider>,
 }
 
 impl LlmOptions {
@@ -66,6 +101,48 @@ impl LlmOptions {
         self.context_window = Some(context_window);
         self
     }
+
+    /// Set top_k
+    pub fn with_top_k(mut self, top_k: u32) -> Self {
+        self.top_k = Some(top_k);
+        self
+    }
+
+    /// Set min_p
+    pub fn with_min_p(mut self, min_p: f32) -> Self {
+        self.min_p = Some(min_p);
+        self
+    }
+
+    /// Set repeat penalty
+    pub fn with_repeat_penalty(mut self, repeat_penalty: f32) -> Self {
+        self.repeat_penalty = Some(repeat_penalty);
+        self
+    }
+
+    /// Set stop sequences
+    pub fn with_stop(mut self, stop: Vec<String>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    /// Set how long Ollama keeps the model loaded
+    pub fn with_keep_alive(mut self, keep_alive: impl Into<String>) -> Self {
+        self.keep_alive = Some(keep_alive.into());
+        self
+    }
+
+    /// Set a provider-specific option
+    pub fn with_extra(mut self, key: impl Into<String>, value: Value) -> Self {
+        self.extra.insert(key.into(), value);
+        self
+    }
+
+    /// Set provider
+    pub fn with_provider(mut self, provider: Provider) -> Self {
+        self.provider = Some(provider);
+        self
+    }
 }
 
 /// Builder for LlmOptions
@@ -117,15 +194,63 @@ impl LlmOptionsBuilder {
         self
     }
 
+    /// Set top_k
+    pub fn top_k(mut self, top_k: u32) -> Self {
+        self.config.top_k = Some(top_k);
+        self
+    }
+
+    /// Set min_p
+    pub fn min_p(mut self, min_p: f32) -> Self {
+        self.config.min_p = Some(min_p);
+        self
+    }
+
+    /// Set repeat penalty
+    pub fn repeat_penalty(mut self, repeat_penalty: f32) -> Self {
+        self.config.repeat_penalty = Some(repeat_penalty);
+        self
+    }
+
+    /// Set stop sequences
+    pub fn stop(mut self, stop: Vec<String>) -> Self {
+        self.config.stop = Some(stop);
+        self
+    }
+
+    /// Set how long Ollama keeps the model loaded
+    pub fn keep_alive(mut self, keep_alive: impl Into<String>) -> Self {
+        self.config.keep_alive = Some(keep_alive.into());
+        self
+    }
+
+    /// Set a provider-specific option
+    pub fn extra(mut self, key: impl Into<String>, value: Value) -> Self {
+        self.config.extra.insert(key.into(), value);
+        self
+    }
+
+    /// Set provider
+    pub fn provider(mut self, provider: Provider) -> Self {
+        self.config.provider = Some(provider);
+        self
+    }
+
     /// Build the configuration
     pub fn build(self) -> LlmOptions {
         self.config
     }
 }
 
+/// Current layout of [`Config`] documents; see [`crate::loader::migrate`]
+pub const CONFIG_VERSION: u32 = 1;
+
 /// Generic Config for agent's decision making
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Config {
+    /// Layout version of the document, for migrations; documents without one are version 0
+    #[serde(default)]
+    pub version: u32,
     pub contracts: Vec<Contract>,
     pub llm_config: LlmOptions,
     pub model: String,
@@ -134,100 +259,50 @@ pub struct Config {
     /// Any global configuration values
     #[serde(default)]
     pub config: std::collections::HashMap<String, String>,
+    /// JSON-RPC endpoint for read-only contract calls; without it no read tools are offered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rpc_url: Option<String>,
+    /// Address transactions are sent from; with `rpc_url`, proposed transactions are simulated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sender: Option<String>,
+    /// Limits every proposed transaction must respect
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<Policy>,
 }
 
 impl Config {
-    /// Load Config from environment variable CONFIG_URI or use default
+    /// Load Config from the document named by the `config_uri` environment variable, or use default
+    ///
+    /// Only the environment is read: WAVS config variables are not applied,
+    /// since reading them needs the component's `host::config_var`. Use
+    /// [`ConfigLoader::with_config_vars`] for those and for overrides.
     pub fn load() -> Result<Self, String> {
-        // Check if CONFIG_URI environment variable is set
-        if let Ok(config_uri) = env::var("config_uri") {
-            println!("Loading config from URI: {}", config_uri);
-            Self::load_from_uri(&config_uri)
-        } else {
-            println!("No CONFIG_URI found, using default configuration");
-            Ok(Self::default())
-        }
+        let loader = match env::var(CONFIG_URI_VAR) {
+            Ok(config_uri) => ConfigLoader::new().with_uri(config_uri),
+            Err(_) => ConfigLoader::new().with_defaults(Self::default()),
+        };
+        Ok(loader.load()?)
     }
 
-    /// Load Config from a URI
+    /// Load Config from an `http(s)://`, `ipfs://` or `data:` URI
+    ///
+    /// Fields the document omits get their serde defaults, never the
+    /// example contracts and prompt of [`Config::default`].
     pub fn load_from_uri(uri: &str) -> Result<Self, String> {
-        let uri = uri.to_string();
-        block_on(async move {
-            // Strip any quotation marks from the URI
-            let clean_uri = uri.trim_matches('"');
-
-            println!("Loading config from URI: {}", clean_uri);
-
-            // Check URI scheme
-            if let Some(uri_with_scheme) = clean_uri.strip_prefix("ipfs://") {
-                // IPFS URI scheme detected
-                Self::load_from_ipfs(uri_with_scheme)
-            } else if clean_uri.starts_with("http://") || clean_uri.starts_with("https://") {
-                // HTTP URI scheme detected
-                Self::fetch_from_uri(clean_uri)
-            } else {
-                // Only support http/https and ipfs URIs
-                Err(format!("Unsupported URI scheme: {}", clean_uri))
-            }
-        })
+        Ok(ConfigLoader::new().with_uri(uri).load()?)
     }
 
-    /// Load configuration from IPFS
-    fn load_from_ipfs(cid: &str) -> Result<Self, String> {
-        let cid = cid.to_string();
-        block_on(async move {
-            let gateway_url = std::env::var("WAVS_ENV_IPFS_GATEWAY_URL").unwrap_or_else(|_| {
-                println!("WAVS_ENV_IPFS_GATEWAY_URL not set, using default");
-                "https://gateway.lighthouse.storage/ipfs".to_string()
-            });
-
-            // Strip any quotation marks from the gateway URL
-            let clean_gateway_url = gateway_url.trim_matches('"');
-
-            // Construct HTTP URL, avoiding duplicate /ipfs in the path
-            let http_url = if clean_gateway_url.ends_with("/ipfs") {
-                format!("{}/{}", clean_gateway_url, cid)
-            } else if clean_gateway_url.ends_with("/ipfs/") {
-                format!("{}{}", clean_gateway_url, cid)
-            } else if clean_gateway_url.ends_with("/") {
-                format!("{}ipfs/{}", clean_gateway_url, cid)
-            } else {
-                format!("{}/ipfs/{}", clean_gateway_url, cid)
-            };
-
-            println!("Fetching IPFS config from: {}", http_url);
-            Self::fetch_from_uri(&http_url)
-        })
+    /// Async version of [`Config::load_from_uri`], for components already inside the runtime
+    pub async fn load_from_uri_async(uri: &str) -> Result<Self, String> {
+        Ok(ConfigLoader::new().with_uri(uri).load_async().await?)
     }
 
-    /// Fetch configuration from a HTTP/HTTPS URI
-    fn fetch_from_uri(uri: &str) -> Result<Self, String> {
-        let uri = uri.to_string();
-        block_on(async move {
-            // Strip any quotation marks from the URI
-            let clean_uri = uri.trim_matches('"');
-
-            println!("Creating HTTP request for URI: {}", clean_uri);
-
-            // Create HTTP request
-            let mut req = http_request_get(clean_uri).map_err(|e| {
-                let error_msg = format!("Failed to create request: {}", e);
-                println!("Error: {}", error_msg);
-                error_msg
-            })?;
-
-            // Add appropriate headers for JSON content
-            req.headers_mut()
-                .insert("Accept", HeaderValue::from_static("application/json"));
-
-            println!("Sending HTTP request...");
-
-            // Execute HTTP request and parse response as JSON
-            let config: Config = fetch_json(req).await.unwrap();
-
-            println!("Successfully loaded configuration");
-            Ok(config)
-        })
+    /// JSON Schema for Config documents
+    ///
+    /// Published as `config.schema.json`; regenerate it with
+    /// `cargo run --example config_schema`.
+    pub fn json_schema() -> Value {
+        schemars::schema_for!(Config).to_value()
     }
 
     /// Load Config from JSON
@@ -296,6 +371,19 @@ impl Config {
             }
         }
 
+        if let Some(sender) = &self.sender {
+            if sender.len() != 42 || !sender.starts_with("0x") {
+                return Err(AgentError::Configuration(format!(
+                    "Sender has invalid address format: {}",
+                    sender
+                )));
+            }
+        }
+
+        if let Some(policy) = &self.policy {
+            policy.validate()?;
+        }
+
         Ok(())
     }
 }
@@ -312,6 +400,7 @@ impl Default for Config {
         .to_string();
 
         Self {
+            version: CONFIG_VERSION,
             contracts: vec![Contract::new_with_description(
                 "USDC",
                 "0xb7278a61aa25c888815afc32ad3cc52ff24fe575",
@@ -326,14 +415,52 @@ impl Default for Config {
             model: "llama3.2".to_string(),
             messages: vec![Message::system(default_system_prompt)],
             config: std::collections::HashMap::new(),
+            rpc_url: None,
+            sender: None,
+            policy: None,
         }
     }
 }
 
+/// HTTP URL for an IPFS CID on the gateway in `WAVS_ENV_IPFS_GATEWAY_URL`
+pub(crate) fn ipfs_gateway_url(cid: &str) -> String {
+    let gateway_url = std::env::var("WAVS_ENV_IPFS_GATEWAY_URL").unwrap_or_else(|_| {
+        println!("WAVS_ENV_IPFS_GATEWAY_URL not set, using default");
+        "https://gateway.lighthouse.storage/ipfs".to_string()
+    });
+
+    // Strip any quotation marks from the gateway URL
+    let clean_gateway_url = gateway_url.trim_matches('"');
+
+    // Construct HTTP URL, avoiding duplicate /ipfs in the path
+    if clean_gateway_url.ends_with("/ipfs") {
+        format!("{}/{}", clean_gateway_url, cid)
+    } else if clean_gateway_url.ends_with("/ipfs/") {
+        format!("{}{}", clean_gateway_url, cid)
+    } else if clean_gateway_url.ends_with("/") {
+        format!("{}ipfs/{}", clean_gateway_url, cid)
+    } else {
+        format!("{}/ipfs/{}", clean_gateway_url, cid)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_published_schema_is_current() {
+        let published: Value = serde_json::from_str(include_str!("../config.schema.json")).unwrap();
+        assert_eq!(
+            published,
+            Config::json_schema(),
+            "regenerate with `cargo run --example config_schema > config.schema.json`"
+        );
+
+        let default = serde_json::to_value(Config::default()).unwrap();
+        assert!(crate::schema::validate(&default, &published).is_ok());
+    }
+
     #[test]
     fn test_config_from_json() {
         // Valid Config JSON
@@ -361,6 +488,10 @@ mod tests {
             ],
             "config": {
                 "test_key": "test_value"
+            },
+            "policy": {
+                "max_value_per_tx": "1000",
+                "require_description": true
             }
         }"#;
 
@@ -385,12 +516,20 @@ mod tests {
             "Test system message"
         );
         assert_eq!(config.config.get("test_key").unwrap(), "test_value");
+        let policy = config.policy.as_ref().unwrap();
+        assert_eq!(policy.max_value_per_tx.as_deref(), Some("1000"));
+        assert!(policy.require_description);
+
+        // Policies are validated with the rest of the config
+        let invalid = json.replace(r#""1000""#, r#""a lot""#);
+        assert!(Config::from_json(&invalid).is_err());
     }
 
     #[test]
     fn test_config_validation() {
         // Valid Config
         let valid_config = Config {
+            version: CONFIG_VERSION,
             contracts: vec![Contract::new(
                 "TestContract",
                 "0x1234567890123456789012345678901234567890",
@@ -400,12 +539,16 @@ mod tests {
             model: "test-model".to_string(),
             messages: vec![Message::system("Test system message".to_string())],
             config: std::collections::HashMap::new(),
+            rpc_url: None,
+            sender: None,
+            policy: None,
         };
 
         assert!(valid_config.validate().is_ok());
 
         // Invalid contract address
         let invalid_address_config = Config {
+            version: CONFIG_VERSION,
             contracts: vec![Contract::new(
                 "TestContract",
                 "invalid-address",
@@ -415,12 +558,16 @@ mod tests {
             model: "test-model".to_string(),
             messages: vec![],
             config: std::collections::HashMap::new(),
+            rpc_url: None,
+            sender: None,
+            policy: None,
         };
 
         assert!(invalid_address_config.validate().is_err());
 
         // Empty ABI
         let empty_abi_config = Config {
+            version: CONFIG_VERSION,
             contracts: vec![Contract::new(
                 "TestContract",
                 "0x1234567890123456789012345678901234567890",
@@ -430,6 +577,9 @@ mod tests {
             model: "test-model".to_string(),
             messages: vec![],
             config: std::collections::HashMap::new(),
+            rpc_url: None,
+            sender: None,
+            policy: None,
         };
 
         assert!(empty_abi_config.validate().is_err());
@@ -438,6 +588,7 @@ mod tests {
     #[test]
     fn test_get_contract_by_name() {
         let config = Config {
+            version: CONFIG_VERSION,
             contracts: vec![
                 Contract::new(
                     "Contract1",
@@ -454,6 +605,9 @@ mod tests {
             model: "test-model".to_string(),
             messages: vec![],
             config: std::collections::HashMap::new(),
+            rpc_url: None,
+            sender: None,
+            policy: None,
         };
 
         // Test exact match
@@ -474,6 +628,7 @@ mod tests {
     #[test]
     fn test_format_contract_descriptions() {
         let config = Config {
+            version: CONFIG_VERSION,
             contracts: vec![
                 Contract::new_with_description(
                     "Contract1",
@@ -492,6 +647,9 @@ mod tests {
             model: "test-model".to_string(),
             messages: vec![],
             config: std::collections::HashMap::new(),
+            rpc_url: None,
+            sender: None,
+            policy: None,
         };
 
         let descriptions = config.format_contract_descriptions();
@@ -0,0 +1,297 @@
+//! Multi-sample consensus
+//!
+//! Operators must all produce the same payload, but a model can give
+//! different answers even with a seed. Consensus mode asks the same question
+//! several times with fixed, distinct seeds, reduces every answer to a
+//! canonical form and only accepts the answer a quorum of samples agree on.
+
+use crate::client::LLMClient;
+use crate::errors::LlmError;
+use serde_json::Value;
+use std::future::Future;
+
+/// How many samples to draw and how many must agree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsensusOptions {
+    /// Number of samples to draw
+    pub samples: usize,
+
+    /// Number of samples that must give the same answer
+    pub quorum: usize,
+
+    /// Seed of the first sample; sample `i` uses `base_seed + i`.
+    /// Defaults to the client's seed, or 0.
+    pub base_seed: Option<u32>,
+}
+
+impl ConsensusOptions {
+    /// Draw `samples` samples and require a strict majority to agree
+    pub fn new(samples: usize) -> Self {
+        Self {
+            samples,
+            quorum: samples / 2 + 1,
+            base_seed: None,
+        }
+    }
+
+    /// Set the number of agreeing samples required
+    pub fn with_quorum(mut self, quorum: usize) -> Self {
+        self.quorum = quorum;
+        self
+    }
+
+    /// Set the seed of the first sample
+    pub fn with_base_seed(mut self, base_seed: u32) -> Self {
+        self.base_seed = Some(base_seed);
+        self
+    }
+
+    fn validate(&self) -> Result<(), LlmError> {
+        if self.samples == 0 {
+            return Err(LlmError::InvalidInput(
+                "Consensus needs at least one sample".to_string(),
+            ));
+        }
+        if self.quorum == 0 || self.quorum > self.samples {
+            return Err(LlmError::InvalidInput(format!(
+                "Consensus quorum must be between 1 and {}, got {}",
+                self.samples, self.quorum
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// The answer a quorum of samples agreed on
+#[derive(Debug, Clone)]
+pub struct Consensus<T> {
+    /// The winning answer, as returned by the first sample that gave it
+    pub answer: T,
+
+    /// Canonical form shared by every agreeing sample
+    pub canonical: String,
+
+    /// Number of samples that gave the winning answer
+    pub votes: usize,
+
+    /// Number of samples drawn
+    pub samples: usize,
+
+    /// Number of distinct answers among the successful samples
+    pub distinct: usize,
+
+    /// Number of samples that failed outright
+    pub failures: usize,
+}
+
+impl<T> Consensus<T> {
+    /// Fraction of all samples that agreed on the answer
+    pub fn agreement(&self) -> f64 {
+        self.votes as f64 / self.samples as f64
+    }
+
+    /// Whether every sample gave the same answer
+    pub fn is_unanimous(&self) -> bool {
+        self.votes == self.samples
+    }
+}
+
+/// Draw the samples and tally them
+///
+/// `sample` is called once per seed with a client configured for that seed and
+/// returns the canonical form of its answer along with the answer itself.
+pub(crate) async fn run<T, F>(
+    client: &LLMClient,
+    options: &ConsensusOptions,
+    mut sample: impl FnMut(LLMClient) -> F,
+) -> Result<Consensus<T>, LlmError>
+where
+    F: Future<Output = Result<(String, T), LlmError>>,
+{
+    options.validate()?;
+
+    let base_seed = options
+        .base_seed
+        .or(client.get_config().seed)
+        .unwrap_or_default();
+
+    let mut results = Vec::with_capacity(options.samples);
+    for i in 0..options.samples {
+        let seed = base_seed.wrapping_add(i as u32);
+        results.push(sample(client.with_seed(seed)).await);
+    }
+
+    tally(results, options)
+}
+
+/// Group answers by canonical form and pick the most common one
+///
+/// Ties go to the answer that appeared first, so every operator picks the same one.
+pub(crate) fn tally<T>(
+    results: Vec<Result<(String, T), LlmError>>,
+    options: &ConsensusOptions,
+) -> Result<Consensus<T>, LlmError> {
+    let samples = results.len();
+    let mut failures = 0;
+    let mut last_error = None;
+    // (canonical, first answer, votes) in order of first appearance
+    let mut groups: Vec<(String, T, usize)> = Vec::new();
+
+    for result in results {
+        match result {
+            Ok((canonical, answer)) => match groups.iter_mut().find(|g| g.0 == canonical) {
+                Some(group) => group.2 += 1,
+                None => groups.push((canonical, answer, 1)),
+            },
+            Err(e) => {
+                failures += 1;
+                last_error = Some(e);
+            }
+        }
+    }
+
+    let distinct = groups.len();
+    let best = groups
+        .into_iter()
+        .enumerate()
+        .max_by_key(|(i, group)| (group.2, std::cmp::Reverse(*i)))
+        .map(|(_, group)| group);
+
+    match best {
+        Some((canonical, answer, votes)) if votes >= options.quorum => Ok(Consensus {
+            answer,
+            canonical,
+            votes,
+            samples,
+            distinct,
+            failures,
+        }),
+        Some((_, _, votes)) => Err(LlmError::NoConsensus(format!(
+            "best answer had {} of {} votes across {} distinct answers, quorum is {}",
+            votes, samples, distinct, options.quorum
+        ))),
+        None => Err(LlmError::NoConsensus(format!(
+            "all {} samples failed{}",
+            samples,
+            last_error
+                .map(|e| format!(", last error: {}", e))
+                .unwrap_or_default()
+        ))),
+    }
+}
+
+/// Canonical form of a text answer
+///
+/// Case, surrounding whitespace, runs of whitespace and trailing sentence
+/// punctuation are not significant.
+pub fn normalize_text(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_end_matches(['.', '!', '?'])
+        .to_lowercase()
+}
+
+/// Canonical form of a JSON value: compact, with object keys sorted
+pub fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Object(object) => {
+            let mut entries: Vec<_> = object.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            let fields: Vec<String> = entries
+                .into_iter()
+                .map(|(key, value)| {
+                    format!("{}:{}", Value::from(key.as_str()), canonical_json(value))
+                })
+                .collect();
+            format!("{{{}}}", fields.join(","))
+        }
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(canonical_json).collect();
+            format!("[{}]", items.join(","))
+        }
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn vote(answer: &str) -> Result<(String, String), LlmError> {
+        Ok((normalize_text(answer), answer.to_string()))
+    }
+
+    #[test]
+    fn test_normalize_text() {
+        assert_eq!(normalize_text("  Yes.\n"), "yes");
+        assert_eq!(
+            normalize_text("Approve   the\tproposal!"),
+            "approve the proposal"
+        );
+        assert_ne!(normalize_text("yes"), normalize_text("no"));
+    }
+
+    #[test]
+    fn test_canonical_json() {
+        let a = json!({"b": [1, {"y": true, "x": null}], "a": "s"});
+        let b: Value =
+            serde_json::from_str(r#"{ "a": "s", "b": [1, {"x": null, "y": true}] }"#).unwrap();
+        assert_eq!(
+            canonical_json(&a),
+            r#"{"a":"s","b":[1,{"x":null,"y":true}]}"#
+        );
+        assert_eq!(canonical_json(&a), canonical_json(&b));
+    }
+
+    #[test]
+    fn test_tally_majority() {
+        let options = ConsensusOptions::new(5);
+        assert_eq!(options.quorum, 3);
+
+        let results = vec![
+            vote("No"),
+            vote("Yes."),
+            vote("yes"),
+            Err(LlmError::RequestError("timeout".to_string())),
+            vote(" YES "),
+        ];
+        let consensus = tally(results, &options).unwrap();
+        assert_eq!(consensus.answer, "Yes.");
+        assert_eq!(consensus.canonical, "yes");
+        assert_eq!(consensus.votes, 3);
+        assert_eq!(consensus.distinct, 2);
+        assert_eq!(consensus.failures, 1);
+        assert!((consensus.agreement() - 0.6).abs() < f64::EPSILON);
+        assert!(!consensus.is_unanimous());
+    }
+
+    #[test]
+    fn test_tally_no_quorum() {
+        let results = vec![vote("a"), vote("b"), vote("a"), vote("c")];
+        let err = tally(results, &ConsensusOptions::new(4)).unwrap_err();
+        assert!(matches!(err, LlmError::NoConsensus(_)));
+        assert!(err.to_string().contains("2 of 4 votes"));
+
+        let failed = vec![Err::<(String, String), _>(LlmError::ApiError(
+            "down".into(),
+        ))];
+        let err = tally(failed, &ConsensusOptions::new(1)).unwrap_err();
+        assert!(err.to_string().contains("down"));
+    }
+
+    #[test]
+    fn test_tally_tie_goes_to_first() {
+        let results = vec![vote("b"), vote("a"), vote("a"), vote("b")];
+        let options = ConsensusOptions::new(4).with_quorum(2);
+        assert_eq!(tally(results, &options).unwrap().answer, "b");
+    }
+
+    #[test]
+    fn test_options_validation() {
+        assert!(ConsensusOptions::new(0).validate().is_err());
+        assert!(ConsensusOptions::new(3).with_quorum(4).validate().is_err());
+        assert!(ConsensusOptions::new(3).with_quorum(3).validate().is_ok());
+    }
+}
@@ -1,15 +1,21 @@
-use crate::config::Config;
 use crate::encoding::encode_function_args;
 use crate::errors::AgentError;
-use alloy_json_abi::{Function, JsonAbi};
-use alloy_primitives::{Bytes, U256};
+use alloy_dyn_abi::JsonAbiExt;
+use alloy_json_abi::{Function, JsonAbi, StateMutability};
+use alloy_primitives::{Address, Bytes, U256};
+use alloy_rpc_types::{TransactionInput, TransactionRequest};
+use alloy_sol_types::sol;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
 use std::str::FromStr;
 
 /// Represents a smart contract that the DAO can interact with
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Contract {
     pub name: String,
+    #[schemars(length(min = 42, max = 42))]
     pub address: String,
     pub abi: String,                 // JSON ABI string
     pub description: Option<String>, // Optional description of what the contract does
@@ -46,8 +52,39 @@ impl Contract {
 
     /// Parse the JSON ABI to JsonAbi struct
     fn parse_abi(&self) -> Result<JsonAbi, AgentError> {
-        serde_json::from_str(&self.abi)
-            .map_err(|e| AgentError::Contract(format!("Failed to parse ABI: {}", e)))
+        self.parse_artifact().map(|(abi, _)| abi)
+    }
+
+    /// Parse the ABI along with any NatSpec documentation
+    ///
+    /// `abi` may be a bare ABI array or a compiler artifact with an `abi`
+    /// field. Artifacts from solc (`devdoc`/`userdoc`) and Foundry
+    /// (`metadata.output.devdoc`/`userdoc`) contribute their documentation.
+    pub fn parse_artifact(&self) -> Result<(JsonAbi, NatSpec), AgentError> {
+        let value: Value = serde_json::from_str(&self.abi)
+            .map_err(|e| AgentError::Contract(format!("Failed to parse ABI: {}", e)))?;
+
+        let (abi, docs) =
+            match value {
+                Value::Array(_) => (value, None),
+                Value::Object(mut artifact) => match artifact.remove("abi") {
+                    Some(abi) => (abi, Some(Value::Object(artifact))),
+                    None => return Err(AgentError::Contract(
+                        "Failed to parse ABI: expected an array or an object with an \"abi\" field"
+                            .to_string(),
+                    )),
+                },
+                _ => {
+                    return Err(AgentError::Contract(
+                        "Failed to parse ABI: expected an array or an object".to_string(),
+                    ))
+                }
+            };
+
+        let abi = serde_json::from_value(abi)
+            .map_err(|e| AgentError::Contract(format!("Failed to parse ABI: {}", e)))?;
+        let natspec = docs.map(|d| NatSpec::from_artifact(&d)).unwrap_or_default();
+        Ok((abi, natspec))
     }
 
     /// Encode a function call for this contract using the ABI
@@ -72,13 +109,14 @@ impl Contract {
         Ok(Bytes::from(calldata))
     }
 
-    /// Find a function in the ABI
+    /// Find a function in the ABI by name, or by signature such as
+    /// `safeTransferFrom(address,address,uint256)` to pick an overload
     pub fn find_function(&self, function_name: &str) -> Result<Function, AgentError> {
         let json_abi = self.parse_abi()?;
 
         json_abi
             .functions()
-            .find(|f| f.name == function_name)
+            .find(|f| f.name == function_name || f.signature() == function_name)
             .cloned()
             .ok_or_else(|| {
                 AgentError::Contract(format!("Function '{}' not found in ABI", function_name))
@@ -111,14 +149,93 @@ impl Contract {
     }
 }
 
+/// NatSpec documentation for a contract's functions, keyed by signature
+#[derive(Debug, Clone, Default)]
+pub struct NatSpec {
+    methods: HashMap<String, MethodDoc>,
+}
+
+/// NatSpec documentation for one function
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MethodDoc {
+    /// `@notice` from the userdoc: what the function does, for end users
+    pub notice: Option<String>,
+    /// `@dev` from the devdoc: extra detail for developers
+    pub details: Option<String>,
+    /// `@param` descriptions by parameter name
+    pub params: BTreeMap<String, String>,
+}
+
+impl MethodDoc {
+    /// Notice and details joined into one description, if either is present
+    pub fn description(&self) -> Option<String> {
+        let parts: Vec<&str> = [&self.notice, &self.details]
+            .into_iter()
+            .flatten()
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .collect();
+        (!parts.is_empty()).then(|| parts.join(" "))
+    }
+}
+
+impl NatSpec {
+    /// Collect `devdoc` and `userdoc` from a compiler artifact
+    pub fn from_artifact(artifact: &Value) -> Self {
+        let output = artifact.pointer("/metadata/output");
+        let doc = |name: &str| {
+            artifact
+                .get(name)
+                .or_else(|| output.and_then(|o| o.get(name)))
+                .and_then(|d| d.get("methods"))
+                .and_then(Value::as_object)
+        };
+
+        let mut methods: HashMap<String, MethodDoc> = HashMap::new();
+        for (signature, entry) in doc("userdoc").into_iter().flatten() {
+            // Older compilers emit the notice as a bare string
+            let notice = entry.get("notice").unwrap_or(entry).as_str();
+            methods.entry(signature.clone()).or_default().notice = notice.map(str::to_string);
+        }
+        for (signature, entry) in doc("devdoc").into_iter().flatten() {
+            let method = methods.entry(signature.clone()).or_default();
+            method.details = entry
+                .get("details")
+                .and_then(Value::as_str)
+                .map(str::to_string);
+            if let Some(params) = entry.get("params").and_then(Value::as_object) {
+                method.params = params
+                    .iter()
+                    .filter_map(|(k, v)| Some((k.clone(), v.as_str()?.to_string())))
+                    .collect();
+            }
+        }
+
+        Self { methods }
+    }
+
+    /// Documentation for the function with the given signature, e.g. `transfer(address,uint256)`
+    pub fn method(&self, signature: &str) -> Option<&MethodDoc> {
+        self.methods.get(signature)
+    }
+
+    /// Whether no function is documented
+    pub fn is_empty(&self) -> bool {
+        self.methods.is_empty()
+    }
+}
+
 /// Represents a transaction to be executed through a wallet
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Transaction {
     pub to: String,
     pub value: String, // Using string to handle large numbers safely
     pub contract_call: Option<ContractCall>, // JSON representation of the call to encode
-    pub data: String,  // Will be populated after encoding
+    pub data: String,  // Encoded calldata, "0x" for plain transfers
     pub description: String, // LLM's explanation of the transaction
+    /// Gas the transaction used when simulated, if it was
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gas_estimate: Option<u64>,
 }
 
 impl Transaction {
@@ -144,80 +261,148 @@ impl Transaction {
         true
     }
 
-    /// Validate a transaction
-    pub fn validate_transaction(tx: &Transaction) -> Result<(), AgentError> {
+    /// Validate a transaction against the caller's contracts
+    ///
+    /// Besides the address and value, a call must target one of `contracts`,
+    /// name a function in its ABI, and pass arguments matching that
+    /// function's inputs in number and type. ETH may only be sent to payable
+    /// functions, and calldata in `data` must be a well-formed call. Plain
+    /// transfers without calldata may go to any address.
+    pub fn validate_transaction(
+        tx: &Transaction,
+        contracts: &[Contract],
+    ) -> Result<(), AgentError> {
         // Basic validation
-        if tx.to.len() != 42 || !tx.to.starts_with("0x") {
+        if tx.to.len() != 42 || !tx.to.starts_with("0x") || Address::from_str(&tx.to).is_err() {
             return Err(AgentError::Transaction(
                 "Invalid destination address".to_string(),
             ));
         }
 
         // Ensure value is a valid number
-        if let Err(e) = U256::from_str(&tx.value) {
-            return Err(AgentError::Transaction(format!("Invalid value: {}", e)));
+        let value = U256::from_str(&tx.value)
+            .map_err(|e| AgentError::Transaction(format!("Invalid value: {}", e)))?;
+
+        let data = Bytes::from_str(&tx.data)
+            .map_err(|e| AgentError::Transaction(format!("Invalid data: {}", e)))?;
+
+        if tx.contract_call.is_none() && data.is_empty() {
+            return Ok(());
         }
 
-        // Get Config to look up contracts
-        let config = Config::default();
-
-        // If there's a contract call, validate its arguments
-        if let Some(contract_call) = &tx.contract_call {
-            // Find the contract
-            let contract = config
-                .contracts
-                .iter()
-                .find(|c| c.address.to_lowercase() == tx.to.to_lowercase())
-                .ok_or_else(|| {
-                    AgentError::Contract(format!("Unknown contract at address: {}", tx.to))
+        let contract = contracts
+            .iter()
+            .find(|c| c.address.eq_ignore_ascii_case(&tx.to))
+            .ok_or_else(|| {
+                AgentError::Contract(format!("{} is not one of the configured contracts", tx.to))
+            })?;
+
+        let function = match &tx.contract_call {
+            Some(contract_call) => {
+                // Validate the function call using the contract
+                contract.validate_function_call(&contract_call.function, &contract_call.args)?;
+                if !data.is_empty()
+                    && data
+                        != contract
+                            .encode_function_call(&contract_call.function, &contract_call.args)?
+                {
+                    return Err(AgentError::Transaction(format!(
+                        "Data does not encode the call to {}",
+                        contract_call.function
+                    )));
+                }
+                contract.find_function(&contract_call.function)?
+            }
+            None => {
+                let (abi, _) = contract.parse_artifact()?;
+                let function = abi
+                    .functions()
+                    .find(|f| data.starts_with(f.selector().as_slice()))
+                    .ok_or_else(|| {
+                        AgentError::Contract(format!(
+                            "Data does not call a function of {}",
+                            contract.name
+                        ))
+                    })?;
+                function.abi_decode_input(&data[4..]).map_err(|e| {
+                    AgentError::Contract(format!("Invalid arguments for {}: {}", function.name, e))
                 })?;
+                function.clone()
+            }
+        };
 
-            // Validate the function call using the contract
-            contract.validate_function_call(&contract_call.function, &contract_call.args)?;
+        if !value.is_zero() && function.state_mutability != StateMutability::Payable {
+            return Err(AgentError::Transaction(format!(
+                "{} is not payable but the transaction sends {} wei",
+                function.name, value
+            )));
         }
 
         Ok(())
     }
 
-    // /// Helper function to create a TransactionPayload from a Transaction
-    // pub fn create_payload_from_tx(tx: &Transaction) -> Result<TransactionPayload, AgentError> {
-    //     // Parse address
-    //     let to: Address = tx
-    //         .to
-    //         .parse()
-    //         .map_err(|e| AgentError::Transaction(format!("Invalid address: {}", e)))?;
-
-    //     // Parse value
-    //     let value = U256::from_str(&tx.value)
-    //         .map_err(|e| AgentError::Transaction(format!("Invalid value: {}", e)))?;
-
-    //     // Handle contract calls
-    //     let data = if let Some(contract_call) = &tx.contract_call {
-    //         // Get contract details from the Config
-    //         let config = Config::default();
-
-    //         // Try to find the contract by address
-    //         let contract = config
-    //             .contracts
-    //             .iter()
-    //             .find(|c| c.address.to_lowercase() == tx.to.to_lowercase())
-    //             .ok_or_else(|| {
-    //                 AgentError::Contract(format!("Cannot find contract at address {}", tx.to))
-    //             })?;
-
-    //         // Use the contract to encode the function call
-    //         contract.encode_function_call(&contract_call.function, &contract_call.args)?
-    //     } else {
-    //         Bytes::default()
-    //     };
-
-    //     Ok(TransactionPayload { to, value, data })
-    // }
+    /// Calldata for this transaction
+    ///
+    /// A contract call is encoded with the ABI of the contract in `contracts`
+    /// deployed at `to`; without one, the `data` field is used as given.
+    pub fn calldata(&self, contracts: &[Contract]) -> Result<Bytes, AgentError> {
+        let Some(contract_call) = &self.contract_call else {
+            return Bytes::from_str(&self.data)
+                .map_err(|e| AgentError::Transaction(format!("Invalid data: {}", e)));
+        };
+
+        let contract = contracts
+            .iter()
+            .find(|c| c.address.eq_ignore_ascii_case(&self.to))
+            .ok_or_else(|| {
+                AgentError::Contract(format!("Unknown contract at address: {}", self.to))
+            })?;
+        contract.encode_function_call(&contract_call.function, &contract_call.args)
+    }
+
+    /// Convert to an alloy `TransactionRequest` with encoded calldata
+    pub fn to_transaction_request(
+        &self,
+        contracts: &[Contract],
+    ) -> Result<TransactionRequest, AgentError> {
+        let payload = self.to_payload(contracts)?;
+        Ok(TransactionRequest {
+            to: Some(payload.to.into()),
+            value: Some(payload.value),
+            input: TransactionInput::new(payload.data),
+            ..Default::default()
+        })
+    }
+
+    /// Convert to a [`TransactionPayload`] with encoded calldata
+    ///
+    /// `payload.abi_encode()` (from `alloy_sol_types::SolValue`) gives the
+    /// bytes to return in a component's `WasmResponse`.
+    pub fn to_payload(&self, contracts: &[Contract]) -> Result<TransactionPayload, AgentError> {
+        let to = Address::from_str(&self.to)
+            .map_err(|e| AgentError::Transaction(format!("Invalid address: {}", e)))?;
+        let value = U256::from_str(&self.value)
+            .map_err(|e| AgentError::Transaction(format!("Invalid value: {}", e)))?;
+        let data = self.calldata(contracts)?;
+
+        Ok(TransactionPayload { to, value, data })
+    }
+}
+
+sol! {
+    /// A transaction for the service's submission contract to execute
+    #[derive(Debug, PartialEq, Eq)]
+    struct TransactionPayload {
+        address to;
+        uint256 value;
+        bytes data;
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Config;
     use serde_json::json;
 
     #[test]
@@ -389,6 +574,7 @@ mod tests {
             }),
             data: "0x".to_string(),
             description: "Test transaction".to_string(),
+            gas_estimate: None,
         };
         assert!(valid_tx.is_valid());
 
@@ -399,6 +585,7 @@ mod tests {
             contract_call: None,
             data: "0x".to_string(),
             description: "Invalid address transaction".to_string(),
+            gas_estimate: None,
         };
         assert!(!invalid_address_tx.is_valid());
 
@@ -409,6 +596,7 @@ mod tests {
             contract_call: None,
             data: "0x".to_string(),
             description: "Invalid value transaction".to_string(),
+            gas_estimate: None,
         };
         assert!(!invalid_value_tx.is_valid());
 
@@ -422,7 +610,167 @@ mod tests {
             }),
             data: "0x".to_string(),
             description: "Invalid contract call transaction".to_string(),
+            gas_estimate: None,
         };
         assert!(!invalid_call_tx.is_valid());
     }
+
+    #[test]
+    fn test_parse_artifact() {
+        let abi = r#"[{"name":"pause","type":"function","stateMutability":"nonpayable","inputs":[],"outputs":[]}]"#;
+        let (parsed, natspec) = Contract::new("Vault", "0x0", abi).parse_artifact().unwrap();
+        assert_eq!(parsed.functions().count(), 1);
+        assert!(natspec.is_empty());
+
+        let artifact = json!({
+            "abi": serde_json::from_str::<serde_json::Value>(abi).unwrap(),
+            "userdoc": {"methods": {"pause()": {"notice": "Stops deposits."}}},
+            "metadata": {"output": {"devdoc": {"methods": {"pause()": {
+                "details": "Only the guardian may call this.",
+                "params": {}
+            }}}}}
+        });
+        let contract = Contract::new("Vault", "0x0", &artifact.to_string());
+        let (parsed, natspec) = contract.parse_artifact().unwrap();
+        assert_eq!(parsed.functions().count(), 1);
+        let doc = natspec.method("pause()").unwrap();
+        assert_eq!(doc.notice.as_deref(), Some("Stops deposits."));
+        assert_eq!(
+            doc.description().as_deref(),
+            Some("Stops deposits. Only the guardian may call this.")
+        );
+        assert!(contract.find_function("pause").is_ok());
+
+        let err = Contract::new("Vault", "0x0", r#"{"bytecode": "0x"}"#)
+            .parse_artifact()
+            .unwrap_err();
+        assert!(err.to_string().contains("\"abi\" field"));
+    }
+
+    #[test]
+    fn test_transaction_payload() {
+        use alloy_sol_types::SolValue;
+
+        let usdc = Config::default().contracts;
+        let recipient = "0x0987654321098765432109876543210987654321";
+        let tx = Transaction {
+            to: usdc[0].address.to_uppercase().replacen("0X", "0x", 1),
+            value: "0".to_string(),
+            contract_call: Some(ContractCall {
+                function: "transfer".to_string(),
+                args: vec![json!(recipient), json!("1500000")],
+            }),
+            data: "0x".to_string(),
+            description: "Pay the invoice".to_string(),
+            gas_estimate: None,
+        };
+
+        let payload = tx.to_payload(&usdc).unwrap();
+        assert_eq!(payload.to, Address::from_str(&usdc[0].address).unwrap());
+        assert_eq!(
+            payload.data,
+            usdc[0]
+                .encode_function_call("transfer", &tx.contract_call.as_ref().unwrap().args)
+                .unwrap()
+        );
+        assert_eq!(&payload.data[..4], &[0xa9, 0x05, 0x9c, 0xbb]);
+
+        let encoded = payload.abi_encode();
+        assert_eq!(TransactionPayload::abi_decode(&encoded).unwrap(), payload);
+
+        let request = tx.to_transaction_request(&usdc).unwrap();
+        assert_eq!(request.to, Some(payload.to.into()));
+        assert_eq!(request.value, Some(U256::ZERO));
+        assert_eq!(request.input.input(), Some(&payload.data));
+
+        // Plain transfers keep their data as given
+        let transfer = Transaction {
+            to: recipient.to_string(),
+            value: "0x10".to_string(),
+            contract_call: None,
+            data: "0xdeadbeef".to_string(),
+            description: "Tip".to_string(),
+            gas_estimate: None,
+        };
+        let payload = transfer.to_payload(&[]).unwrap();
+        assert_eq!(payload.value, U256::from(16));
+        assert_eq!(payload.data, Bytes::from(vec![0xde, 0xad, 0xbe, 0xef]));
+
+        // Contract calls need the contract's ABI
+        assert!(tx.to_payload(&[]).is_err());
+    }
+
+    #[test]
+    fn test_validate_transaction() {
+        let vault = Contract::new(
+            "Vault",
+            "0x1234567890123456789012345678901234567890",
+            r#"[
+                {"name": "deposit", "type": "function", "stateMutability": "payable",
+                 "inputs": [{"name": "receiver", "type": "address"}], "outputs": []},
+                {"name": "withdraw", "type": "function", "stateMutability": "nonpayable",
+                 "inputs": [{"name": "amount", "type": "uint256"}], "outputs": []}
+            ]"#,
+        );
+        let contracts = vec![vault.clone()];
+        let call = |function: &str, args: Vec<serde_json::Value>, value: &str| Transaction {
+            to: vault.address.clone(),
+            value: value.to_string(),
+            contract_call: Some(ContractCall {
+                function: function.to_string(),
+                args,
+            }),
+            data: "0x".to_string(),
+            description: String::new(),
+            gas_estimate: None,
+        };
+        let receiver = json!("0x0987654321098765432109876543210987654321");
+        let validate = |tx: &Transaction| Transaction::validate_transaction(tx, &contracts);
+
+        assert!(validate(&call("deposit", vec![receiver.clone()], "1000")).is_ok());
+        assert!(validate(&call("withdraw", vec![json!("5")], "0")).is_ok());
+
+        let cases = [
+            (call("withdraw", vec![json!("5")], "1"), "not payable"),
+            (call("withdraw", vec![], "0"), "expects 1 arguments"),
+            (call("withdraw", vec![json!("five")], "0"), "Invalid number"),
+            (call("borrow", vec![], "0"), "not found in ABI"),
+            (call("withdraw", vec![json!("5")], "lots"), "Invalid value"),
+        ];
+        for (tx, expected) in cases {
+            let err = validate(&tx).unwrap_err().to_string();
+            assert!(
+                err.contains(expected),
+                "{} should contain {}",
+                err,
+                expected
+            );
+        }
+
+        // The target must be a configured contract
+        let elsewhere = Transaction {
+            to: "0x0000000000000000000000000000000000000001".to_string(),
+            ..call("withdraw", vec![json!("5")], "0")
+        };
+        let err = validate(&elsewhere).unwrap_err().to_string();
+        assert!(err.contains("not one of the configured contracts"));
+
+        // Encoded data must match the call, or decode as one when given alone
+        let mut encoded = call("withdraw", vec![json!("5")], "0");
+        encoded.data = encoded.calldata(&contracts).unwrap().to_string();
+        assert!(validate(&encoded).is_ok());
+        encoded.contract_call = None;
+        assert!(validate(&encoded).is_ok());
+        encoded.value = "1".to_string();
+        assert!(validate(&encoded).is_err());
+        encoded.data = "0xdeadbeef".to_string();
+        assert!(validate(&encoded).is_err());
+
+        // Plain transfers go anywhere
+        let transfer = Transaction {
+            contract_call: None,
+            ..elsewhere
+        };
+        assert!(validate(&transfer).is_ok());
+    }
 }
@@ -0,0 +1,288 @@
+//! Embeddings
+//!
+//! [`LLMClient::embed`] turns texts into vectors with the client's provider:
+//! Ollama's `/api/embed` or an OpenAI-compatible `/v1/embeddings`. Inputs are
+//! sent in batches, every batch is retried under the client's
+//! [`RetryPolicy`], and the vectors come back in input order with their
+//! dimension checked. See [`crate::vector`] for comparing them.
+
+use crate::client::LLMClient;
+use crate::errors::LlmError;
+use crate::retry::RetryPolicy;
+use std::borrow::Cow;
+use std::future::Future;
+use wstd::runtime::block_on;
+
+/// Inputs sent per request unless [`EmbeddingRequest::with_batch_size`] says otherwise
+pub const DEFAULT_EMBEDDING_BATCH_SIZE: usize = 32;
+
+/// Vectors for one batch and the prompt tokens it used
+type BatchResult = Result<(Vec<Vec<f32>>, Option<u32>), LlmError>;
+
+/// Vectors for a list of inputs
+#[derive(Debug, Clone, PartialEq)]
+pub struct Embeddings {
+    /// Model that produced the vectors
+    pub model: String,
+    /// One vector per input, in input order
+    pub vectors: Vec<Vec<f32>>,
+    /// Length of every vector
+    pub dimensions: usize,
+    /// Prompt tokens across all batches, if the provider reported them
+    pub prompt_tokens: Option<u32>,
+}
+
+impl Embeddings {
+    /// Number of vectors
+    pub fn len(&self) -> usize {
+        self.vectors.len()
+    }
+
+    /// Whether there are no vectors
+    pub fn is_empty(&self) -> bool {
+        self.vectors.is_empty()
+    }
+}
+
+/// Builder for embedding requests
+pub struct EmbeddingRequest<'a> {
+    client: Cow<'a, LLMClient>,
+    model: String,
+    inputs: Vec<String>,
+    dimensions: Option<u32>,
+    batch_size: usize,
+    retry: RetryPolicy,
+}
+
+impl<'a> EmbeddingRequest<'a> {
+    pub(crate) fn new(client: &'a LLMClient, inputs: Vec<String>) -> Self {
+        Self {
+            client: Cow::Borrowed(client),
+            model: client.get_model().to_string(),
+            inputs,
+            dimensions: None,
+            batch_size: DEFAULT_EMBEDDING_BATCH_SIZE,
+            retry: client.get_retry_policy().clone(),
+        }
+    }
+
+    /// Use this embedding model instead of the client's model
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = model.into();
+        self
+    }
+
+    /// Ask for vectors of this length, for models that can shorten them
+    ///
+    /// The response is rejected if the model returns another length.
+    pub fn with_dimensions(mut self, dimensions: u32) -> Self {
+        self.dimensions = Some(dimensions);
+        self
+    }
+
+    /// Send at most this many inputs per request (at least 1)
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Set the number of retries per batch, keeping the rest of the retry policy
+    pub fn with_retries(mut self, retries: u32) -> Self {
+        self.retry.max_retries = retries;
+        self
+    }
+
+    /// Retry failed batches according to `policy` instead of the client's
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Embed every input
+    pub fn send(self) -> Result<Embeddings, LlmError> {
+        let request = EmbeddingRequest {
+            client: Cow::Owned(self.client.into_owned()),
+            ..self
+        };
+        block_on(request.send_async())
+    }
+
+    /// Async version of [`EmbeddingRequest::send`]
+    pub async fn send_async(self) -> Result<Embeddings, LlmError> {
+        let client = &*self.client;
+        let provider = &client.get_provider();
+        let url = &provider.embeddings_url()?;
+        let (model, dimensions, retry) = (&self.model, self.dimensions, &self.retry);
+
+        self.collect(|batch| {
+            let body = provider.embeddings_body(model, batch, dimensions, client.get_config());
+            async move {
+                let body = &body;
+                retry
+                    .run_async(|| async move {
+                        let response = client.post_json(provider, url, body).await?;
+                        provider.parse_embeddings_response(&response)
+                    })
+                    .await
+            }
+        })
+        .await
+    }
+
+    /// Embed the inputs batch by batch with `embed_batch` and check the results
+    async fn collect<F>(
+        &self,
+        mut embed_batch: impl FnMut(&[String]) -> F,
+    ) -> Result<Embeddings, LlmError>
+    where
+        F: Future<Output = BatchResult>,
+    {
+        if self.inputs.is_empty() {
+            return Err(LlmError::InvalidInput(
+                "Nothing to embed: inputs are empty".to_string(),
+            ));
+        }
+
+        let mut vectors = Vec::with_capacity(self.inputs.len());
+        let mut prompt_tokens = None;
+        for batch in self.inputs.chunks(self.batch_size) {
+            let (batch_vectors, tokens) = embed_batch(batch).await?;
+            if batch_vectors.len() != batch.len() {
+                return Err(LlmError::ParseError(format!(
+                    "Expected {} embeddings, got {}",
+                    batch.len(),
+                    batch_vectors.len()
+                )));
+            }
+            if let Some(tokens) = tokens {
+                prompt_tokens = Some(prompt_tokens.unwrap_or(0) + tokens);
+            }
+            vectors.extend(batch_vectors);
+        }
+
+        let dimensions = vectors[0].len();
+        if let Some(i) = vectors.iter().position(|v| v.len() != dimensions) {
+            return Err(LlmError::ParseError(format!(
+                "Embedding {} has {} dimensions, the first has {}",
+                i,
+                vectors[i].len(),
+                dimensions
+            )));
+        }
+        if let Some(expected) = self.dimensions {
+            if dimensions != expected as usize {
+                return Err(LlmError::ParseError(format!(
+                    "Model {} returned {} dimensions, expected {}",
+                    self.model, dimensions, expected
+                )));
+            }
+        }
+
+        Ok(Embeddings {
+            model: self.model.clone(),
+            vectors,
+            dimensions,
+            prompt_tokens,
+        })
+    }
+}
+
+impl LLMClient {
+    /// Embed texts with this client's provider and model
+    ///
+    /// # Example
+    /// ```no_run
+    /// use wavs_llm::LLMClient;
+    ///
+    /// let client = LLMClient::new("nomic-embed-text");
+    /// let embeddings = client.embed(["first text", "second text"]).send()?;
+    /// assert_eq!(embeddings.len(), 2);
+    /// # Ok::<(), wavs_llm::LlmError>(())
+    /// ```
+    pub fn embed<S: Into<String>>(
+        &self,
+        inputs: impl IntoIterator<Item = S>,
+    ) -> EmbeddingRequest<'_> {
+        EmbeddingRequest::new(self, inputs.into_iter().map(Into::into).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    /// Run `collect` with a batch function that answers immediately
+    fn collect(
+        request: EmbeddingRequest<'_>,
+        mut embed_batch: impl FnMut(&[String]) -> BatchResult,
+    ) -> Result<Embeddings, LlmError> {
+        block_on(request.collect(|batch| std::future::ready(embed_batch(batch))))
+    }
+
+    /// Embeds each input as `[len, batch number]` and records batch sizes
+    fn fake_batches(batches: &mut Vec<usize>) -> impl FnMut(&[String]) -> BatchResult + '_ {
+        move |batch| {
+            batches.push(batch.len());
+            let n = batches.len() as f32;
+            let vectors = batch.iter().map(|s| vec![s.len() as f32, n]).collect();
+            Ok((vectors, Some(batch.len() as u32)))
+        }
+    }
+
+    #[test]
+    fn test_batching() {
+        let client = LLMClient::new("nomic-embed-text");
+        let inputs = ["a", "bb", "ccc", "dddd", "eeeee"];
+        let mut batches = Vec::new();
+
+        let embeddings = collect(
+            client.embed(inputs).with_batch_size(2),
+            fake_batches(&mut batches),
+        )
+        .unwrap();
+        assert_eq!(batches, [2, 2, 1]);
+        assert_eq!(embeddings.model, "nomic-embed-text");
+        assert_eq!(embeddings.dimensions, 2);
+        assert_eq!(embeddings.prompt_tokens, Some(5));
+        assert_eq!(
+            embeddings.vectors,
+            [[1.0, 1.0], [2.0, 1.0], [3.0, 2.0], [4.0, 2.0], [5.0, 3.0]]
+        );
+    }
+
+    #[test]
+    fn test_rejects_bad_responses() {
+        let client = LLMClient::new("nomic-embed-text");
+
+        let err = collect(client.embed(Vec::<String>::new()), |_| unreachable!()).unwrap_err();
+        assert!(matches!(err, LlmError::InvalidInput(_)));
+
+        let err = collect(client.embed(["a", "b"]), |_| Ok((vec![vec![0.0]], None))).unwrap_err();
+        assert!(err.to_string().contains("Expected 2 embeddings, got 1"));
+
+        let err = collect(client.embed(["a", "b"]), |_| {
+            Ok((vec![vec![0.0, 1.0], vec![0.0]], None))
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("Embedding 1 has 1 dimensions"));
+
+        let err = collect(client.embed(["a"]).with_dimensions(256), |_| {
+            Ok((vec![vec![0.0; 768]], None))
+        })
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("returned 768 dimensions, expected 256"));
+    }
+
+    #[test]
+    fn test_unsupported_provider() {
+        let client =
+            LLMClient::new("claude").with_provider(crate::providers::Provider::anthropic("key"));
+        assert!(matches!(
+            client.embed(["a"]).send(),
+            Err(LlmError::ConfigError(_))
+        ));
+    }
+}
@@ -1,14 +1,25 @@
+//! Conversions between model JSON, Solidity values and calldata
+//!
+//! Matches over [`DynSolType`] and [`DynSolValue`] end in a catch-all arm
+//! marked `#[allow(unreachable_patterns)]`. alloy only adds its
+//! `CustomStruct` variants when the `eip712` feature is enabled, which
+//! another crate in the dependency graph may do; without it the arm is
+//! unreachable.
+
 use crate::errors::{AgentError, LlmError};
-use alloy_dyn_abi::{DynSolType, DynSolValue};
-use alloy_json_abi::Function;
-use alloy_primitives::{Address, FixedBytes, U256};
+use alloy_dyn_abi::{DynSolType, DynSolValue, FunctionExt, JsonAbiExt, Specifier};
+use alloy_json_abi::{Function, Param};
+use alloy_primitives::{Address, FixedBytes, I256, U256};
 use base64::{engine::general_purpose::STANDARD, Engine};
 use hex;
+use serde_json::{json, Value};
 use std::fs;
 use std::path::Path;
 use std::str::FromStr;
 
 /// Encode an image file to base64 for use with Ollama
+///
+/// To send images to any provider, attach an [`crate::image::Image`] to a message.
 pub fn encode_image_to_base64(image_path: &str) -> Result<String, LlmError> {
     // Check if the file exists
     let path = Path::new(image_path);
@@ -29,7 +40,12 @@ pub fn encode_image_to_base64(image_path: &str) -> Result<String, LlmError> {
     Ok(encoded)
 }
 
-/// Convert a string to a DynSolValue based on the type
+/// Convert a JSON value to a DynSolValue of the given type
+///
+/// Integers may be JSON numbers or decimal/`0x` hex strings (use strings for
+/// anything beyond 2^53), bytes are `0x` hex strings, and arrays and tuples
+/// are JSON arrays. Tuples given as objects need the field names from the
+/// ABI; use [`param_to_sol_value`] for those.
 pub fn json_to_sol_value(
     value: &serde_json::Value,
     ty: &DynSolType,
@@ -45,14 +61,29 @@ pub fn json_to_sol_value(
             Ok(DynSolValue::Address(address))
         }
         DynSolType::Uint(bits) => {
-            // Convert string number to DynSolValue::Uint
-            let num_str = value
-                .as_str()
-                .ok_or(AgentError::Contract("Number must be a string".to_string()))?;
-            let num = U256::from_str(num_str)
+            let num_str = integer_string(value)?;
+            let num = U256::from_str(&num_str)
                 .map_err(|_| AgentError::Contract(format!("Invalid number: {}", num_str)))?;
+            if num.bit_len() > *bits {
+                return Err(AgentError::Contract(format!(
+                    "{} does not fit in uint{}",
+                    num_str, bits
+                )));
+            }
             Ok(DynSolValue::Uint(num, *bits))
         }
+        DynSolType::Int(bits) => {
+            let num_str = integer_string(value)?;
+            let num = I256::from_str(&num_str)
+                .map_err(|_| AgentError::Contract(format!("Invalid number: {}", num_str)))?;
+            if num.bits() as usize > *bits {
+                return Err(AgentError::Contract(format!(
+                    "{} does not fit in int{}",
+                    num_str, bits
+                )));
+            }
+            Ok(DynSolValue::Int(num, *bits))
+        }
         DynSolType::Bool => {
             // Convert JSON boolean to DynSolValue::Bool
             let bool_val = value
@@ -67,125 +98,475 @@ pub fn json_to_sol_value(
                 .ok_or(AgentError::Contract("Expected a string value".to_string()))?;
             Ok(DynSolValue::String(string_val.to_string()))
         }
-        DynSolType::Bytes => {
-            // Convert hex string to DynSolValue::Bytes
-            let bytes_str = value.as_str().ok_or(AgentError::Contract(
-                "Bytes must be a hex string".to_string(),
-            ))?;
-            if !bytes_str.starts_with("0x") {
-                return Err(AgentError::Contract("Bytes must start with 0x".to_string()));
-            }
-            let hex_str = &bytes_str[2..];
-            let bytes = hex::decode(hex_str)
-                .map_err(|_| AgentError::Contract("Invalid hex string".to_string()))?;
-            Ok(DynSolValue::Bytes(bytes))
-        }
+        DynSolType::Bytes => Ok(DynSolValue::Bytes(hex_bytes(value)?)),
         DynSolType::FixedBytes(size) => {
-            // Convert hex string to fixed-size bytes
-            let bytes_str = value.as_str().ok_or(AgentError::Contract(
-                "Bytes must be a hex string".to_string(),
-            ))?;
-            if !bytes_str.starts_with("0x") {
-                return Err(AgentError::Contract("Bytes must start with 0x".to_string()));
+            let bytes = hex_bytes(value)?;
+            if bytes.len() != *size {
+                return Err(AgentError::Contract(format!(
+                    "bytes{} needs exactly {} bytes, got {}",
+                    size,
+                    size,
+                    bytes.len()
+                )));
             }
-            let hex_str = &bytes_str[2..];
-            let bytes = hex::decode(hex_str)
-                .map_err(|_| AgentError::Contract("Invalid hex string".to_string()))?;
 
-            if bytes.len() > *size {
+            // bytesN values are left-aligned in their word
+            let mut word = [0u8; 32];
+            word[..bytes.len()].copy_from_slice(&bytes);
+            Ok(DynSolValue::FixedBytes(FixedBytes::from(word), *size))
+        }
+        DynSolType::Function => {
+            let bytes = hex_bytes(value)?;
+            let function: [u8; 24] = bytes.try_into().map_err(|_| {
+                AgentError::Contract(
+                    "Function must be 24 bytes: an address followed by a selector".to_string(),
+                )
+            })?;
+            Ok(DynSolValue::Function(function.into()))
+        }
+        DynSolType::Array(inner) => {
+            let items = json_array(value, ty)?;
+            Ok(DynSolValue::Array(json_items(items, inner)?))
+        }
+        DynSolType::FixedArray(inner, len) => {
+            let items = json_array(value, ty)?;
+            if items.len() != *len {
+                return Err(AgentError::Contract(format!(
+                    "Expected {} items for {}, got {}",
+                    len,
+                    ty,
+                    items.len()
+                )));
+            }
+            Ok(DynSolValue::FixedArray(json_items(items, inner)?))
+        }
+        DynSolType::Tuple(types) => {
+            let items = json_array(value, ty)?;
+            if items.len() != types.len() {
                 return Err(AgentError::Contract(format!(
-                    "Hex string too long for bytes{}",
-                    size
+                    "Expected {} fields for {}, got {}",
+                    types.len(),
+                    ty,
+                    items.len()
                 )));
             }
+            let values = items
+                .iter()
+                .zip(types)
+                .enumerate()
+                .map(|(i, (item, ty))| {
+                    json_to_sol_value(item, ty)
+                        .map_err(|e| AgentError::Contract(format!("field {}: {}", i, e)))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(DynSolValue::Tuple(values))
+        }
+        // `eip712` variants; see the module docs
+        #[allow(unreachable_patterns)]
+        _ => Err(AgentError::Contract(format!("Unsupported type: {}", ty))),
+    }
+}
 
-            // For bytes32, create a FixedBytes<32>
-            if *size == 32 {
-                let mut fixed = [0u8; 32];
-                let start = 32 - bytes.len();
-                fixed[start..].copy_from_slice(&bytes);
-                Ok(DynSolValue::FixedBytes(FixedBytes::from(fixed), 32))
+/// Convert a JSON value to a DynSolValue for an ABI parameter
+///
+/// Unlike [`json_to_sol_value`] this knows the component names, so structs
+/// may be given as JSON objects keyed by field name, at any nesting depth.
+pub fn param_to_sol_value(
+    value: &serde_json::Value,
+    param: &Param,
+) -> Result<DynSolValue, AgentError> {
+    let ty = param.resolve().map_err(|e| {
+        AgentError::Contract(format!("Invalid parameter type '{}': {}", param.ty, e))
+    })?;
+    let positional = positional_structs(value, &ty, &param.components)?;
+    json_to_sol_value(&positional, &ty)
+}
+
+/// JSON Schema describing the JSON [`param_to_sol_value`] accepts for a parameter
+///
+/// Structs become objects keyed by component name (or positional arrays when
+/// the ABI leaves components unnamed), arrays carry their element schema and
+/// length, and integers, addresses and bytes are strings constrained by
+/// patterns. `description` is attached to the top-level schema.
+pub fn param_schema(param: &Param, description: Option<&str>) -> Result<Value, AgentError> {
+    let ty = param.resolve().map_err(|e| {
+        AgentError::Contract(format!("Invalid parameter type '{}': {}", param.ty, e))
+    })?;
+    let mut schema = sol_type_schema(&ty, &param.components);
+    if let Some(description) = description {
+        schema["description"] = json!(description);
+    }
+    Ok(schema)
+}
+
+/// JSON Schema for a Solidity type; `components` names the fields of struct types
+pub fn sol_type_schema(ty: &DynSolType, components: &[Param]) -> Value {
+    match ty {
+        DynSolType::Address => json!({
+            "type": "string",
+            "format": "ethereum-address",
+            "pattern": "^0x[0-9a-fA-F]{40}$",
+            "minLength": 42,
+            "maxLength": 42
+        }),
+        DynSolType::Uint(bits) => {
+            let max = U256::MAX >> (256 - bits);
+            json!({
+                "type": "string",
+                "description": format!("uint{} as a decimal or 0x-prefixed hex string", bits),
+                "pattern": format!(
+                    "^(0x[0-9a-fA-F]{{1,{}}}|[0-9]{{1,{}}})$",
+                    bits.div_ceil(4),
+                    max.to_string().len()
+                )
+            })
+        }
+        DynSolType::Int(bits) => {
+            // The most negative value has the most digits
+            let min = U256::from(1) << (bits - 1);
+            json!({
+                "type": "string",
+                "description": format!("int{} as a decimal or 0x-prefixed hex string", bits),
+                "pattern": format!(
+                    "^-?(0x[0-9a-fA-F]{{1,{}}}|[0-9]{{1,{}}})$",
+                    bits.div_ceil(4),
+                    min.to_string().len()
+                )
+            })
+        }
+        DynSolType::Bool => json!({"type": "boolean"}),
+        DynSolType::String => json!({"type": "string"}),
+        DynSolType::Bytes => json!({
+            "type": "string",
+            "format": "hex",
+            "pattern": "^0x([0-9a-fA-F]{2})*$"
+        }),
+        DynSolType::FixedBytes(size) => fixed_hex_schema(*size),
+        DynSolType::Function => fixed_hex_schema(24),
+        DynSolType::Array(inner) => json!({
+            "type": "array",
+            "items": sol_type_schema(inner, components)
+        }),
+        DynSolType::FixedArray(inner, len) => json!({
+            "type": "array",
+            "items": sol_type_schema(inner, components),
+            "minItems": len,
+            "maxItems": len
+        }),
+        DynSolType::Tuple(types) => {
+            let named =
+                components.len() == types.len() && components.iter().all(|c| !c.name.is_empty());
+            if named {
+                let properties: serde_json::Map<String, Value> = components
+                    .iter()
+                    .zip(types)
+                    .map(|(component, ty)| {
+                        let mut schema = sol_type_schema(ty, &component.components);
+                        schema["description"] = json!(format!(
+                            "{} ({})",
+                            component.name,
+                            component.selector_type()
+                        ));
+                        (component.name.clone(), schema)
+                    })
+                    .collect();
+                let required: Vec<&str> = components.iter().map(|c| c.name.as_str()).collect();
+                json!({
+                    "type": "object",
+                    "properties": properties,
+                    "required": required,
+                    "additionalProperties": false
+                })
             } else {
-                // For other sizes, use regular bytes
-                Ok(DynSolValue::Bytes(bytes))
+                let items: Vec<Value> = types
+                    .iter()
+                    .enumerate()
+                    .map(|(i, ty)| {
+                        let nested = components.get(i).map(|c| c.components.as_slice());
+                        sol_type_schema(ty, nested.unwrap_or_default())
+                    })
+                    .collect();
+                json!({
+                    "type": "array",
+                    "prefixItems": items,
+                    "minItems": types.len(),
+                    "maxItems": types.len()
+                })
             }
         }
-        // Add handling for other types as needed
-        _ => Err(AgentError::Contract(format!("Unsupported type: {:?}", ty))),
+        // `eip712` variants; see the module docs
+        #[allow(unreachable_patterns)]
+        _ => json!({}),
+    }
+}
+
+fn fixed_hex_schema(size: usize) -> Value {
+    json!({
+        "type": "string",
+        "format": "hex",
+        "pattern": format!("^0x[0-9a-fA-F]{{{}}}$", size * 2),
+        "minLength": 2 + size * 2,
+        "maxLength": 2 + size * 2
+    })
+}
+
+/// Convert a DynSolValue back to the JSON accepted by [`json_to_sol_value`]
+///
+/// Integers become decimal strings so no precision is lost, addresses are
+/// checksummed, and bytes are `0x` hex of their exact length.
+pub fn sol_value_to_json(value: &DynSolValue) -> serde_json::Value {
+    match value {
+        DynSolValue::Bool(b) => Value::Bool(*b),
+        DynSolValue::Int(num, _) => Value::String(num.to_string()),
+        DynSolValue::Uint(num, _) => Value::String(num.to_string()),
+        DynSolValue::FixedBytes(word, size) => {
+            Value::String(format!("0x{}", hex::encode(&word[..*size])))
+        }
+        DynSolValue::Address(address) => Value::String(address.to_checksum(None)),
+        DynSolValue::Function(function) => Value::String(format!("0x{}", hex::encode(function))),
+        DynSolValue::Bytes(bytes) => Value::String(format!("0x{}", hex::encode(bytes))),
+        DynSolValue::String(s) => Value::String(s.clone()),
+        DynSolValue::Array(items) | DynSolValue::FixedArray(items) | DynSolValue::Tuple(items) => {
+            Value::Array(items.iter().map(sol_value_to_json).collect())
+        }
+        // `eip712` variants; see the module docs
+        #[allow(unreachable_patterns)]
+        other => match other.as_fixed_seq() {
+            Some(items) => Value::Array(items.iter().map(sol_value_to_json).collect()),
+            None => Value::Null,
+        },
     }
 }
 
-/// Encode function arguments using Alloy's built-in functionality
+/// Integer input as a string `U256`/`I256` can parse
+fn integer_string(value: &serde_json::Value) -> Result<String, AgentError> {
+    match value {
+        serde_json::Value::String(s) => Ok(s.trim().to_string()),
+        serde_json::Value::Number(n) if n.is_u64() || n.is_i64() => Ok(n.to_string()),
+        serde_json::Value::Number(n) => Err(AgentError::Contract(format!(
+            "Invalid integer: {} (pass large numbers as strings)",
+            n
+        ))),
+        _ => Err(AgentError::Contract(
+            "Number must be a string or an integer".to_string(),
+        )),
+    }
+}
+
+/// Decode a `0x`-prefixed hex string
+fn hex_bytes(value: &serde_json::Value) -> Result<Vec<u8>, AgentError> {
+    let bytes_str = value.as_str().ok_or(AgentError::Contract(
+        "Bytes must be a hex string".to_string(),
+    ))?;
+    let hex_str = bytes_str
+        .strip_prefix("0x")
+        .ok_or_else(|| AgentError::Contract("Bytes must start with 0x".to_string()))?;
+    hex::decode(hex_str).map_err(|_| AgentError::Contract("Invalid hex string".to_string()))
+}
+
+fn json_array<'v>(
+    value: &'v serde_json::Value,
+    ty: &DynSolType,
+) -> Result<&'v Vec<serde_json::Value>, AgentError> {
+    value
+        .as_array()
+        .ok_or_else(|| AgentError::Contract(format!("Expected a JSON array for {}", ty)))
+}
+
+fn json_items(
+    items: &[serde_json::Value],
+    ty: &DynSolType,
+) -> Result<Vec<DynSolValue>, AgentError> {
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| {
+            json_to_sol_value(item, ty).map_err(|e| AgentError::Contract(format!("[{}]: {}", i, e)))
+        })
+        .collect()
+}
+
+/// Rewrite struct objects as positional arrays using the ABI component names
+fn positional_structs(
+    value: &serde_json::Value,
+    ty: &DynSolType,
+    components: &[Param],
+) -> Result<serde_json::Value, AgentError> {
+    match (ty, value) {
+        (DynSolType::Tuple(types), Value::Object(fields)) => {
+            if components.len() != types.len() {
+                return Err(AgentError::Contract(format!(
+                    "Named fields need ABI components for {}",
+                    ty
+                )));
+            }
+            let items = components
+                .iter()
+                .zip(types)
+                .map(|(component, ty)| {
+                    let field = fields.get(&component.name).ok_or_else(|| {
+                        AgentError::Contract(format!("Missing struct field '{}'", component.name))
+                    })?;
+                    positional_structs(field, ty, &component.components)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Value::Array(items))
+        }
+        (DynSolType::Tuple(types), Value::Array(items)) => {
+            let items = items
+                .iter()
+                .zip(types)
+                .enumerate()
+                .map(|(i, (item, ty))| {
+                    let nested = components.get(i).map(|c| c.components.as_slice());
+                    positional_structs(item, ty, nested.unwrap_or_default())
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Value::Array(items))
+        }
+        (DynSolType::Array(inner) | DynSolType::FixedArray(inner, _), Value::Array(items)) => {
+            let items = items
+                .iter()
+                .map(|item| positional_structs(item, inner, components))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(Value::Array(items))
+        }
+        _ => Ok(value.clone()),
+    }
+}
+
+/// ABI-encode function arguments (without the selector)
 pub fn encode_function_args(
     function: &Function,
     args: &[serde_json::Value],
 ) -> Result<Vec<u8>, AgentError> {
-    // If there are no arguments, return an empty vector
-    if args.is_empty() {
-        return Ok(Vec::new());
+    if args.len() != function.inputs.len() {
+        return Err(AgentError::Contract(format!(
+            "Function {} expects {} arguments, got {}",
+            function.name,
+            function.inputs.len(),
+            args.len()
+        )));
     }
 
-    // Parse each parameter's type
-    let param_types: Vec<DynSolType> = function
-        .inputs
+    // Convert each JSON value to a DynSolValue
+    let values = args
         .iter()
-        .map(|param| {
-            DynSolType::parse(&param.ty).map_err(|e| {
-                AgentError::Contract(format!("Invalid parameter type '{}': {}", param.ty, e))
+        .zip(&function.inputs)
+        .enumerate()
+        .map(|(i, (arg, param))| {
+            param_to_sol_value(arg, param).map_err(|e| {
+                AgentError::Contract(format!("Error converting argument {}: {}", i, e))
             })
         })
         .collect::<Result<Vec<_>, _>>()?;
 
-    // Convert each JSON value to a DynSolValue
-    let mut values = Vec::with_capacity(args.len());
+    function
+        .abi_encode_input_raw(&values)
+        .map_err(|e| AgentError::Contract(format!("Failed to encode arguments: {}", e)))
+}
 
-    for (i, (arg, ty)) in args.iter().zip(&param_types).enumerate() {
-        match json_to_sol_value(arg, ty) {
-            Ok(value) => values.push(value),
-            Err(e) => {
-                return Err(AgentError::Contract(format!(
-                    "Error converting argument {}: {}",
-                    i, e
-                )))
-            }
-        }
+/// Name of a function input as it appears in tool arguments
+///
+/// Unnamed inputs are addressed by position: `arg0`, `arg1`, ...
+pub fn argument_name(param: &Param, index: usize) -> String {
+    if param.name.is_empty() {
+        format!("arg{}", index)
+    } else {
+        param.name.clone()
     }
+}
+
+/// Order a JSON object of named arguments by the function's inputs
+///
+/// Keys are the [`argument_name`]s; keys that are not inputs (such as the
+/// `value` of a payable call) are ignored.
+pub fn named_function_args(function: &Function, args: &Value) -> Result<Vec<Value>, AgentError> {
+    let object = match args {
+        Value::Object(object) => object,
+        // A call without arguments may come through as null
+        Value::Null if function.inputs.is_empty() => return Ok(Vec::new()),
+        _ => {
+            return Err(AgentError::Contract(format!(
+                "Arguments for {} must be a JSON object",
+                function.name
+            )))
+        }
+    };
+
+    function
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(i, param)| {
+            let name = argument_name(param, i);
+            object.get(&name).cloned().ok_or_else(|| {
+                AgentError::Contract(format!("Missing argument '{}' for {}", name, function.name))
+            })
+        })
+        .collect()
+}
+
+/// Decode a function's return data into JSON
+///
+/// A single unnamed output is returned as its value; otherwise the result is
+/// an object keyed by output name (`output0`, ... for unnamed ones). Structs
+/// become objects keyed by component name.
+pub fn decode_function_output(function: &Function, data: &[u8]) -> Result<Value, AgentError> {
+    let values = function.abi_decode_output(data).map_err(|e| {
+        AgentError::Contract(format!("Failed to decode {} output: {}", function.name, e))
+    })?;
 
-    // Manually encode according to the ABI specification
-    // First, encode head and tail parts
-    let mut head = Vec::new();
-    let mut tail = Vec::new();
-
-    for (i, (value, ty)) in values.iter().zip(&param_types).enumerate() {
-        if is_dynamic_type(ty) {
-            // For dynamic types, the head contains the offset to the data
-            let offset = head.len() + (values.len() - i) * 32; // Calculate offset
-            head.extend_from_slice(&U256::from(offset).to_be_bytes::<32>());
-
-            // The tail contains the actual data
-            let encoded = value.abi_encode();
-            tail.extend_from_slice(&encoded);
-        } else {
-            // For static types, encode directly in the head
-            let encoded = value.abi_encode();
-            head.extend_from_slice(&encoded);
+    if let ([output], [value]) = (function.outputs.as_slice(), values.as_slice()) {
+        if output.name.is_empty() {
+            return Ok(param_value_to_json(value, &output.components));
         }
     }
 
-    // Combine head and tail
-    let mut result = Vec::new();
-    result.extend_from_slice(&head);
-    result.extend_from_slice(&tail);
+    let fields: serde_json::Map<String, Value> = function
+        .outputs
+        .iter()
+        .zip(&values)
+        .enumerate()
+        .map(|(i, (output, value))| {
+            let name = if output.name.is_empty() {
+                format!("output{}", i)
+            } else {
+                output.name.clone()
+            };
+            (name, param_value_to_json(value, &output.components))
+        })
+        .collect();
+    Ok(Value::Object(fields))
+}
 
-    Ok(result)
+/// Like [`sol_value_to_json`], but structs with named components become objects
+fn param_value_to_json(value: &DynSolValue, components: &[Param]) -> Value {
+    match value {
+        DynSolValue::Tuple(items)
+            if items.len() == components.len() && components.iter().all(|c| !c.name.is_empty()) =>
+        {
+            Value::Object(
+                components
+                    .iter()
+                    .zip(items)
+                    .map(|(c, item)| (c.name.clone(), param_value_to_json(item, &c.components)))
+                    .collect(),
+            )
+        }
+        DynSolValue::Array(items) | DynSolValue::FixedArray(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| param_value_to_json(item, components))
+                .collect(),
+        ),
+        other => sol_value_to_json(other),
+    }
 }
 
 /// Check if a type is dynamic according to ABI spec
 pub fn is_dynamic_type(ty: &DynSolType) -> bool {
-    matches!(
-        ty,
-        DynSolType::String | DynSolType::Bytes | DynSolType::Array(_) | DynSolType::Tuple(_)
-    )
+    ty.is_dynamic()
 }
 
 #[cfg(test)]
@@ -249,13 +630,226 @@ mod tests {
         ))));
         assert!(is_dynamic_type(&DynSolType::Tuple(vec![
             DynSolType::Uint(256),
-            DynSolType::Address
+            DynSolType::String
         ])));
 
         // Test static types
+        assert!(!is_dynamic_type(&DynSolType::Tuple(vec![
+            DynSolType::Uint(256),
+            DynSolType::Address
+        ])));
+        assert!(!is_dynamic_type(&DynSolType::FixedArray(
+            Box::new(DynSolType::Uint(8)),
+            2
+        )));
         assert!(!is_dynamic_type(&DynSolType::Address));
         assert!(!is_dynamic_type(&DynSolType::Uint(256)));
         assert!(!is_dynamic_type(&DynSolType::Bool));
         assert!(!is_dynamic_type(&DynSolType::FixedBytes(32)));
     }
+
+    #[test]
+    fn test_integers() {
+        let int8 = DynSolType::Int(8);
+        let uint8 = DynSolType::Uint(8);
+
+        assert!(json_to_sol_value(&json!(-128), &int8).is_ok());
+        assert!(json_to_sol_value(&json!("127"), &int8).is_ok());
+        assert!(json_to_sol_value(&json!(128), &int8).is_err());
+        assert!(json_to_sol_value(&json!("-129"), &int8).is_err());
+
+        assert!(json_to_sol_value(&json!(255), &uint8).is_ok());
+        assert!(json_to_sol_value(&json!("0xff"), &uint8).is_ok());
+        assert!(json_to_sol_value(&json!(256), &uint8).is_err());
+        assert!(json_to_sol_value(&json!(-1), &uint8).is_err());
+        assert!(json_to_sol_value(&json!(1.5), &uint8).is_err());
+
+        let min = "-57896044618658097711785492504343953926634992332820282019728792003956564819968";
+        let value = json_to_sol_value(&json!(min), &DynSolType::Int(256)).unwrap();
+        assert_eq!(sol_value_to_json(&value), json!(min));
+    }
+
+    #[test]
+    fn test_fixed_bytes_sizes() {
+        let value = json_to_sol_value(&json!("0xa9059cbb"), &DynSolType::FixedBytes(4)).unwrap();
+        let DynSolValue::FixedBytes(word, 4) = &value else {
+            panic!("expected bytes4, got {:?}", value);
+        };
+        assert_eq!(&word[..4], &[0xa9, 0x05, 0x9c, 0xbb]);
+        assert!(word[4..].iter().all(|b| *b == 0));
+        assert_eq!(sol_value_to_json(&value), json!("0xa9059cbb"));
+
+        assert!(json_to_sol_value(&json!("0xa9059cbb00"), &DynSolType::FixedBytes(4)).is_err());
+        let err = json_to_sol_value(&json!("0xa905"), &DynSolType::FixedBytes(4)).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("bytes4 needs exactly 4 bytes, got 2"));
+        assert!(json_to_sol_value(&json!("a9059cbb"), &DynSolType::FixedBytes(4)).is_err());
+    }
+
+    #[test]
+    fn test_arrays_and_tuples() {
+        let ty = DynSolType::parse("(address,uint256[],(bool,bytes2)[2])[]").unwrap();
+        let json = json!([[
+            "0x1234567890123456789012345678901234567890",
+            ["1", "2"],
+            [[true, "0x0102"], [false, "0x0304"]]
+        ]]);
+        let value = json_to_sol_value(&json, &ty).unwrap();
+        assert!(value.matches(&ty));
+
+        let bad_len = json!([[
+            "0x1234567890123456789012345678901234567890",
+            [],
+            [[true, "0x0102"]]
+        ]]);
+        let err = json_to_sol_value(&bad_len, &ty).unwrap_err().to_string();
+        assert!(err.contains("Expected 2 items"), "{}", err);
+    }
+
+    #[test]
+    fn test_encode_function_args_roundtrip() {
+        let abi: alloy_json_abi::JsonAbi = serde_json::from_str(
+            r#"[{
+                "type": "function",
+                "name": "execute",
+                "stateMutability": "nonpayable",
+                "outputs": [],
+                "inputs": [
+                    {"name": "recipients", "type": "address[]"},
+                    {"name": "amounts", "type": "uint256[]"},
+                    {"name": "orders", "type": "tuple[]", "components": [
+                        {"name": "token", "type": "address"},
+                        {"name": "amount", "type": "uint96"},
+                        {"name": "selector", "type": "bytes4"}
+                    ]},
+                    {"name": "tick", "type": "int24"},
+                    {"name": "salt", "type": "bytes32"},
+                    {"name": "memo", "type": "string"},
+                    {"name": "pair", "type": "uint8[2]"}
+                ]
+            }]"#,
+        )
+        .unwrap();
+        let function = abi.function("execute").unwrap()[0].clone();
+
+        let args = vec![
+            json!([
+                "0x1234567890123456789012345678901234567890",
+                "0xabcdef0123456789abcdef0123456789abcdef01"
+            ]),
+            json!(["1000000000000000000", 5]),
+            json!([{
+                "token": "0x1234567890123456789012345678901234567890",
+                "amount": "42",
+                "selector": "0xa9059cbb"
+            }]),
+            json!(-887272),
+            json!("0x0000000000000000000000000000000000000000000000000000000000000001"),
+            json!("rebalance"),
+            json!([1, 2]),
+        ];
+
+        let encoded = encode_function_args(&function, &args).unwrap();
+        let decoded = function.abi_decode_input(&encoded).unwrap();
+        assert_eq!(function.abi_encode_input_raw(&decoded).unwrap(), encoded);
+
+        let decoded: Vec<_> = decoded.iter().map(sol_value_to_json).collect();
+        assert_eq!(
+            decoded[0][1],
+            json!("0xabCDeF0123456789AbcdEf0123456789aBCDEF01")
+        );
+        assert_eq!(decoded[1], json!(["1000000000000000000", "5"]));
+        assert_eq!(
+            decoded[2],
+            json!([[
+                "0x1234567890123456789012345678901234567890",
+                "42",
+                "0xa9059cbb"
+            ]])
+        );
+        assert_eq!(decoded[3], json!("-887272"));
+        assert_eq!(decoded[4], args[4]);
+        assert_eq!(decoded[5], args[5]);
+        assert_eq!(decoded[6], json!(["1", "2"]));
+
+        // Decoded JSON is valid input again
+        assert_eq!(encode_function_args(&function, &decoded).unwrap(), encoded);
+
+        let err = encode_function_args(&function, &args[..2]).unwrap_err();
+        assert!(err.to_string().contains("expects 7 arguments"));
+    }
+
+    #[test]
+    fn test_param_schema() {
+        let param: Param = serde_json::from_value(json!({
+            "name": "orders",
+            "type": "tuple[]",
+            "components": [
+                {"name": "maker", "type": "address"},
+                {"name": "amounts", "type": "uint128[2]"},
+                {"name": "salt", "type": "bytes32"},
+                {"name": "legs", "type": "tuple[]", "components": [
+                    {"name": "delta", "type": "int24"},
+                    {"name": "data", "type": "bytes"}
+                ]}
+            ]
+        }))
+        .unwrap();
+
+        let schema = param_schema(&param, Some("Orders to fill")).unwrap();
+        assert_eq!(schema["type"], "array");
+        assert_eq!(schema["description"], "Orders to fill");
+
+        let order = &schema["items"];
+        assert_eq!(order["type"], "object");
+        assert_eq!(
+            order["required"],
+            json!(["maker", "amounts", "salt", "legs"])
+        );
+        let fields = &order["properties"];
+        assert_eq!(fields["maker"]["format"], "ethereum-address");
+        assert_eq!(fields["amounts"]["minItems"], 2);
+        assert_eq!(
+            fields["amounts"]["items"]["pattern"],
+            "^(0x[0-9a-fA-F]{1,32}|[0-9]{1,39})$"
+        );
+        assert_eq!(fields["salt"]["pattern"], "^0x[0-9a-fA-F]{64}$");
+        let leg = &fields["legs"]["items"]["properties"];
+        assert_eq!(
+            leg["delta"]["pattern"],
+            "^-?(0x[0-9a-fA-F]{1,6}|[0-9]{1,7})$"
+        );
+        assert_eq!(leg["data"]["pattern"], "^0x([0-9a-fA-F]{2})*$");
+
+        // Anything the schema accepts encodes, and the schema rejects malformed shapes
+        let value = json!([{
+            "maker": "0x1234567890123456789012345678901234567890",
+            "amounts": ["1", "0x10"],
+            "salt": format!("0x{}", "00".repeat(32)),
+            "legs": [{"delta": "-60", "data": "0x"}]
+        }]);
+        assert!(crate::schema::validate(&value, &schema).is_ok());
+        assert!(param_to_sol_value(&value, &param).is_ok());
+
+        let short = json!([{
+            "maker": "0x1234",
+            "amounts": ["1"],
+            "salt": "0x00",
+            "legs": []
+        }]);
+        let violations = crate::schema::validate(&short, &schema).unwrap_err();
+        assert_eq!(violations.len(), 3);
+
+        // Unnamed components fall back to positional arrays
+        let pair = sol_type_schema(
+            &DynSolType::Tuple(vec![DynSolType::Bool, DynSolType::String]),
+            &[],
+        );
+        assert_eq!(
+            pair["prefixItems"],
+            json!([{"type": "boolean"}, {"type": "string"}])
+        );
+        assert_eq!(pair["maxItems"], 2);
+    }
 }
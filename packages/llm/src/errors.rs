@@ -19,10 +19,36 @@ pub enum LlmError {
     #[error("API error: {0}")]
     ApiError(String),
 
+    /// The provider answered with a non-success HTTP status
+    #[error("API returned status {status}: {body}")]
+    HttpStatus {
+        status: u16,
+        /// Wait the provider asked for in its `Retry-After` header
+        retry_after: Option<std::time::Duration>,
+        body: String,
+    },
+
     /// Parsing errors
     #[error("Parse error: {0}")]
     ParseError(String),
 
+    /// Structured output that does not match its schema
+    #[error("Validation error: {0}")]
+    ValidationError(String),
+
+    /// Samples did not agree on an answer
+    #[error("No consensus: {0}")]
+    NoConsensus(String),
+
+    /// The reply or tool run ended without what the caller asked for,
+    /// e.g. no text content or a budget ran out
+    #[error("Incomplete response: {0}")]
+    Incomplete(String),
+
+    /// Response cache errors
+    #[error("Cache error: {0}")]
+    CacheError(String),
+
     /// Image encoding errors
     #[error("Image encoding error: {0}")]
     ImageError(String),
@@ -32,6 +58,33 @@ pub enum LlmError {
     IoError(#[from] std::io::Error),
 }
 
+impl LlmError {
+    /// Whether the same request could succeed if sent again
+    ///
+    /// Transport failures, rate limits (429), timeouts (408), server errors
+    /// (5xx except 501) and API errors such as an empty provider response
+    /// are retryable. Everything caused by the request itself is not, and
+    /// neither are responses that fail to parse or validate and runs that
+    /// ended [`Incomplete`](LlmError::Incomplete).
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            LlmError::RequestError(_) | LlmError::ApiError(_) => true,
+            LlmError::HttpStatus { status, .. } => {
+                matches!(status, 408 | 425 | 429) || (*status >= 500 && *status != 501)
+            }
+            LlmError::ConfigError(_)
+            | LlmError::InvalidInput(_)
+            | LlmError::ParseError(_)
+            | LlmError::ValidationError(_)
+            | LlmError::NoConsensus(_)
+            | LlmError::Incomplete(_)
+            | LlmError::CacheError(_)
+            | LlmError::ImageError(_)
+            | LlmError::IoError(_) => false,
+        }
+    }
+}
+
 /// Error type for Agent operations
 #[derive(Error, Debug)]
 pub enum AgentError {
@@ -51,6 +104,10 @@ pub enum AgentError {
     #[error("LLM error: {0}")]
     Llm(String),
 
+    /// Prompt template errors
+    #[error("Template error: {0}")]
+    Template(String),
+
     /// Context loading-related errors
     #[error("Context loading error: {0}")]
     ContextLoading(String),
@@ -122,6 +179,7 @@ impl From<AgentError> for String {
             AgentError::Api(msg) => format!("API error: {}", msg),
             AgentError::ExternalService(msg) => format!("External service error: {}", msg),
             AgentError::Configuration(msg) => format!("Configuration error: {}", msg),
+            AgentError::Template(msg) => format!("Template error: {}", msg),
             AgentError::ContextLoading(msg) => format!("Context loading error: {}", msg),
             AgentError::ContextValidation(msg) => format!("Context validation error: {}", msg),
         }
@@ -0,0 +1,135 @@
+//! Images attached to messages
+//!
+//! Vision models take images alongside text. An [`Image`] holds the encoded
+//! bytes and their media type; each provider receives them in its own layout
+//! (Ollama's `images` array, OpenAI `image_url` content parts with a data URI,
+//! Anthropic `image` blocks).
+
+use crate::config::ipfs_gateway_url;
+use crate::errors::LlmError;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use wavs_wasi_utils::http::{fetch_bytes, http_request_get};
+use wstd::runtime::block_on;
+
+/// A base64-encoded image
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Image {
+    /// MIME type, e.g. `image/png`
+    pub media_type: String,
+    /// Base64 of the image bytes, without a data URI prefix
+    pub data: String,
+}
+
+impl Image {
+    /// Encode raw image bytes, detecting PNG, JPEG, GIF or WebP from their header
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, LlmError> {
+        let media_type = media_type(bytes).ok_or_else(|| {
+            LlmError::ImageError("Unsupported image format; use PNG, JPEG, GIF or WebP".to_string())
+        })?;
+        Ok(Self {
+            media_type: media_type.to_string(),
+            data: STANDARD.encode(bytes),
+        })
+    }
+
+    /// Use data that is already base64-encoded
+    pub fn from_base64(data: impl Into<String>, media_type: impl Into<String>) -> Self {
+        Self {
+            media_type: media_type.into(),
+            data: data.into(),
+        }
+    }
+
+    /// Read an image file
+    pub fn from_file(path: &str) -> Result<Self, LlmError> {
+        let bytes = fs::read(path).map_err(|e| {
+            LlmError::ImageError(format!("Failed to read image file {}: {}", path, e))
+        })?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Fetch an image from IPFS through the gateway in `WAVS_ENV_IPFS_GATEWAY_URL`
+    ///
+    /// Accepts a bare CID or an `ipfs://` URI.
+    pub fn from_ipfs(cid: &str) -> Result<Self, LlmError> {
+        let cid = cid.to_string();
+        block_on(async move { Self::from_ipfs_async(&cid).await })
+    }
+
+    /// Async version of [`Image::from_ipfs`]
+    pub async fn from_ipfs_async(cid: &str) -> Result<Self, LlmError> {
+        if cfg!(not(target_arch = "wasm32")) {
+            return Err(LlmError::ImageError(
+                "IPFS images can only be fetched inside a WASI component".to_string(),
+            ));
+        }
+
+        let url = ipfs_gateway_url(cid.trim_start_matches("ipfs://"));
+        let request = http_request_get(&url)
+            .map_err(|e| LlmError::RequestError(format!("Failed to create request: {}", e)))?;
+        let bytes = fetch_bytes(request).await.map_err(|e| {
+            LlmError::ImageError(format!("Failed to fetch image from {}: {}", url, e))
+        })?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// `data:` URI, as OpenAI-compatible APIs expect
+    pub fn data_uri(&self) -> String {
+        format!("data:{};base64,{}", self.media_type, self.data)
+    }
+}
+
+/// Media type from an image's magic bytes
+fn media_type(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [0xff, 0xd8, 0xff, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Smallest valid PNG header prefix
+    const PNG: &[u8] = &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+    #[test]
+    fn test_from_bytes() {
+        let image = Image::from_bytes(PNG).unwrap();
+        assert_eq!(image.media_type, "image/png");
+        assert_eq!(image.data, "iVBORw0KGgo=");
+        assert_eq!(image.data_uri(), "data:image/png;base64,iVBORw0KGgo=");
+
+        let webp = b"RIFF\x24\x00\x00\x00WEBPVP8 ";
+        assert_eq!(Image::from_bytes(webp).unwrap().media_type, "image/webp");
+        assert_eq!(
+            Image::from_bytes(&[0xff, 0xd8, 0xff, 0xe0])
+                .unwrap()
+                .media_type,
+            "image/jpeg"
+        );
+        assert!(matches!(
+            Image::from_bytes(b"<svg></svg>"),
+            Err(LlmError::ImageError(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_file() {
+        let path = std::env::temp_dir().join("wavs-llm-test-image.png");
+        fs::write(&path, PNG).unwrap();
+        let image = Image::from_file(path.to_str().unwrap()).unwrap();
+        assert_eq!(image, Image::from_base64("iVBORw0KGgo=", "image/png"));
+        fs::remove_file(&path).unwrap();
+
+        assert!(Image::from_file("/no/such/image.png").is_err());
+        assert!(Image::from_ipfs("ipfs://bafkreib").is_err());
+    }
+}
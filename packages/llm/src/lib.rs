@@ -1,22 +1,94 @@
+// Lets exported bindings refer to this crate by name from inside it
+extern crate self as wavs_llm;
+
+mod bindings;
+pub mod cache;
 pub mod client;
 pub mod config;
+pub mod consensus;
 pub mod contracts;
+pub mod embeddings;
 pub mod encoding;
 pub mod errors;
+pub mod image;
+pub mod loader;
+pub mod policy;
+pub mod providers;
+pub mod reader;
+pub mod registry;
+pub mod retry;
+pub mod schema;
+pub mod simulation;
+pub mod streaming;
+pub mod template;
+pub mod tokens;
+pub mod tool_provider;
 pub mod tools;
+pub mod toolset;
 pub mod types;
+pub mod vector;
 
 // Re-export the main client and message types for easy access
-pub use client::{ChatRequest, LLMClient, LlmResponse, Message, StructuredChatRequest};
+pub use client::{
+    AgentRun, ChatRequest, LLMClient, LlmResponse, Message, StopReason, StructuredChatRequest,
+};
+
+// Re-export cache types
+pub use cache::{CacheStore, KeyValueCache, MemoryCache, ResponseCache};
 
 // Re-export configuration types
 pub use config::{Config, LlmOptions, LlmOptionsBuilder};
 
+// Re-export layered config loading
+pub use loader::ConfigLoader;
+
+// Re-export consensus types
+pub use consensus::{Consensus, ConsensusOptions};
+
 // Re-export contract types for tool integration
-pub use contracts::{Contract, ContractCall, Transaction};
+pub use contracts::{Contract, ContractCall, Transaction, TransactionPayload};
+
+// Re-export embedding types
+pub use embeddings::{EmbeddingRequest, Embeddings};
+
+// Re-export the image type for multimodal messages
+pub use image::Image;
+
+// Re-export transaction policy types
+pub use policy::{Policy, PolicyDenial, PolicyEngine, PolicyRule, PolicyViolation};
+
+// Re-export transaction simulation types
+pub use simulation::{ChainRpc, HttpRpc, Simulation, SimulationError, Simulator};
+
+// Re-export provider types
+pub use providers::{ChatCompletion, ModelCapabilities, Provider, ProviderKind, Usage};
+
+// Re-export the read-only contract call handler
+pub use reader::ContractReader;
+
+// Re-export the contract tool registry
+pub use registry::{ContractTool, ToolRegistry};
+
+// Re-export the retry policy
+pub use retry::RetryPolicy;
+
+// Re-export streaming types
+pub use streaming::{StreamControl, StreamEvent};
+
+// Re-export prompt template types
+pub use template::{Template, TemplateContext};
+
+// Re-export token accounting types
+pub use tokens::{ApproxTokenizer, ContextStrategy, Tokenizer};
 
 // Re-export error types
 pub use errors::{AgentError, LlmError};
 
+// Re-export component tool provider types
+pub use tool_provider::{ComponentToolHandler, ToolProvider};
+
+// Re-export typed tool types
+pub use toolset::{Toolset, TypedTool};
+
 // Re-export tool types
 pub use tools::{CustomToolHandler, Function, Tool, ToolCall, ToolCallFunction, Tools};
@@ -0,0 +1,456 @@
+//! Layered config loading
+//!
+//! A [`Config`] is assembled from up to four layers, each applied as a JSON
+//! merge patch (RFC 7386) over the ones before it:
+//!
+//! 1. defaults: empty `contracts` and `messages` and [`LlmOptions::default`],
+//!    or the config given to [`ConfigLoader::with_defaults`]
+//! 2. a document from an `http(s)://`, `ipfs://` or `data:` URI
+//! 3. WAVS config variables (`host::config_var`)
+//! 4. per-trigger overrides
+//!
+//! Documents are migrated to [`CONFIG_VERSION`] before they are applied. The
+//! merged result is checked against [`Config::json_schema`], so errors name
+//! the exact field (`$.contracts[0].address: ...`), and then against
+//! [`Config::validate`]. Nothing on this path panics; every failure is an
+//! [`AgentError::Configuration`].
+
+use crate::config::{ipfs_gateway_url, Config, LlmOptions, CONFIG_VERSION};
+use crate::errors::AgentError;
+use crate::schema;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{json, Value};
+use wavs_wasi_utils::http::{fetch_bytes, http_request_get};
+use wstd::http::HeaderValue;
+use wstd::runtime::block_on;
+
+/// Config variable holding the URI of the config document
+pub const CONFIG_URI_VAR: &str = "config_uri";
+
+/// Config variable holding a JSON merge patch applied over the document
+pub const CONFIG_OVERRIDES_VAR: &str = "config_overrides";
+
+/// Config variables that replace a single top-level field
+pub const FIELD_VARS: &[&str] = &["model", "rpc_url", "sender"];
+
+/// Looks up a config variable by name
+type ConfigVar<'a> = Box<dyn Fn(&str) -> Option<String> + 'a>;
+
+/// Builds a [`Config`] from defaults, a document, config variables and overrides
+pub struct ConfigLoader<'a> {
+    defaults: Option<Config>,
+    uri: Option<String>,
+    config_var: Option<ConfigVar<'a>>,
+    overrides: Vec<Value>,
+}
+
+impl Default for ConfigLoader<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> ConfigLoader<'a> {
+    /// Start from the fields' serde defaults
+    ///
+    /// Nothing is filled in for the document: `model` must be set by a
+    /// layer, and contracts and system messages are only those it lists.
+    pub fn new() -> Self {
+        Self {
+            defaults: None,
+            uri: None,
+            config_var: None,
+            overrides: Vec::new(),
+        }
+    }
+
+    /// Start from these defaults instead, e.g. [`Config::default`]
+    pub fn with_defaults(mut self, defaults: Config) -> Self {
+        self.defaults = Some(defaults);
+        self
+    }
+
+    /// Load the document at `uri` (`http(s)://`, `ipfs://` or `data:`)
+    ///
+    /// Takes precedence over the `config_uri` config variable.
+    pub fn with_uri(mut self, uri: impl Into<String>) -> Self {
+        self.uri = Some(uri.into());
+        self
+    }
+
+    /// Read config variables through `lookup`, typically `host::config_var`
+    ///
+    /// `config_uri` names the document, `config_overrides` holds a JSON merge
+    /// patch, and `model`, `rpc_url` and `sender` set those fields directly.
+    pub fn with_config_vars(mut self, lookup: impl Fn(&str) -> Option<String> + 'a) -> Self {
+        self.config_var = Some(Box::new(lookup));
+        self
+    }
+
+    /// Apply a JSON merge patch after every other layer, e.g. from the trigger
+    pub fn with_overrides(mut self, patch: Value) -> Self {
+        self.overrides.push(patch);
+        self
+    }
+
+    /// Merge the layers and validate the result
+    ///
+    /// Blocks on the WASI runtime to fetch the document; inside an async
+    /// function use [`ConfigLoader::load_async`].
+    pub fn load(self) -> Result<Config, AgentError> {
+        let document = match self.document_uri() {
+            Some(uri) => Some(block_on(fetch_document(uri))?),
+            None => None,
+        };
+        self.merge(document)
+    }
+
+    /// Async version of [`ConfigLoader::load`]
+    pub async fn load_async(self) -> Result<Config, AgentError> {
+        let document = match self.document_uri() {
+            Some(uri) => Some(fetch_document(uri).await?),
+            None => None,
+        };
+        self.merge(document)
+    }
+
+    fn var(&self, name: &str) -> Option<String> {
+        self.config_var.as_ref().and_then(|lookup| lookup(name))
+    }
+
+    /// URI of the document layer, if there is one
+    fn document_uri(&self) -> Option<String> {
+        self.uri.clone().or_else(|| self.var(CONFIG_URI_VAR))
+    }
+
+    /// Apply the fetched document and the later layers over the defaults
+    fn merge(self, document: Option<Value>) -> Result<Config, AgentError> {
+        let mut value = match &self.defaults {
+            Some(defaults) => serde_json::to_value(defaults)?,
+            None => json!({
+                "contracts": [],
+                "llm_config": LlmOptions::default(),
+                "messages": [],
+            }),
+        };
+        let var = |name: &str| self.var(name);
+
+        if let Some(document) = document {
+            merge_patch(&mut value, migrate(document)?);
+        }
+
+        if let Some(patch) = var(CONFIG_OVERRIDES_VAR) {
+            let patch = serde_json::from_str(&patch).map_err(|e| {
+                AgentError::Configuration(format!(
+                    "Config variable {} is not valid JSON: {}",
+                    CONFIG_OVERRIDES_VAR, e
+                ))
+            })?;
+            merge_patch(&mut value, patch);
+        }
+        for field in FIELD_VARS {
+            if let Some(setting) = var(field) {
+                value[*field] = Value::String(setting);
+            }
+        }
+
+        for patch in self.overrides {
+            merge_patch(&mut value, patch);
+        }
+
+        from_value(value)
+    }
+}
+
+/// Check a config document against the schema and deserialize it
+pub(crate) fn from_value(value: Value) -> Result<Config, AgentError> {
+    schema::validate(&value, &Config::json_schema()).map_err(|violations| {
+        AgentError::Configuration(format!(
+            "Invalid config:\n{}",
+            schema::describe_violations(&violations)
+        ))
+    })?;
+
+    let config: Config = serde_json::from_value(value)
+        .map_err(|e| AgentError::Configuration(format!("Invalid config: {}", e)))?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// Bring a config document up to [`CONFIG_VERSION`]
+///
+/// Documents without a `version` are version 0, which has the same layout
+/// as version 1. Layout changes add a step here that rewrites older
+/// documents, so published configs keep working.
+pub fn migrate(mut document: Value) -> Result<Value, AgentError> {
+    let Some(object) = document.as_object_mut() else {
+        return Err(AgentError::Configuration(
+            "Config document must be a JSON object".to_string(),
+        ));
+    };
+
+    let version = match object.get("version") {
+        None => 0,
+        Some(v) => v.as_u64().ok_or_else(|| {
+            AgentError::Configuration(format!(
+                "Config version must be a non-negative integer, got {}",
+                v
+            ))
+        })?,
+    };
+    if version > u64::from(CONFIG_VERSION) {
+        return Err(AgentError::Configuration(format!(
+            "Config version {} is newer than this library supports ({})",
+            version, CONFIG_VERSION
+        )));
+    }
+
+    object.insert("version".to_string(), Value::from(CONFIG_VERSION));
+    Ok(document)
+}
+
+/// Apply an RFC 7386 merge patch: objects merge, `null` removes, anything else replaces
+pub fn merge_patch(target: &mut Value, patch: Value) {
+    let Value::Object(patch) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    let Value::Object(target) = target else {
+        return;
+    };
+
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(&key);
+        } else {
+            merge_patch(target.entry(key).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// Fetch and parse the JSON document at `uri`
+async fn fetch_document(uri: String) -> Result<Value, AgentError> {
+    // Config variables are often quoted
+    let uri = uri.trim().trim_matches('"');
+
+    let bytes = if let Some(data) = uri.strip_prefix("data:") {
+        decode_data_uri(data)?
+    } else if let Some(cid) = uri.strip_prefix("ipfs://") {
+        fetch_http(&ipfs_gateway_url(cid)).await?
+    } else if uri.starts_with("http://") || uri.starts_with("https://") {
+        fetch_http(uri).await?
+    } else {
+        return Err(AgentError::Configuration(format!(
+            "Unsupported config URI {}; use http(s)://, ipfs:// or data:",
+            uri
+        )));
+    };
+
+    serde_json::from_slice(&bytes).map_err(|e| {
+        AgentError::Configuration(format!("Config at {} is not valid JSON: {}", uri, e))
+    })
+}
+
+async fn fetch_http(url: &str) -> Result<Vec<u8>, AgentError> {
+    if cfg!(not(target_arch = "wasm32")) {
+        return Err(AgentError::Configuration(format!(
+            "Cannot fetch {}: HTTP is only available inside a WASI component",
+            url
+        )));
+    }
+
+    let mut request = http_request_get(url)
+        .map_err(|e| AgentError::Http(format!("Failed to create request for {}: {}", url, e)))?;
+    request
+        .headers_mut()
+        .insert("Accept", HeaderValue::from_static("application/json"));
+
+    fetch_bytes(request)
+        .await
+        .map_err(|e| AgentError::Http(format!("Failed to fetch config from {}: {}", url, e)))
+}
+
+/// Payload of a `data:` URI (the part after `data:`), base64 or percent-encoded
+fn decode_data_uri(data: &str) -> Result<Vec<u8>, AgentError> {
+    let (header, payload) = data.split_once(',').ok_or_else(|| {
+        AgentError::Configuration("data: URI has no ',' before its payload".to_string())
+    })?;
+
+    if header.ends_with(";base64") {
+        STANDARD
+            .decode(payload)
+            .map_err(|e| AgentError::Configuration(format!("data: URI is not base64: {}", e)))
+    } else {
+        percent_decode(payload)
+    }
+}
+
+fn percent_decode(text: &str) -> Result<Vec<u8>, AgentError> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let byte = bytes
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| {
+                    AgentError::Configuration(format!("Invalid escape in data: URI at byte {}", i))
+                })?;
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    const ADDRESS: &str = "0x1234567890123456789012345678901234567890";
+
+    fn data_uri(document: &Value) -> String {
+        format!(
+            "data:application/json;base64,{}",
+            STANDARD.encode(document.to_string())
+        )
+    }
+
+    #[test]
+    fn test_layers() {
+        let document = json!({
+            "model": "qwen2.5",
+            "contracts": [{"name": "Vault", "address": ADDRESS, "abi": "[]"}],
+            "llm_config": {"temperature": 0.5}
+        });
+        let vars: HashMap<&str, &str> = HashMap::from([
+            (
+                "config_uri",
+                "\"data:application/json,{\"model\": \"ignored\"}\"",
+            ),
+            ("config_overrides", r#"{"llm_config": {"seed": 7}}"#),
+            ("rpc_url", "http://localhost:8545"),
+        ]);
+
+        let config = ConfigLoader::new()
+            .with_defaults(Config::default())
+            .with_uri(data_uri(&document))
+            .with_config_vars(|name| vars.get(name).map(|v| v.to_string()))
+            .with_overrides(json!({"model": "llava", "llm_config": {"max_tokens": null}}))
+            .load()
+            .unwrap();
+
+        assert_eq!(config.version, CONFIG_VERSION);
+        assert_eq!(config.model, "llava");
+        assert_eq!(config.contracts[0].name, "Vault");
+        // Document and variables merge into the defaults
+        assert_eq!(config.llm_config.temperature, Some(0.5));
+        assert_eq!(config.llm_config.top_p, Config::default().llm_config.top_p);
+        assert_eq!(config.llm_config.seed, Some(7));
+        assert_eq!(config.llm_config.max_tokens, None);
+        assert_eq!(config.rpc_url.as_deref(), Some("http://localhost:8545"));
+        assert_eq!(config.messages.len(), 1);
+
+        // Without an explicit URI the config variable names the document
+        let config = ConfigLoader::new()
+            .with_config_vars(|name| vars.get(name).map(|v| v.to_string()))
+            .load()
+            .unwrap();
+        assert_eq!(config.model, "ignored");
+    }
+
+    #[test]
+    fn test_documents_get_no_agent_defaults() {
+        let document = json!({"model": "qwen2.5", "llm_config": {"temperature": 0.5}});
+        let config = ConfigLoader::new()
+            .with_uri(data_uri(&document))
+            .load()
+            .unwrap();
+        assert!(config.contracts.is_empty());
+        assert!(config.messages.is_empty());
+        assert_eq!(config.llm_config.temperature, Some(0.5));
+        assert_eq!(config.llm_config.top_p, LlmOptions::default().top_p);
+        assert_eq!(config.llm_config.seed, LlmOptions::default().seed);
+        assert!(config.policy.is_none());
+
+        let err = ConfigLoader::new()
+            .with_uri(data_uri(&json!({"contracts": []})))
+            .load()
+            .unwrap_err();
+        assert!(err.to_string().contains("model"), "{}", err);
+    }
+
+    #[test]
+    fn test_errors_name_the_field() {
+        let document = json!({
+            "contracts": [{"name": "Vault", "address": "0x12", "abi": "[]"}],
+            "llm_config": {"temperature": "hot"}
+        });
+        let err = ConfigLoader::new()
+            .with_uri(data_uri(&document))
+            .load()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("$.contracts[0].address"), "{}", err);
+        assert!(err.contains("$.llm_config.temperature"), "{}", err);
+
+        for (uri, expected) in [
+            ("ftp://example.com/config.json", "Unsupported config URI"),
+            ("data:application/json,{oops", "not valid JSON"),
+            ("data:application/json;base64,!!!", "not base64"),
+            ("data:application/json,%zz", "Invalid escape"),
+            ("data:application/json,[1]", "must be a JSON object"),
+            (
+                "https://example.com/config.json",
+                "only available inside a WASI component",
+            ),
+            ("ipfs://bafkreib", "only available inside a WASI component"),
+        ] {
+            let err = ConfigLoader::new().with_uri(uri).load().unwrap_err();
+            assert!(err.to_string().contains(expected), "{}: {}", uri, err);
+        }
+
+        let err = ConfigLoader::new()
+            .with_config_vars(|name| (name == CONFIG_OVERRIDES_VAR).then(|| "{".to_string()))
+            .load()
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("config_overrides is not valid JSON"));
+    }
+
+    #[test]
+    fn test_migrate() {
+        let migrated = migrate(json!({"model": "llama3.2"})).unwrap();
+        assert_eq!(migrated["version"], CONFIG_VERSION);
+        assert!(migrate(json!({"version": CONFIG_VERSION})).is_ok());
+        assert!(migrate(json!({"version": CONFIG_VERSION + 1}))
+            .unwrap_err()
+            .to_string()
+            .contains("newer than this library supports"));
+        assert!(migrate(json!({"version": "1"})).is_err());
+    }
+
+    #[test]
+    fn test_merge_patch() {
+        let mut target = json!({"a": {"b": 1, "c": 2}, "list": [1, 2], "gone": true});
+        merge_patch(
+            &mut target,
+            json!({"a": {"b": 3}, "list": [3], "gone": null, "new": "x"}),
+        );
+        assert_eq!(
+            target,
+            json!({"a": {"b": 3, "c": 2}, "list": [3], "new": "x"})
+        );
+    }
+}
@@ -0,0 +1,1015 @@
+//! Guardrails for transactions proposed by the model
+//!
+//! A [`Policy`] declares what an agent may send: which contracts and
+//! functions it may call, how much ETH it may move per transaction and per
+//! time window, how many tokens an ERC-20 `transfer`, `approve` or
+//! `transferFrom` may move, which addresses must never receive anything, and
+//! whether the model has to explain each transaction. A [`PolicyEngine`]
+//! checks every tool result against it before the result is handed back.
+//! Denials list every rule that was broken, so the model can re-plan instead
+//! of retrying blindly.
+//!
+//! Checking a transaction does not spend from the window: proposals may be
+//! dropped (a losing consensus sample, a run that stops early), so only
+//! transactions passed to [`PolicyEngine::commit`] once they are actually
+//! submitted count against it.
+//!
+//! Results that are not transactions (read results, custom tool output)
+//! pass unchecked. A result that looks like a transaction (a JSON object with
+//! `to` and `value`, `data` or `contract_call`) but does not parse as a
+//! [`Transaction`] is denied, so a handler emitting a different shape cannot
+//! slip past the rules.
+
+use crate::cache::{now, CacheStore, KeyValueCache, MemoryCache};
+use crate::config::Config;
+use crate::contracts::{Contract, Transaction};
+use crate::errors::AgentError;
+use crate::tools::ToolCall;
+use alloy_primitives::{keccak256, Address, Bytes, Selector, U256};
+use alloy_sol_types::{sol, SolCall};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+sol! {
+    interface IERC20 {
+        function transfer(address to, uint256 amount) external returns (bool);
+        function approve(address spender, uint256 amount) external returns (bool);
+        function transferFrom(address from, address to, uint256 amount) external returns (bool);
+    }
+}
+
+/// Declarative limits on the transactions an agent may propose
+///
+/// Every field is optional; the default policy allows everything. Amounts
+/// are decimal (or `0x` hex) strings in wei or token base units.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Policy {
+    /// Contracts calls may target; empty allows any contract
+    ///
+    /// Only transactions with calldata are checked. Plain ETH transfers are
+    /// bounded by the value limits and forbidden recipients instead.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_contracts: Vec<AllowedContract>,
+
+    /// Most ETH a single transaction may send
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_value_per_tx: Option<String>,
+
+    /// Most ETH all committed transactions may send within a rolling window
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_value_per_window: Option<ValueWindow>,
+
+    /// Per-token caps on ERC-20 `transfer`, `approve` and `transferFrom` amounts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub token_limits: Vec<TokenLimit>,
+
+    /// Addresses that may not receive ETH, tokens or allowances
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forbidden_recipients: Vec<String>,
+
+    /// Require the model to pass a `description` explaining each transaction
+    #[serde(default)]
+    pub require_description: bool,
+}
+
+/// A contract calls may target
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct AllowedContract {
+    pub address: String,
+    /// Functions that may be called, as signatures (`transfer(address,uint256)`)
+    /// or selectors (`0xa9059cbb`); empty allows every function
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub functions: Vec<String>,
+}
+
+/// A cap on the ETH sent within a rolling time window
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ValueWindow {
+    pub max_value: String,
+    /// Window length in seconds
+    pub window_secs: u64,
+}
+
+/// A cap on the amount of one token a single transaction may move or approve
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TokenLimit {
+    /// Token contract address
+    pub token: String,
+    pub max_amount: String,
+}
+
+impl Policy {
+    /// Create a policy that allows everything
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a policy from JSON
+    pub fn from_json(json: &str) -> Result<Self, AgentError> {
+        let policy: Self = serde_json::from_str(json).map_err(|e| {
+            AgentError::Configuration(format!("Failed to parse Policy JSON: {}", e))
+        })?;
+        policy.validate()?;
+        Ok(policy)
+    }
+
+    /// Allow calls to a contract, limited to `functions` unless it is empty
+    pub fn with_allowed_contract(mut self, address: &str, functions: &[&str]) -> Self {
+        self.allowed_contracts.push(AllowedContract {
+            address: address.to_string(),
+            functions: functions.iter().map(|f| f.to_string()).collect(),
+        });
+        self
+    }
+
+    /// Set the most ETH (in wei) a single transaction may send
+    pub fn with_max_value_per_tx(mut self, max_value: impl Into<String>) -> Self {
+        self.max_value_per_tx = Some(max_value.into());
+        self
+    }
+
+    /// Set the most ETH (in wei) that may be sent within `window`
+    pub fn with_max_value_per_window(
+        mut self,
+        max_value: impl Into<String>,
+        window: Duration,
+    ) -> Self {
+        self.max_value_per_window = Some(ValueWindow {
+            max_value: max_value.into(),
+            window_secs: window.as_secs(),
+        });
+        self
+    }
+
+    /// Cap the amount of `token` a single transaction may move or approve
+    pub fn with_token_limit(mut self, token: &str, max_amount: impl Into<String>) -> Self {
+        self.token_limits.push(TokenLimit {
+            token: token.to_string(),
+            max_amount: max_amount.into(),
+        });
+        self
+    }
+
+    /// Never send ETH, tokens or allowances to `address`
+    pub fn with_forbidden_recipient(mut self, address: &str) -> Self {
+        self.forbidden_recipients.push(address.to_string());
+        self
+    }
+
+    /// Require the model to explain each transaction
+    pub fn with_require_description(mut self, require: bool) -> Self {
+        self.require_description = require;
+        self
+    }
+
+    /// Check that every address, amount and function in the policy parses
+    pub fn validate(&self) -> Result<(), AgentError> {
+        for allowed in &self.allowed_contracts {
+            parse_address("allowed contract", &allowed.address)?;
+            for function in &allowed.functions {
+                parse_selector(function)?;
+            }
+        }
+        if let Some(max_value) = &self.max_value_per_tx {
+            parse_amount("max_value_per_tx", max_value)?;
+        }
+        if let Some(window) = &self.max_value_per_window {
+            parse_amount("max_value_per_window", &window.max_value)?;
+            if window.window_secs == 0 {
+                return Err(AgentError::Configuration(
+                    "Policy max_value_per_window needs a window longer than 0 seconds".to_string(),
+                ));
+            }
+        }
+        for limit in &self.token_limits {
+            parse_address("token limit", &limit.token)?;
+            parse_amount("token limit", &limit.max_amount)?;
+        }
+        for recipient in &self.forbidden_recipients {
+            parse_address("forbidden recipient", recipient)?;
+        }
+        Ok(())
+    }
+
+    /// Every rule `tx` breaks, given the ETH already sent in the current window
+    ///
+    /// A policy that fails [`Policy::validate`] breaks
+    /// [`PolicyRule::InvalidPolicy`] for every transaction.
+    /// The rules apply to the calldata the transaction will carry: a
+    /// `contract_call` is encoded with the matching contract in `contracts`,
+    /// as [`Transaction::to_payload`] does, and is denied if that fails or
+    /// does not give the `data` the transaction states. `tool_call` is the
+    /// call that produced the transaction; its arguments carry the model's
+    /// description.
+    pub fn violations(
+        &self,
+        tx: &Transaction,
+        contracts: &[Contract],
+        tool_call: &ToolCall,
+        spent_in_window: U256,
+    ) -> Vec<PolicyViolation> {
+        let mut violations = Vec::new();
+        let mut deny = |rule, message: String| violations.push(PolicyViolation { rule, message });
+
+        // A limit that does not parse must not read as no limit, so a policy
+        // that does not validate denies everything; past this, limits parse
+        if let Err(e) = self.validate() {
+            deny(PolicyRule::InvalidPolicy, e.to_string());
+            return violations;
+        }
+
+        let (Ok(to), Ok(value), Ok(stated)) = (
+            Address::from_str(&tx.to),
+            U256::from_str(&tx.value),
+            Bytes::from_str(&tx.data),
+        ) else {
+            deny(
+                PolicyRule::InvalidTransaction,
+                "Transaction needs a valid address, value and hex data".to_string(),
+            );
+            return violations;
+        };
+        let data = match tx.calldata(contracts) {
+            Ok(data) if stated.is_empty() || data == stated => data,
+            Ok(_) => {
+                deny(
+                    PolicyRule::InvalidTransaction,
+                    "Transaction data does not encode its contract_call".to_string(),
+                );
+                return violations;
+            }
+            Err(e) => {
+                deny(
+                    PolicyRule::InvalidTransaction,
+                    format!("Could not encode the contract_call: {}", e),
+                );
+                return violations;
+            }
+        };
+
+        if self.is_forbidden(to) {
+            deny(
+                PolicyRule::ForbiddenRecipient,
+                format!("{} is a forbidden recipient", to),
+            );
+        }
+
+        // Calls must target an allowed contract and function
+        if !data.is_empty() && !self.allowed_contracts.is_empty() {
+            match self
+                .allowed_contracts
+                .iter()
+                .find(|allowed| Address::from_str(&allowed.address) == Ok(to))
+            {
+                None => deny(
+                    PolicyRule::ContractNotAllowed,
+                    format!("Calls to {} are not allowed", to),
+                ),
+                Some(allowed) if !allowed.functions.is_empty() => {
+                    let selector = (data.len() >= 4).then(|| Selector::from_slice(&data[..4]));
+                    let permitted = allowed
+                        .functions
+                        .iter()
+                        .any(|f| parse_selector(f).ok() == selector);
+                    if !permitted {
+                        deny(
+                            PolicyRule::FunctionNotAllowed,
+                            format!(
+                                "Only {} may be called on {}",
+                                allowed.functions.join(", "),
+                                to
+                            ),
+                        );
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+
+        // Token movements and allowances
+        if let Some((recipient, amount)) = decode_erc20(&data) {
+            if self.is_forbidden(recipient) {
+                deny(
+                    PolicyRule::ForbiddenRecipient,
+                    format!("{} is a forbidden recipient", recipient),
+                );
+            }
+            let limit = self
+                .token_limits
+                .iter()
+                .find(|limit| Address::from_str(&limit.token) == Ok(to))
+                .and_then(|limit| U256::from_str(&limit.max_amount).ok());
+            if let Some(limit) = limit.filter(|limit| amount > *limit) {
+                deny(
+                    PolicyRule::TokenLimit,
+                    format!(
+                        "Amount {} of token {} exceeds the limit of {}",
+                        amount, to, limit
+                    ),
+                );
+            }
+        }
+
+        let max_per_tx = self
+            .max_value_per_tx
+            .as_deref()
+            .and_then(|v| U256::from_str(v).ok());
+        if let Some(max) = max_per_tx.filter(|max| value > *max) {
+            deny(
+                PolicyRule::MaxValuePerTx,
+                format!(
+                    "Value {} wei exceeds the limit of {} wei per transaction",
+                    value, max
+                ),
+            );
+        }
+
+        if let Some(window) = &self.max_value_per_window {
+            let max = U256::from_str(&window.max_value).unwrap_or_default();
+            if value > U256::ZERO && spent_in_window.saturating_add(value) > max {
+                deny(
+                    PolicyRule::MaxValuePerWindow,
+                    format!(
+                        "Value {} wei would exceed the limit of {} wei per {} seconds ({} wei already sent)",
+                        value, max, window.window_secs, spent_in_window
+                    ),
+                );
+            }
+        }
+
+        if self.require_description && model_description(tool_call).is_none() {
+            deny(
+                PolicyRule::MissingDescription,
+                format!(
+                    "Pass a 'description' argument to {} explaining why this transaction should be sent",
+                    tool_call.function.name
+                ),
+            );
+        }
+
+        violations
+    }
+
+    fn is_forbidden(&self, address: Address) -> bool {
+        self.forbidden_recipients
+            .iter()
+            .any(|forbidden| Address::from_str(forbidden) == Ok(address))
+    }
+}
+
+/// The rule a transaction broke
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyRule {
+    /// A limit in the policy itself does not parse
+    InvalidPolicy,
+    /// The address, value or data did not parse
+    InvalidTransaction,
+    /// The call targets a contract outside the allowlist
+    ContractNotAllowed,
+    /// The call targets a function outside the contract's allowlist
+    FunctionNotAllowed,
+    /// The transaction sends more ETH than one transaction may
+    MaxValuePerTx,
+    /// The transaction would push the ETH sent in the window over its cap
+    MaxValuePerWindow,
+    /// An ERC-20 transfer or approval exceeds the token's cap
+    TokenLimit,
+    /// ETH, tokens or an allowance would go to a forbidden address
+    ForbiddenRecipient,
+    /// The model did not explain the transaction
+    MissingDescription,
+    /// ETH sent earlier in the window could not be looked up
+    SpendingLedger,
+}
+
+/// One broken rule and what the model can do about it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyViolation {
+    pub rule: PolicyRule,
+    pub message: String,
+}
+
+/// A transaction the policy refused
+///
+/// Displays as JSON so the model receives the reasons as a tool result.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PolicyDenial {
+    pub violations: Vec<PolicyViolation>,
+}
+
+impl fmt::Display for PolicyDenial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let body = serde_json::json!({
+            "error": "Transaction denied by policy",
+            "violations": self.violations,
+        });
+        write!(f, "{}", body)
+    }
+}
+
+impl std::error::Error for PolicyDenial {}
+
+/// ETH sent by a committed transaction
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Spend {
+    /// Unix time of the commit, in seconds
+    at: u64,
+    value: String,
+}
+
+/// `wasi:keyvalue` bucket holding the spending ledger of policies set in a [`Config`]
+pub const POLICY_BUCKET: &str = "wavs-llm-policy";
+
+type Ledger = Arc<dyn CacheStore + Send + Sync>;
+
+/// Applies a [`Policy`] to tool results and keeps track of ETH spent
+///
+/// Spending is recorded in memory unless a store is given; inside a
+/// component, a `wasi:keyvalue` bucket keeps the window across triggers.
+/// Cloning is cheap and shares the ledger.
+#[derive(Clone)]
+pub struct PolicyEngine {
+    policy: Policy,
+    /// Where spending is recorded, or why it cannot be
+    ledger: Result<Ledger, String>,
+    key: String,
+}
+
+impl fmt::Debug for PolicyEngine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PolicyEngine")
+            .field("policy", &self.policy)
+            .field("key", &self.key)
+            .finish_non_exhaustive()
+    }
+}
+
+impl From<Policy> for PolicyEngine {
+    fn from(policy: Policy) -> Self {
+        Self::new(policy)
+    }
+}
+
+impl PolicyEngine {
+    /// Enforce `policy`, recording spending in memory
+    ///
+    /// Check the policy with [`Policy::validate`] first: one that does not
+    /// validate denies every transaction.
+    pub fn new(policy: Policy) -> Self {
+        Self {
+            policy,
+            ledger: Ok(Arc::new(MemoryCache::new())),
+            key: "policy:spent".to_string(),
+        }
+    }
+
+    /// Enforce the config's policy, if it has one
+    ///
+    /// A window limit needs its spending to outlive the trigger, so it is
+    /// recorded in the [`POLICY_BUCKET`] `wasi:keyvalue` bucket. Where that
+    /// bucket cannot be opened (e.g. outside a component), every transaction
+    /// that sends ETH is denied rather than checked against a window that
+    /// starts empty on each trigger.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let mut engine = Self::new(config.policy.clone()?);
+        if engine.policy.max_value_per_window.is_some() {
+            engine.ledger = KeyValueCache::open(POLICY_BUCKET)
+                .map(|store| Arc::new(store) as Ledger)
+                .map_err(|e| format!("no persistent spending ledger ({})", e));
+        }
+        Some(engine)
+    }
+
+    /// Record spending under `key` in `store`, so windows outlive the engine
+    pub fn with_store(
+        mut self,
+        store: impl CacheStore + Send + Sync + 'static,
+        key: impl Into<String>,
+    ) -> Self {
+        self.ledger = Ok(Arc::new(store));
+        self.key = key.into();
+        self
+    }
+
+    /// The policy being enforced
+    pub fn policy(&self) -> &Policy {
+        &self.policy
+    }
+
+    /// Check a tool result against the policy without recording anything
+    ///
+    /// Results that are not transactions pass unchanged; results shaped like
+    /// a transaction that does not parse are denied. `contracts` are the ones
+    /// the transaction will be encoded with; see [`Policy::violations`].
+    pub fn evaluate(
+        &self,
+        tool_call: &ToolCall,
+        result: &str,
+        contracts: &[Contract],
+    ) -> Result<(), PolicyDenial> {
+        match serde_json::from_str::<Transaction>(result) {
+            Ok(tx) => self.evaluate_transaction(&tx, contracts, tool_call, now()),
+            Err(e) if looks_like_transaction(result) => Err(PolicyDenial {
+                violations: vec![PolicyViolation {
+                    rule: PolicyRule::InvalidTransaction,
+                    message: format!("Result looks like a transaction but does not parse: {}", e),
+                }],
+            }),
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Count a submitted transaction's ETH against the window
+    ///
+    /// Call this for the transaction the component actually emits, once.
+    /// Does nothing unless the policy has a window limit.
+    pub fn commit(&self, tx: &Transaction) -> Result<(), AgentError> {
+        self.commit_at(tx, now())
+    }
+
+    fn commit_at(&self, tx: &Transaction, at: u64) -> Result<(), AgentError> {
+        let Some(window) = &self.policy.max_value_per_window else {
+            return Ok(());
+        };
+        let value = U256::from_str(&tx.value).map_err(|e| {
+            AgentError::Transaction(format!("Invalid transaction value {}: {}", tx.value, e))
+        })?;
+        if value == U256::ZERO {
+            return Ok(());
+        }
+
+        let ledger_error =
+            |e: String| AgentError::Other(format!("Could not record spending: {}", e));
+        let mut spends = self.spends_since(at, window).map_err(ledger_error)?;
+        spends.push(Spend {
+            at,
+            value: value.to_string(),
+        });
+        self.save_spends(&spends).map_err(ledger_error)
+    }
+
+    fn evaluate_transaction(
+        &self,
+        tx: &Transaction,
+        contracts: &[Contract],
+        tool_call: &ToolCall,
+        at: u64,
+    ) -> Result<(), PolicyDenial> {
+        let ledger_error = |e: String| PolicyDenial {
+            violations: vec![PolicyViolation {
+                rule: PolicyRule::SpendingLedger,
+                message: format!("Could not check earlier spending: {}", e),
+            }],
+        };
+
+        let spends = match &self.policy.max_value_per_window {
+            Some(window) => self.spends_since(at, window).map_err(ledger_error)?,
+            None => Vec::new(),
+        };
+        let spent = spends
+            .iter()
+            .filter_map(|spend| U256::from_str(&spend.value).ok())
+            .fold(U256::ZERO, U256::saturating_add);
+
+        let violations = self.policy.violations(tx, contracts, tool_call, spent);
+        if !violations.is_empty() {
+            return Err(PolicyDenial { violations });
+        }
+        Ok(())
+    }
+
+    /// Spending recorded within `window` before `at`
+    fn spends_since(&self, at: u64, window: &ValueWindow) -> Result<Vec<Spend>, String> {
+        let start = at.saturating_sub(window.window_secs);
+        let mut spends = self.spends()?;
+        spends.retain(|spend| spend.at > start);
+        Ok(spends)
+    }
+
+    fn ledger(&self) -> Result<&Ledger, String> {
+        self.ledger.as_ref().map_err(Clone::clone)
+    }
+
+    fn spends(&self) -> Result<Vec<Spend>, String> {
+        match self.ledger()?.get(&self.key).map_err(|e| e.to_string())? {
+            Some(bytes) => serde_json::from_slice(&bytes).map_err(|e| e.to_string()),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn save_spends(&self, spends: &[Spend]) -> Result<(), String> {
+        let bytes = serde_json::to_vec(spends).map_err(|e| e.to_string())?;
+        self.ledger()?
+            .set(&self.key, &bytes)
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Whether `result` is a JSON object with a recipient and something to send
+fn looks_like_transaction(result: &str) -> bool {
+    let Ok(Value::Object(object)) = serde_json::from_str::<Value>(result) else {
+        return false;
+    };
+    object.contains_key("to")
+        && ["value", "data", "contract_call"]
+            .iter()
+            .any(|key| object.contains_key(*key))
+}
+
+/// Recipient (or spender) and amount of an ERC-20 transfer, approval or transferFrom
+fn decode_erc20(data: &[u8]) -> Option<(Address, U256)> {
+    let selector: [u8; 4] = data.get(..4)?.try_into().ok()?;
+    match selector {
+        IERC20::transferCall::SELECTOR => IERC20::transferCall::abi_decode(data)
+            .ok()
+            .map(|call| (call.to, call.amount)),
+        IERC20::approveCall::SELECTOR => IERC20::approveCall::abi_decode(data)
+            .ok()
+            .map(|call| (call.spender, call.amount)),
+        IERC20::transferFromCall::SELECTOR => IERC20::transferFromCall::abi_decode(data)
+            .ok()
+            .map(|call| (call.to, call.amount)),
+        _ => None,
+    }
+}
+
+/// The non-empty `description` argument the model passed, if any
+fn model_description(tool_call: &ToolCall) -> Option<String> {
+    let args: Value = serde_json::from_str(&tool_call.function.arguments).ok()?;
+    args.get("description")?
+        .as_str()
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .map(str::to_string)
+}
+
+fn parse_address(what: &str, address: &str) -> Result<Address, AgentError> {
+    Address::from_str(address).map_err(|_| {
+        AgentError::Configuration(format!("Policy {} is not an address: {}", what, address))
+    })
+}
+
+fn parse_amount(what: &str, amount: &str) -> Result<U256, AgentError> {
+    U256::from_str(amount).map_err(|_| {
+        AgentError::Configuration(format!("Policy {} is not an amount: {}", what, amount))
+    })
+}
+
+/// Selector from a `0x`-prefixed selector or a function signature
+fn parse_selector(function: &str) -> Result<Selector, AgentError> {
+    if let Some(hex) = function.strip_prefix("0x") {
+        return Selector::from_str(hex).map_err(|_| {
+            AgentError::Configuration(format!("Policy function {} is not a selector", function))
+        });
+    }
+    if !function.contains('(') || !function.ends_with(')') {
+        return Err(AgentError::Configuration(format!(
+            "Policy function {} needs a full signature like transfer(address,uint256) or a selector",
+            function
+        )));
+    }
+    let signature: String = function.chars().filter(|c| !c.is_whitespace()).collect();
+    Ok(Selector::from_slice(&keccak256(signature.as_bytes())[..4]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::ToolCallFunction;
+    use serde_json::json;
+
+    const TOKEN: &str = "0xb7278a61aa25c888815afc32ad3cc52ff24fe575";
+    const ALICE: &str = "0x1234567890123456789012345678901234567890";
+    const MALLORY: &str = "0x0987654321098765432109876543210987654321";
+
+    fn call(arguments: Value) -> ToolCall {
+        ToolCall {
+            id: "call_1".to_string(),
+            tool_type: "function".to_string(),
+            function: ToolCallFunction {
+                name: "send_eth".to_string(),
+                arguments: arguments.to_string(),
+            },
+        }
+    }
+
+    fn transfer(to: &str, value: &str) -> Transaction {
+        Transaction {
+            to: to.to_string(),
+            value: value.to_string(),
+            contract_call: None,
+            data: "0x".to_string(),
+            description: "Pay the contributor".to_string(),
+            gas_estimate: None,
+        }
+    }
+
+    fn token_call(data: Vec<u8>) -> Transaction {
+        Transaction {
+            data: Bytes::from(data).to_string(),
+            ..transfer(TOKEN, "0")
+        }
+    }
+
+    fn rules(violations: &[PolicyViolation]) -> Vec<PolicyRule> {
+        violations.iter().map(|v| v.rule).collect()
+    }
+
+    #[test]
+    fn test_from_json_and_validate() {
+        let policy = Policy::from_json(&format!(
+            r#"{{
+                "allowed_contracts": [{{"address": "{TOKEN}", "functions": ["transfer(address, uint256)", "0x095ea7b3"]}}],
+                "max_value_per_tx": "1000000000000000000",
+                "max_value_per_window": {{"max_value": "2000000000000000000", "window_secs": 86400}},
+                "token_limits": [{{"token": "{TOKEN}", "max_amount": "500"}}],
+                "forbidden_recipients": ["{MALLORY}"],
+                "require_description": true
+            }}"#
+        ))
+        .unwrap();
+        assert_eq!(
+            policy,
+            Policy::new()
+                .with_allowed_contract(TOKEN, &["transfer(address, uint256)", "0x095ea7b3"])
+                .with_max_value_per_tx("1000000000000000000")
+                .with_max_value_per_window("2000000000000000000", Duration::from_secs(86400))
+                .with_token_limit(TOKEN, "500")
+                .with_forbidden_recipient(MALLORY)
+                .with_require_description(true)
+        );
+        assert_eq!(
+            parse_selector("transfer(address, uint256)").unwrap(),
+            IERC20::transferCall::SELECTOR
+        );
+
+        assert!(Policy::from_json("{}").unwrap() == Policy::default());
+        for invalid in [
+            Policy::new().with_max_value_per_tx("lots"),
+            Policy::new().with_forbidden_recipient("0x1234"),
+            Policy::new().with_allowed_contract(TOKEN, &["transfer"]),
+            Policy::new().with_max_value_per_window("1", Duration::ZERO),
+            Policy::new().with_max_value_per_window("lots", Duration::from_secs(60)),
+            Policy::new().with_token_limit(TOKEN, "1 ether"),
+        ] {
+            assert!(invalid.validate().is_err(), "{:?}", invalid);
+
+            // Limits that do not parse deny everything rather than nothing
+            let violations =
+                invalid.violations(&transfer(ALICE, "1"), &[], &call(json!({})), U256::ZERO);
+            assert_eq!(rules(&violations), [PolicyRule::InvalidPolicy]);
+        }
+        let engine = PolicyEngine::new(Policy::new().with_max_value_per_tx("1 ether"));
+        let tx = serde_json::to_string(&transfer(ALICE, "1000000000000000000000")).unwrap();
+        let denial = engine.evaluate(&call(json!({})), &tx, &[]).unwrap_err();
+        assert!(denial.violations[0]
+            .message
+            .contains("max_value_per_tx is not an amount"));
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let policy = Policy::new().with_allowed_contract(TOKEN, &["transfer(address,uint256)"]);
+        let args = json!({});
+
+        let data = IERC20::transferCall {
+            to: ALICE.parse().unwrap(),
+            amount: U256::from(10),
+        }
+        .abi_encode();
+        assert!(policy
+            .violations(
+                &token_call(data.clone()),
+                &[],
+                &call(args.clone()),
+                U256::ZERO
+            )
+            .is_empty());
+
+        let approve = IERC20::approveCall {
+            spender: ALICE.parse().unwrap(),
+            amount: U256::from(10),
+        }
+        .abi_encode();
+        let violations =
+            policy.violations(&token_call(approve), &[], &call(args.clone()), U256::ZERO);
+        assert_eq!(rules(&violations), [PolicyRule::FunctionNotAllowed]);
+
+        let elsewhere = Transaction {
+            to: ALICE.to_string(),
+            ..token_call(data)
+        };
+        let violations = policy.violations(&elsewhere, &[], &call(args.clone()), U256::ZERO);
+        assert_eq!(rules(&violations), [PolicyRule::ContractNotAllowed]);
+
+        // Plain transfers are not calls
+        assert!(policy
+            .violations(&transfer(ALICE, "5"), &[], &call(args), U256::ZERO)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_tokens_and_recipients() {
+        let policy = Policy::new()
+            .with_token_limit(TOKEN, "1000")
+            .with_forbidden_recipient(MALLORY);
+        let args = json!({});
+
+        let approve_all = IERC20::approveCall {
+            spender: MALLORY.parse().unwrap(),
+            amount: U256::MAX,
+        }
+        .abi_encode();
+        let violations = policy.violations(
+            &token_call(approve_all),
+            &[],
+            &call(args.clone()),
+            U256::ZERO,
+        );
+        assert_eq!(
+            rules(&violations),
+            [PolicyRule::ForbiddenRecipient, PolicyRule::TokenLimit]
+        );
+
+        let small = IERC20::transferFromCall {
+            from: ALICE.parse().unwrap(),
+            to: ALICE.parse().unwrap(),
+            amount: U256::from(1000),
+        }
+        .abi_encode();
+        assert!(policy
+            .violations(&token_call(small), &[], &call(args.clone()), U256::ZERO)
+            .is_empty());
+
+        let violations = policy.violations(&transfer(MALLORY, "0"), &[], &call(args), U256::ZERO);
+        assert_eq!(rules(&violations), [PolicyRule::ForbiddenRecipient]);
+    }
+
+    #[test]
+    fn test_contract_call_is_checked_as_encoded() {
+        let policy = Policy::new()
+            .with_allowed_contract(TOKEN, &["approve(address,uint256)"])
+            .with_token_limit(TOKEN, "10")
+            .with_forbidden_recipient(MALLORY);
+        let engine = PolicyEngine::new(policy);
+        let contracts = [Contract::new(
+            "Token",
+            TOKEN,
+            r#"[
+                {"name": "transfer", "type": "function", "stateMutability": "nonpayable",
+                 "inputs": [{"name": "to", "type": "address"}, {"name": "amount", "type": "uint256"}],
+                 "outputs": [{"name": "", "type": "bool"}]}
+            ]"#,
+        )];
+        let result = json!({
+            "to": TOKEN,
+            "value": "0",
+            "data": "0x",
+            "description": "",
+            "contract_call": {"function": "transfer", "args": [MALLORY, "1000000000"]}
+        })
+        .to_string();
+
+        // The call is checked as the payload will encode it, not by its empty data
+        let denial = engine
+            .evaluate(&call(json!({})), &result, &contracts)
+            .unwrap_err();
+        assert_eq!(
+            rules(&denial.violations),
+            [
+                PolicyRule::FunctionNotAllowed,
+                PolicyRule::ForbiddenRecipient,
+                PolicyRule::TokenLimit
+            ]
+        );
+
+        // A call that cannot be encoded, or whose data says otherwise, is denied
+        let denial = engine.evaluate(&call(json!({})), &result, &[]).unwrap_err();
+        assert_eq!(rules(&denial.violations), [PolicyRule::InvalidTransaction]);
+        let approve = IERC20::approveCall {
+            spender: ALICE.parse().unwrap(),
+            amount: U256::from(1),
+        }
+        .abi_encode();
+        let mut disguised: Value = serde_json::from_str(&result).unwrap();
+        disguised["data"] = json!(Bytes::from(approve).to_string());
+        let denial = engine
+            .evaluate(&call(json!({})), &disguised.to_string(), &contracts)
+            .unwrap_err();
+        assert_eq!(rules(&denial.violations), [PolicyRule::InvalidTransaction]);
+    }
+
+    #[test]
+    fn test_value_window_and_description() {
+        let engine = PolicyEngine::new(
+            Policy::new()
+                .with_max_value_per_tx("100")
+                .with_max_value_per_window("150", Duration::from_secs(60))
+                .with_require_description(true),
+        );
+        let described = call(json!({"description": "Monthly grant payout"}));
+
+        // Checking alone spends nothing
+        for _ in 0..3 {
+            assert!(engine
+                .evaluate_transaction(&transfer(ALICE, "100"), &[], &described, 1000)
+                .is_ok());
+        }
+        engine.commit_at(&transfer(ALICE, "100"), 1000).unwrap();
+
+        // 100 already sent this minute
+        let denial = engine
+            .evaluate_transaction(&transfer(ALICE, "60"), &[], &described, 1030)
+            .unwrap_err();
+        assert_eq!(rules(&denial.violations), [PolicyRule::MaxValuePerWindow]);
+        assert!(denial.violations[0]
+            .message
+            .contains("100 wei already sent"));
+
+        // Old spending expires
+        assert!(engine
+            .evaluate_transaction(&transfer(ALICE, "50"), &[], &described, 1030)
+            .is_ok());
+        assert!(engine
+            .evaluate_transaction(&transfer(ALICE, "100"), &[], &described, 1061)
+            .is_ok());
+
+        let denial = engine
+            .evaluate_transaction(
+                &transfer(ALICE, "101"),
+                &[],
+                &call(json!({"description": " "})),
+                2000,
+            )
+            .unwrap_err();
+        assert_eq!(
+            rules(&denial.violations),
+            [PolicyRule::MaxValuePerTx, PolicyRule::MissingDescription]
+        );
+    }
+
+    #[test]
+    fn test_evaluate_tool_results() {
+        let store = Arc::new(MemoryCache::new());
+        let engine = PolicyEngine::new(Policy::new().with_max_value_per_tx("10"));
+
+        // Only transactions are checked
+        assert!(engine.evaluate(&call(json!({})), "42", &[]).is_ok());
+        assert!(engine
+            .evaluate(&call(json!({})), r#"{"balance": "1000"}"#, &[])
+            .is_ok());
+
+        // Transaction-shaped results that do not parse fail closed
+        let denial = engine
+            .evaluate(
+                &call(json!({})),
+                &json!({"to": ALICE, "value": 11}).to_string(),
+                &[],
+            )
+            .unwrap_err();
+        assert_eq!(rules(&denial.violations), [PolicyRule::InvalidTransaction]);
+
+        let tx = serde_json::to_string(&transfer(ALICE, "11")).unwrap();
+        let denial = engine.evaluate(&call(json!({})), &tx, &[]).unwrap_err();
+        let reported: Value = serde_json::from_str(&denial.to_string()).unwrap();
+        assert_eq!(reported["error"], "Transaction denied by policy");
+        assert_eq!(reported["violations"][0]["rule"], "max_value_per_tx");
+
+        // Spending persists in the store across engines
+        let policy = Policy::new().with_max_value_per_window("10", Duration::from_secs(3600));
+        let tx = serde_json::to_string(&transfer(ALICE, "6")).unwrap();
+        let first = PolicyEngine::new(policy.clone()).with_store(store.clone(), "spent");
+        assert!(first.evaluate(&call(json!({})), &tx, &[]).is_ok());
+        first.commit(&transfer(ALICE, "6")).unwrap();
+        let second = PolicyEngine::new(policy).with_store(store, "spent");
+        assert!(second.evaluate(&call(json!({})), &tx, &[]).is_err());
+    }
+
+    #[test]
+    fn test_config_policy_needs_persistent_ledger() {
+        let mut config = Config::default();
+        assert!(PolicyEngine::from_config(&config).is_none());
+
+        // Without a window nothing has to persist
+        config.policy = Some(Policy::new().with_max_value_per_tx("10"));
+        let engine = PolicyEngine::from_config(&config).unwrap();
+        let tx = serde_json::to_string(&transfer(ALICE, "6")).unwrap();
+        assert!(engine.evaluate(&call(json!({})), &tx, &[]).is_ok());
+
+        // Outside a component the keyvalue ledger is unavailable, so windows fail closed
+        config.policy =
+            Some(Policy::new().with_max_value_per_window("10", Duration::from_secs(60)));
+        let engine = PolicyEngine::from_config(&config).unwrap();
+        let denial = engine.evaluate(&call(json!({})), &tx, &[]).unwrap_err();
+        assert_eq!(rules(&denial.violations), [PolicyRule::SpendingLedger]);
+        assert!(denial.violations[0]
+            .message
+            .contains("no persistent spending ledger"));
+    }
+}
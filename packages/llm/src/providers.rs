@@ -0,0 +1,1182 @@
+//! LLM provider backends
+//!
+//! A [`Provider`] knows where its chat and embeddings endpoints live, which
+//! auth headers it needs and how to map requests and responses to and from
+//! its wire format.
+
+use crate::client::Message;
+use crate::config::LlmOptions;
+use crate::errors::LlmError;
+use crate::schema::schema_name;
+use crate::tools::{Tool, ToolCall, ToolCallFunction};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+/// Default Ollama endpoint used when neither the config nor the environment sets one
+pub const DEFAULT_OLLAMA_URL: &str = "http://localhost:11434";
+
+/// Default OpenAI endpoint
+pub const DEFAULT_OPENAI_URL: &str = "https://api.openai.com";
+
+/// Default Anthropic endpoint
+pub const DEFAULT_ANTHROPIC_URL: &str = "https://api.anthropic.com";
+
+/// Anthropic API version sent when none is configured
+pub const DEFAULT_ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// Anthropic requires `max_tokens`, so use this when the options leave it unset
+const ANTHROPIC_DEFAULT_MAX_TOKENS: u32 = 1024;
+
+/// The wire protocol spoken by an inference server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ProviderKind {
+    /// Ollama's native `/api/chat`
+    #[default]
+    Ollama,
+    /// OpenAI-compatible `/v1/chat/completions` (OpenAI, vLLM, llama.cpp server, LM Studio)
+    #[serde(alias = "openai-compatible", alias = "open_ai")]
+    OpenAi,
+    /// Anthropic's Messages API
+    Anthropic,
+}
+
+/// What the model behind a provider supports
+///
+/// Tool-call and structured-output handling is driven by this descriptor
+/// rather than guessed from the model name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ModelCapabilities {
+    /// The model accepts a `tools` list and returns structured tool calls.
+    /// When false, tools are described in the prompt and calls are parsed from the reply text.
+    pub native_tools: bool,
+
+    /// The model may request several tool calls in one turn.
+    /// When false, only the first call of a turn is executed.
+    pub parallel_tool_calls: bool,
+
+    /// The provider can constrain output to a JSON schema.
+    /// When false, the schema is given to the model as an instruction instead.
+    pub json_schema_output: bool,
+}
+
+impl ModelCapabilities {
+    /// Defaults for a provider kind, assuming a tool-capable model
+    pub fn for_kind(kind: ProviderKind) -> Self {
+        match kind {
+            ProviderKind::Ollama | ProviderKind::OpenAi => Self {
+                native_tools: true,
+                parallel_tool_calls: true,
+                json_schema_output: true,
+            },
+            ProviderKind::Anthropic => Self {
+                native_tools: true,
+                parallel_tool_calls: true,
+                json_schema_output: false,
+            },
+        }
+    }
+
+    /// Set native tool support
+    pub fn with_native_tools(mut self, native_tools: bool) -> Self {
+        self.native_tools = native_tools;
+        self
+    }
+
+    /// Set parallel tool call support
+    pub fn with_parallel_tool_calls(mut self, parallel_tool_calls: bool) -> Self {
+        self.parallel_tool_calls = parallel_tool_calls;
+        self
+    }
+
+    /// Set JSON schema output support
+    pub fn with_json_schema_output(mut self, json_schema_output: bool) -> Self {
+        self.json_schema_output = json_schema_output;
+        self
+    }
+}
+
+impl Default for ModelCapabilities {
+    fn default() -> Self {
+        Self::for_kind(ProviderKind::default())
+    }
+}
+
+/// Token counts reported by the provider for a single request
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    /// Tokens consumed by the prompt
+    pub prompt_tokens: u32,
+    /// Tokens generated in the reply
+    pub completion_tokens: u32,
+}
+
+impl Usage {
+    /// Prompt and completion tokens combined
+    pub fn total_tokens(&self) -> u32 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+/// A parsed chat reply together with its token usage
+#[derive(Debug, Clone)]
+pub struct ChatCompletion {
+    /// The assistant's message
+    pub message: Message,
+    /// Token usage, if the provider reported it
+    pub usage: Option<Usage>,
+}
+
+/// Provider selection and endpoint configuration
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Provider {
+    /// Which wire protocol to use
+    #[serde(default)]
+    pub kind: ProviderKind,
+
+    /// Base URL of the server, e.g. `http://localhost:11434` or `http://vllm:8000`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_url: Option<String>,
+
+    /// API key sent with each request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+
+    /// Name of an environment variable holding the API key, so keys stay out of published configs
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key_env: Option<String>,
+
+    /// API version header (Anthropic only)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_version: Option<String>,
+
+    /// What the configured model supports, overriding the provider defaults
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<ModelCapabilities>,
+}
+
+impl Provider {
+    /// Create a provider of the given kind with default endpoint settings
+    pub fn new(kind: ProviderKind) -> Self {
+        Self {
+            kind,
+            ..Default::default()
+        }
+    }
+
+    /// Ollama at the given base URL
+    pub fn ollama(base_url: impl Into<String>) -> Self {
+        Self::new(ProviderKind::Ollama).with_base_url(base_url)
+    }
+
+    /// An OpenAI-compatible server at the given base URL
+    pub fn openai(base_url: impl Into<String>) -> Self {
+        Self::new(ProviderKind::OpenAi).with_base_url(base_url)
+    }
+
+    /// Anthropic's hosted Messages API
+    pub fn anthropic(api_key: impl Into<String>) -> Self {
+        Self::new(ProviderKind::Anthropic).with_api_key(api_key)
+    }
+
+    /// Set the base URL
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = Some(base_url.into());
+        self
+    }
+
+    /// Set the API key
+    pub fn with_api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Read the API key from an environment variable at request time
+    pub fn with_api_key_env(mut self, var: impl Into<String>) -> Self {
+        self.api_key_env = Some(var.into());
+        self
+    }
+
+    /// Set the API version header
+    pub fn with_api_version(mut self, api_version: impl Into<String>) -> Self {
+        self.api_version = Some(api_version.into());
+        self
+    }
+
+    /// Declare what the configured model supports
+    pub fn with_capabilities(mut self, capabilities: ModelCapabilities) -> Self {
+        self.capabilities = Some(capabilities);
+        self
+    }
+
+    /// Capabilities of the configured model, falling back to the provider defaults
+    pub fn capabilities(&self) -> ModelCapabilities {
+        self.capabilities
+            .unwrap_or_else(|| ModelCapabilities::for_kind(self.kind))
+    }
+
+    /// Resolve the base URL, falling back to `WAVS_ENV_OLLAMA_API_URL` for Ollama
+    pub fn base_url(&self) -> String {
+        let url = match (&self.base_url, self.kind) {
+            (Some(url), _) => url.clone(),
+            (None, ProviderKind::Ollama) => std::env::var("WAVS_ENV_OLLAMA_API_URL")
+                .unwrap_or_else(|_| DEFAULT_OLLAMA_URL.to_string()),
+            (None, ProviderKind::OpenAi) => DEFAULT_OPENAI_URL.to_string(),
+            (None, ProviderKind::Anthropic) => DEFAULT_ANTHROPIC_URL.to_string(),
+        };
+
+        url.trim_matches('"').trim_end_matches('/').to_string()
+    }
+
+    /// Join a versioned API path onto the base URL without doubling a trailing `/v1`
+    fn versioned_url(&self, path: &str) -> String {
+        let base = self.base_url();
+        match base.strip_suffix("/v1") {
+            Some(stripped) => format!("{}/v1/{}", stripped, path),
+            None => format!("{}/v1/{}", base, path),
+        }
+    }
+
+    /// Full URL of the chat endpoint
+    pub fn chat_url(&self) -> String {
+        match self.kind {
+            ProviderKind::Ollama => format!("{}/api/chat", self.base_url()),
+            ProviderKind::OpenAi => self.versioned_url("chat/completions"),
+            ProviderKind::Anthropic => self.versioned_url("messages"),
+        }
+    }
+
+    /// Full URL of the embeddings endpoint; Anthropic has none
+    pub fn embeddings_url(&self) -> Result<String, LlmError> {
+        match self.kind {
+            ProviderKind::Ollama => Ok(format!("{}/api/embed", self.base_url())),
+            ProviderKind::OpenAi => Ok(self.versioned_url("embeddings")),
+            ProviderKind::Anthropic => Err(LlmError::ConfigError(
+                "Anthropic does not serve embeddings; use an Ollama or OpenAI-compatible provider"
+                    .to_string(),
+            )),
+        }
+    }
+
+    /// Resolve the API key from the config or the configured environment variable
+    pub fn resolve_api_key(&self) -> Option<String> {
+        self.api_key.clone().or_else(|| {
+            self.api_key_env
+                .as_ref()
+                .and_then(|var| std::env::var(var).ok())
+        })
+    }
+
+    /// HTTP headers required by this provider
+    pub fn headers(&self) -> Vec<(String, String)> {
+        let mut headers = vec![("Content-Type".to_string(), "application/json".to_string())];
+        let api_key = self.resolve_api_key();
+
+        match self.kind {
+            ProviderKind::Ollama | ProviderKind::OpenAi => {
+                if let Some(key) = api_key {
+                    headers.push(("Authorization".to_string(), format!("Bearer {}", key)));
+                }
+            }
+            ProviderKind::Anthropic => {
+                if let Some(key) = api_key {
+                    headers.push(("x-api-key".to_string(), key));
+                }
+                headers.push((
+                    "anthropic-version".to_string(),
+                    self.api_version
+                        .clone()
+                        .unwrap_or_else(|| DEFAULT_ANTHROPIC_VERSION.to_string()),
+                ));
+            }
+        }
+
+        headers
+    }
+
+    /// Build the chat request body in this provider's format
+    ///
+    /// `format` is a JSON schema the response should follow, if any.
+    pub fn chat_body(
+        &self,
+        model: &str,
+        messages: &[Message],
+        tools: Option<&[Tool]>,
+        options: &LlmOptions,
+        format: Option<&Value>,
+    ) -> Value {
+        let tools = tools.filter(|t| !t.is_empty());
+
+        match self.kind {
+            ProviderKind::Ollama => {
+                let mut body = json!({
+                    "model": model,
+                    "messages": messages.iter().map(ollama_message).collect::<Vec<_>>(),
+                    "stream": false,
+                });
+                apply_ollama_options(&mut body, options);
+                if let Some(tools) = tools {
+                    body["tools"] = json!(tools);
+                }
+                if let Some(format) = format {
+                    body["format"] = format.clone();
+                }
+                body
+            }
+            ProviderKind::OpenAi => {
+                let mut body = json!({
+                    "model": model,
+                    "messages": messages.iter().map(openai_message).collect::<Vec<_>>(),
+                    "stream": false,
+                });
+                apply_openai_options(&mut body, options);
+                if let Some(tools) = tools {
+                    body["tools"] = json!(tools);
+                    if !self.capabilities().parallel_tool_calls {
+                        body["parallel_tool_calls"] = json!(false);
+                    }
+                }
+                if let Some(format) = format {
+                    body["response_format"] = json!({
+                        "type": "json_schema",
+                        "json_schema": {
+                            "name": schema_name(format),
+                            "schema": format,
+                        },
+                    });
+                }
+                body
+            }
+            ProviderKind::Anthropic => {
+                let (system, messages) = anthropic_messages(messages);
+                let mut body = json!({
+                    "model": model,
+                    "messages": messages,
+                    "max_tokens": options.max_tokens.unwrap_or(ANTHROPIC_DEFAULT_MAX_TOKENS),
+                });
+                if let Some(system) = system {
+                    body["system"] = json!(system);
+                }
+                apply_anthropic_options(&mut body, options);
+                if let Some(tools) = tools {
+                    body["tools"] = json!(tools
+                        .iter()
+                        .map(|tool| json!({
+                            "name": tool.function.name,
+                            "description": tool.function.description.clone().unwrap_or_default(),
+                            "input_schema": tool.function.parameters.clone()
+                                .unwrap_or_else(|| json!({"type": "object", "properties": {}})),
+                        }))
+                        .collect::<Vec<_>>());
+                }
+                body
+            }
+        }
+    }
+
+    /// Parse a chat response body into the assistant's message and token usage
+    pub fn parse_chat_response(&self, body: &[u8]) -> Result<ChatCompletion, LlmError> {
+        let value: Value = serde_json::from_slice(body)
+            .map_err(|e| LlmError::ParseError(format!("Failed to parse response: {}", e)))?;
+
+        let count = |pointer: &str| value.pointer(pointer).and_then(|v| v.as_u64());
+        let (message, prompt, completion) = match self.kind {
+            ProviderKind::Ollama => (
+                value.get("message").cloned(),
+                count("/prompt_eval_count"),
+                count("/eval_count"),
+            ),
+            ProviderKind::OpenAi => (
+                value.pointer("/choices/0/message").cloned(),
+                count("/usage/prompt_tokens"),
+                count("/usage/completion_tokens"),
+            ),
+            ProviderKind::Anthropic => (
+                None,
+                count("/usage/input_tokens"),
+                count("/usage/output_tokens"),
+            ),
+        };
+
+        let message = match self.kind {
+            ProviderKind::Anthropic => parse_anthropic_message(&value)?,
+            _ => {
+                let message = message
+                    .ok_or_else(|| LlmError::ParseError("Response has no message".to_string()))?;
+                serde_json::from_value(message)
+                    .map_err(|e| LlmError::ParseError(format!("Failed to parse message: {}", e)))?
+            }
+        };
+
+        let usage = match (prompt, completion) {
+            (None, None) => None,
+            (prompt, completion) => Some(Usage {
+                prompt_tokens: prompt.unwrap_or_default() as u32,
+                completion_tokens: completion.unwrap_or_default() as u32,
+            }),
+        };
+
+        Ok(ChatCompletion { message, usage })
+    }
+
+    /// Build the embeddings request body for a batch of inputs
+    ///
+    /// `dimensions` asks models that support it for shorter vectors.
+    pub fn embeddings_body(
+        &self,
+        model: &str,
+        inputs: &[String],
+        dimensions: Option<u32>,
+        options: &LlmOptions,
+    ) -> Value {
+        let mut body = json!({
+            "model": model,
+            "input": inputs,
+        });
+        if let Some(dimensions) = dimensions {
+            body["dimensions"] = json!(dimensions);
+        }
+        match self.kind {
+            ProviderKind::Ollama => {
+                if let Some(keep_alive) = &options.keep_alive {
+                    body["keep_alive"] = json!(keep_alive);
+                }
+            }
+            ProviderKind::OpenAi | ProviderKind::Anthropic => {
+                body["encoding_format"] = json!("float");
+            }
+        }
+        body
+    }
+
+    /// Parse an embeddings response into one vector per input, in input order,
+    /// and the prompt tokens used
+    pub fn parse_embeddings_response(
+        &self,
+        body: &[u8],
+    ) -> Result<(Vec<Vec<f32>>, Option<u32>), LlmError> {
+        let value: Value = serde_json::from_slice(body)
+            .map_err(|e| LlmError::ParseError(format!("Failed to parse response: {}", e)))?;
+        let parse = |vector: &Value| -> Result<Vec<f32>, LlmError> {
+            serde_json::from_value(vector.clone())
+                .map_err(|e| LlmError::ParseError(format!("Invalid embedding: {}", e)))
+        };
+
+        match self.kind {
+            ProviderKind::Ollama => {
+                let vectors = value
+                    .get("embeddings")
+                    .and_then(Value::as_array)
+                    .ok_or_else(|| LlmError::ParseError("Response has no embeddings".to_string()))?
+                    .iter()
+                    .map(parse)
+                    .collect::<Result<_, _>>()?;
+                let tokens = value.get("prompt_eval_count").and_then(Value::as_u64);
+                Ok((vectors, tokens.map(|t| t as u32)))
+            }
+            ProviderKind::OpenAi | ProviderKind::Anthropic => {
+                let data = value
+                    .get("data")
+                    .and_then(Value::as_array)
+                    .ok_or_else(|| LlmError::ParseError("Response has no data".to_string()))?;
+                // Entries carry their input's index and need not arrive in order
+                let mut indexed = data
+                    .iter()
+                    .enumerate()
+                    .map(|(i, entry)| {
+                        let index = entry
+                            .get("index")
+                            .and_then(Value::as_u64)
+                            .map_or(i, |index| index as usize);
+                        let vector = entry.get("embedding").ok_or_else(|| {
+                            LlmError::ParseError("Entry has no embedding".to_string())
+                        })?;
+                        Ok((index, parse(vector)?))
+                    })
+                    .collect::<Result<Vec<_>, LlmError>>()?;
+                indexed.sort_by_key(|(index, _)| *index);
+                let tokens = value
+                    .pointer("/usage/prompt_tokens")
+                    .and_then(Value::as_u64);
+                Ok((
+                    indexed.into_iter().map(|(_, v)| v).collect(),
+                    tokens.map(|t| t as u32),
+                ))
+            }
+        }
+    }
+}
+
+/// Ollama takes sampling parameters inside `options`, with its own names for
+/// the token limits, and `keep_alive` at the top level
+fn apply_ollama_options(body: &mut Value, options: &LlmOptions) {
+    let mut ollama = Map::new();
+    let mut set = |key: &str, value: Option<Value>| {
+        if let Some(value) = value {
+            ollama.insert(key.to_string(), value);
+        }
+    };
+    set("temperature", options.temperature.map(|v| json!(v)));
+    set("top_p", options.top_p.map(|v| json!(v)));
+    set("top_k", options.top_k.map(|v| json!(v)));
+    set("min_p", options.min_p.map(|v| json!(v)));
+    set("repeat_penalty", options.repeat_penalty.map(|v| json!(v)));
+    set("seed", options.seed.map(|v| json!(v)));
+    set("num_predict", options.max_tokens.map(|v| json!(v)));
+    set("num_ctx", options.context_window.map(|v| json!(v)));
+    set("stop", options.stop.as_ref().map(|v| json!(v)));
+    ollama.extend(options.extra.clone());
+
+    if !ollama.is_empty() {
+        body["options"] = Value::Object(ollama);
+    }
+    if let Some(keep_alive) = &options.keep_alive {
+        body["keep_alive"] = json!(keep_alive);
+    }
+}
+
+/// OpenAI-compatible servers take parameters at the top level. `top_k`,
+/// `min_p` and `repetition_penalty` are extensions understood by vLLM and
+/// llama.cpp; the context window is fixed by the server.
+fn apply_openai_options(body: &mut Value, options: &LlmOptions) {
+    if let Some(temp) = options.temperature {
+        body["temperature"] = json!(temp);
+    }
+    if let Some(max_tokens) = options.max_tokens {
+        body["max_tokens"] = json!(max_tokens);
+    }
+    if let Some(top_p) = options.top_p {
+        body["top_p"] = json!(top_p);
+    }
+    if let Some(seed) = options.seed {
+        body["seed"] = json!(seed);
+    }
+    if let Some(stop) = &options.stop {
+        body["stop"] = json!(stop);
+    }
+    if let Some(top_k) = options.top_k {
+        body["top_k"] = json!(top_k);
+    }
+    if let Some(min_p) = options.min_p {
+        body["min_p"] = json!(min_p);
+    }
+    if let Some(repeat_penalty) = options.repeat_penalty {
+        body["repetition_penalty"] = json!(repeat_penalty);
+    }
+    apply_extra_options(body, options);
+}
+
+/// Anthropic has no seed, `min_p` or repeat penalty, and calls stop sequences `stop_sequences`
+fn apply_anthropic_options(body: &mut Value, options: &LlmOptions) {
+    if let Some(temp) = options.temperature {
+        body["temperature"] = json!(temp);
+    }
+    if let Some(top_p) = options.top_p {
+        body["top_p"] = json!(top_p);
+    }
+    if let Some(top_k) = options.top_k {
+        body["top_k"] = json!(top_k);
+    }
+    if let Some(stop) = &options.stop {
+        body["stop_sequences"] = json!(stop);
+    }
+    apply_extra_options(body, options);
+}
+
+fn apply_extra_options(body: &mut Value, options: &LlmOptions) {
+    for (key, value) in &options.extra {
+        body[key.as_str()] = value.clone();
+    }
+}
+
+/// Tool call arguments as a JSON value, falling back to the raw string
+fn arguments_value(function: &ToolCallFunction) -> Value {
+    if function.arguments.trim().is_empty() {
+        return json!({});
+    }
+    serde_json::from_str(&function.arguments).unwrap_or_else(|_| json!(function.arguments))
+}
+
+/// Ollama expects tool call arguments as objects rather than JSON strings
+fn ollama_message(message: &Message) -> Value {
+    let mut value = json!({
+        "role": message.role,
+        "content": message.content.clone().unwrap_or_default(),
+    });
+    if let Some(tool_calls) = &message.tool_calls {
+        value["tool_calls"] = json!(tool_calls
+            .iter()
+            .map(|call| json!({
+                "function": {
+                    "name": call.function.name,
+                    "arguments": arguments_value(&call.function),
+                }
+            }))
+            .collect::<Vec<_>>());
+    }
+    if let Some(name) = &message.name {
+        if message.role == "tool" {
+            value["tool_name"] = json!(name);
+        }
+    }
+    if !message.images.is_empty() {
+        value["images"] = json!(message
+            .images
+            .iter()
+            .map(|image| image.data.as_str())
+            .collect::<Vec<_>>());
+    }
+    value
+}
+
+/// OpenAI messages match our own layout, minus `name` on tool results
+///
+/// Messages with images send their content as an array of text and
+/// `image_url` parts.
+fn openai_message(message: &Message) -> Value {
+    let content = if message.images.is_empty() {
+        json!(message.content)
+    } else {
+        let mut parts = Vec::new();
+        if let Some(text) = message.content.as_ref().filter(|c| !c.is_empty()) {
+            parts.push(json!({ "type": "text", "text": text }));
+        }
+        for image in &message.images {
+            parts.push(json!({
+                "type": "image_url",
+                "image_url": { "url": image.data_uri() }
+            }));
+        }
+        json!(parts)
+    };
+    let mut value = json!({
+        "role": message.role,
+        "content": content,
+    });
+    if let Some(tool_calls) = &message.tool_calls {
+        value["tool_calls"] = json!(tool_calls);
+    }
+    if let Some(id) = &message.tool_call_id {
+        value["tool_call_id"] = json!(id);
+    }
+    if let Some(name) = &message.name {
+        if message.role != "tool" {
+            value["name"] = json!(name);
+        }
+    }
+    value
+}
+
+/// Split out system prompts and convert the rest to Anthropic content blocks
+///
+/// Consecutive tool results are merged into a single user turn, as the API requires.
+fn anthropic_messages(messages: &[Message]) -> (Option<String>, Vec<Value>) {
+    let mut system = Vec::new();
+    let mut converted: Vec<Value> = Vec::new();
+
+    for message in messages {
+        match message.role.as_str() {
+            "system" => {
+                if let Some(content) = &message.content {
+                    system.push(content.clone());
+                }
+            }
+            "tool" => {
+                let block = json!({
+                    "type": "tool_result",
+                    "tool_use_id": message.tool_call_id.clone().unwrap_or_default(),
+                    "content": message.content.clone().unwrap_or_default(),
+                });
+                let merged = converted.last_mut().is_some_and(|last| {
+                    let is_result_turn = last["role"] == "user"
+                        && last["content"]
+                            .as_array()
+                            .and_then(|blocks| blocks.first())
+                            .is_some_and(|b| b["type"] == "tool_result");
+                    if is_result_turn {
+                        if let Some(blocks) = last["content"].as_array_mut() {
+                            blocks.push(block.clone());
+                        }
+                    }
+                    is_result_turn
+                });
+                if !merged {
+                    converted.push(json!({ "role": "user", "content": [block] }));
+                }
+            }
+            role => {
+                // Images go before the text that refers to them
+                let mut blocks: Vec<Value> = message
+                    .images
+                    .iter()
+                    .map(|image| {
+                        json!({
+                            "type": "image",
+                            "source": {
+                                "type": "base64",
+                                "media_type": image.media_type,
+                                "data": image.data,
+                            }
+                        })
+                    })
+                    .collect();
+                if let Some(content) = message.content.as_ref().filter(|c| !c.is_empty()) {
+                    blocks.push(json!({ "type": "text", "text": content }));
+                }
+                for call in message.tool_calls.iter().flatten() {
+                    blocks.push(json!({
+                        "type": "tool_use",
+                        "id": call.id,
+                        "name": call.function.name,
+                        "input": arguments_value(&call.function),
+                    }));
+                }
+                converted.push(json!({ "role": role, "content": blocks }));
+            }
+        }
+    }
+
+    let system = if system.is_empty() {
+        None
+    } else {
+        Some(system.join("\n\n"))
+    };
+
+    (system, converted)
+}
+
+/// Convert an Anthropic response's content blocks into a Message
+fn parse_anthropic_message(value: &Value) -> Result<Message, LlmError> {
+    let blocks = value
+        .get("content")
+        .and_then(|c| c.as_array())
+        .ok_or_else(|| LlmError::ParseError("Response has no content blocks".to_string()))?;
+
+    let mut text = String::new();
+    let mut tool_calls = Vec::new();
+
+    for block in blocks {
+        match block["type"].as_str() {
+            Some("text") => text.push_str(block["text"].as_str().unwrap_or_default()),
+            Some("tool_use") => tool_calls.push(ToolCall {
+                id: block["id"].as_str().unwrap_or_default().to_string(),
+                tool_type: "function".to_string(),
+                function: ToolCallFunction {
+                    name: block["name"].as_str().unwrap_or_default().to_string(),
+                    arguments: block["input"].to_string(),
+                },
+            }),
+            _ => {}
+        }
+    }
+
+    let mut message = Message::assistant(text);
+    if !tool_calls.is_empty() {
+        message.tool_calls = Some(tool_calls);
+    }
+
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::Image;
+    use crate::tools::Tools;
+
+    fn tool_call_message() -> Message {
+        let mut message = Message::assistant("");
+        message.tool_calls = Some(vec![ToolCall {
+            id: "call_1".to_string(),
+            tool_type: "function".to_string(),
+            function: ToolCallFunction {
+                name: "get_weather".to_string(),
+                arguments: r#"{"location":"Paris"}"#.to_string(),
+            },
+        }]);
+        message
+    }
+
+    #[test]
+    fn test_chat_urls() {
+        assert_eq!(
+            Provider::ollama("http://ollama:11434/").chat_url(),
+            "http://ollama:11434/api/chat"
+        );
+        assert_eq!(
+            Provider::openai("http://vllm:8000").chat_url(),
+            "http://vllm:8000/v1/chat/completions"
+        );
+        assert_eq!(
+            Provider::openai("http://vllm:8000/v1").chat_url(),
+            "http://vllm:8000/v1/chat/completions"
+        );
+        assert_eq!(
+            Provider::anthropic("key").chat_url(),
+            "https://api.anthropic.com/v1/messages"
+        );
+    }
+
+    #[test]
+    fn test_embeddings() {
+        let options = LlmOptions::new().with_keep_alive("5m");
+        let inputs = vec!["a".to_string(), "b".to_string()];
+
+        let ollama = Provider::ollama("http://ollama:11434");
+        assert_eq!(
+            ollama.embeddings_url().unwrap(),
+            "http://ollama:11434/api/embed"
+        );
+        assert_eq!(
+            ollama.embeddings_body("nomic-embed-text", &inputs, None, &options),
+            json!({"model": "nomic-embed-text", "input": ["a", "b"], "keep_alive": "5m"})
+        );
+        let response = json!({"embeddings": [[0.1, 0.2], [0.3, 0.4]], "prompt_eval_count": 4});
+        assert_eq!(
+            ollama
+                .parse_embeddings_response(response.to_string().as_bytes())
+                .unwrap(),
+            (vec![vec![0.1, 0.2], vec![0.3, 0.4]], Some(4))
+        );
+
+        let openai = Provider::openai("http://vllm:8000/v1");
+        assert_eq!(
+            openai.embeddings_url().unwrap(),
+            "http://vllm:8000/v1/embeddings"
+        );
+        assert_eq!(
+            openai.embeddings_body("text-embedding-3-small", &inputs, Some(256), &options),
+            json!({"model": "text-embedding-3-small", "input": ["a", "b"],
+                   "dimensions": 256, "encoding_format": "float"})
+        );
+        let response = json!({
+            "data": [{"index": 1, "embedding": [0.3, 0.4]}, {"index": 0, "embedding": [0.1, 0.2]}],
+            "usage": {"prompt_tokens": 2, "total_tokens": 2}
+        });
+        assert_eq!(
+            openai
+                .parse_embeddings_response(response.to_string().as_bytes())
+                .unwrap(),
+            (vec![vec![0.1, 0.2], vec![0.3, 0.4]], Some(2))
+        );
+        assert!(openai.parse_embeddings_response(b"{}").is_err());
+
+        assert!(matches!(
+            Provider::anthropic("key").embeddings_url(),
+            Err(LlmError::ConfigError(_))
+        ));
+    }
+
+    #[test]
+    fn test_headers() {
+        let headers = Provider::openai("http://localhost:8000")
+            .with_api_key("secret")
+            .headers();
+        assert!(headers.contains(&("Authorization".to_string(), "Bearer secret".to_string())));
+
+        let headers = Provider::anthropic("secret").headers();
+        assert!(headers.contains(&("x-api-key".to_string(), "secret".to_string())));
+        assert!(headers.iter().any(|(k, _)| k == "anthropic-version"));
+
+        let headers = Provider::ollama("http://localhost:11434").headers();
+        assert!(!headers.iter().any(|(k, _)| k == "Authorization"));
+    }
+
+    #[test]
+    fn test_provider_deserialize() {
+        let provider: Provider =
+            serde_json::from_str(r#"{"kind": "openai", "base_url": "http://lmstudio:1234"}"#)
+                .unwrap();
+        assert_eq!(provider.kind, ProviderKind::OpenAi);
+        assert_eq!(provider.base_url(), "http://lmstudio:1234");
+
+        let provider: Provider = serde_json::from_str(r#"{}"#).unwrap();
+        assert_eq!(provider.kind, ProviderKind::Ollama);
+    }
+
+    #[test]
+    fn test_ollama_body_uses_object_arguments() {
+        let provider = Provider::ollama("http://localhost:11434");
+        let messages = vec![Message::user("Weather?"), tool_call_message()];
+        let body = provider.chat_body("llama3.2", &messages, None, &LlmOptions::default(), None);
+
+        assert_eq!(body["model"], "llama3.2");
+        assert_eq!(
+            body["messages"][1]["tool_calls"][0]["function"]["arguments"]["location"],
+            "Paris"
+        );
+    }
+
+    #[test]
+    fn test_openai_roundtrip() {
+        let provider = Provider::openai("http://localhost:8000");
+        let tools = vec![Tools::send_eth_tool()];
+        let body = provider.chat_body(
+            "gpt-4o",
+            &[Message::user("Hi")],
+            Some(&tools),
+            &LlmOptions::default(),
+            None,
+        );
+        assert_eq!(body["tools"][0]["function"]["name"], "send_eth");
+
+        let response = br#"{
+            "choices": [{
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_abc",
+                        "type": "function",
+                        "function": {"name": "send_eth", "arguments": "{\"to\":\"0x1\"}"}
+                    }]
+                }
+            }]
+        }"#;
+        let message = provider.parse_chat_response(response).unwrap().message;
+        let calls = message.tool_calls.unwrap();
+        assert_eq!(calls[0].id, "call_abc");
+        assert_eq!(calls[0].function.arguments, r#"{"to":"0x1"}"#);
+    }
+
+    #[test]
+    fn test_structured_format() {
+        let schema = json!({"title": "Vote", "type": "object", "properties": {}});
+        let messages = [Message::user("Hi")];
+        let options = LlmOptions::default();
+
+        let body =
+            Provider::default().chat_body("llama3.2", &messages, None, &options, Some(&schema));
+        assert_eq!(body["format"], schema);
+
+        let body = Provider::openai("http://vllm:8000").chat_body(
+            "qwen",
+            &messages,
+            None,
+            &options,
+            Some(&schema),
+        );
+        assert_eq!(body["response_format"]["type"], "json_schema");
+        assert_eq!(body["response_format"]["json_schema"]["name"], "Vote");
+        assert_eq!(body["response_format"]["json_schema"]["schema"], schema);
+    }
+
+    #[test]
+    fn test_option_mapping() {
+        let options = LlmOptions::new()
+            .with_temperature(0.0)
+            .with_max_tokens(256)
+            .with_seed(42)
+            .with_context_window(8192)
+            .with_top_k(20)
+            .with_repeat_penalty(1.1)
+            .with_stop(vec!["</answer>".to_string()])
+            .with_keep_alive("10m")
+            .with_extra("num_gpu", json!(1));
+        let messages = [Message::user("Hi")];
+
+        let body = Provider::default().chat_body("llama3.2", &messages, None, &options, None);
+        assert_eq!(body["options"]["seed"], 42);
+        assert_eq!(body["options"]["num_predict"], 256);
+        assert_eq!(body["options"]["num_ctx"], 8192);
+        assert_eq!(body["options"]["top_k"], 20);
+        assert_eq!(body["options"]["stop"][0], "</answer>");
+        assert_eq!(body["options"]["num_gpu"], 1);
+        assert_eq!(body["keep_alive"], "10m");
+        assert!(body.get("seed").is_none());
+        assert!(body.get("max_tokens").is_none());
+
+        let body =
+            Provider::openai("http://vllm:8000").chat_body("qwen", &messages, None, &options, None);
+        assert_eq!(body["seed"], 42);
+        assert_eq!(body["max_tokens"], 256);
+        assert_eq!(body["stop"][0], "</answer>");
+        assert_eq!(body["repetition_penalty"], json!(1.1f32));
+        assert_eq!(body["num_gpu"], 1);
+        assert!(body.get("options").is_none());
+        assert!(body.get("keep_alive").is_none());
+
+        let body = Provider::anthropic("k").chat_body("claude", &messages, None, &options, None);
+        assert_eq!(body["max_tokens"], 256);
+        assert_eq!(body["top_k"], 20);
+        assert_eq!(body["stop_sequences"][0], "</answer>");
+        assert!(body.get("seed").is_none());
+
+        // Nothing set means no options object at all
+        let body = Provider::default().chat_body(
+            "llama3.2",
+            &messages,
+            None,
+            &LlmOptions::default(),
+            None,
+        );
+        assert!(body.get("options").is_none());
+    }
+
+    #[test]
+    fn test_capabilities() {
+        assert!(Provider::default().capabilities().json_schema_output);
+        assert!(!Provider::anthropic("k").capabilities().json_schema_output);
+
+        let provider: Provider = serde_json::from_str(
+            r#"{
+                "kind": "openai",
+                "base_url": "http://llamacpp:8080",
+                "capabilities": {
+                    "native_tools": true,
+                    "parallel_tool_calls": false,
+                    "json_schema_output": true
+                }
+            }"#,
+        )
+        .unwrap();
+        assert!(!provider.capabilities().parallel_tool_calls);
+
+        let body = provider.chat_body(
+            "qwen2.5",
+            &[Message::user("Hi")],
+            Some(&[Tools::send_eth_tool()]),
+            &LlmOptions::default(),
+            None,
+        );
+        assert_eq!(body["parallel_tool_calls"], false);
+    }
+
+    #[test]
+    fn test_parse_usage() {
+        let ollama = br#"{"message": {"role": "assistant", "content": "hi"}, "prompt_eval_count": 12, "eval_count": 3}"#;
+        let completion = Provider::default().parse_chat_response(ollama).unwrap();
+        assert_eq!(
+            completion.usage,
+            Some(Usage {
+                prompt_tokens: 12,
+                completion_tokens: 3
+            })
+        );
+
+        let openai = br#"{"choices": [{"message": {"role": "assistant", "content": "hi"}}], "usage": {"prompt_tokens": 20, "completion_tokens": 5}}"#;
+        let completion = Provider::openai("http://x")
+            .parse_chat_response(openai)
+            .unwrap();
+        assert_eq!(completion.usage.unwrap().total_tokens(), 25);
+
+        let anthropic = br#"{"content": [{"type": "text", "text": "hi"}], "usage": {"input_tokens": 7, "output_tokens": 2}}"#;
+        let completion = Provider::anthropic("k")
+            .parse_chat_response(anthropic)
+            .unwrap();
+        assert_eq!(completion.usage.unwrap().prompt_tokens, 7);
+
+        let no_usage = br#"{"message": {"role": "assistant", "content": "hi"}}"#;
+        let completion = Provider::default().parse_chat_response(no_usage).unwrap();
+        assert!(completion.usage.is_none());
+    }
+
+    #[test]
+    fn test_image_messages() {
+        let image = Image::from_base64("iVBORw0KGgo=", "image/png");
+        let messages = [Message::user_with_images(
+            "What is in this picture?",
+            vec![image],
+        )];
+
+        let body =
+            Provider::default().chat_body("llava", &messages, None, &LlmOptions::default(), None);
+        assert_eq!(body["messages"][0]["content"], "What is in this picture?");
+        assert_eq!(body["messages"][0]["images"], json!(["iVBORw0KGgo="]));
+
+        let body = Provider::openai("http://vllm:8000").chat_body(
+            "qwen-vl",
+            &messages,
+            None,
+            &LlmOptions::default(),
+            None,
+        );
+        assert_eq!(
+            body["messages"][0]["content"],
+            json!([
+                {"type": "text", "text": "What is in this picture?"},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,iVBORw0KGgo="}}
+            ])
+        );
+        // Text-only messages keep plain string content
+        let body = Provider::openai("http://vllm:8000").chat_body(
+            "qwen-vl",
+            &[Message::user("Hi")],
+            None,
+            &LlmOptions::default(),
+            None,
+        );
+        assert_eq!(body["messages"][0]["content"], "Hi");
+
+        let body = Provider::anthropic("key").chat_body(
+            "claude",
+            &messages,
+            None,
+            &LlmOptions::default(),
+            None,
+        );
+        let blocks = &body["messages"][0]["content"];
+        assert_eq!(
+            blocks[0],
+            json!({
+                "type": "image",
+                "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo="}
+            })
+        );
+        assert_eq!(blocks[1]["text"], "What is in this picture?");
+    }
+
+    #[test]
+    fn test_anthropic_mapping() {
+        let provider = Provider::anthropic("key");
+        let messages = vec![
+            Message::system("Be terse"),
+            Message::user("Weather in Paris?"),
+            tool_call_message(),
+            Message::tool_result(
+                "call_1".to_string(),
+                "get_weather".to_string(),
+                "Sunny".to_string(),
+            ),
+        ];
+        let body = provider.chat_body(
+            "claude-sonnet",
+            &messages,
+            Some(&[Tools::send_eth_tool()]),
+            &LlmOptions::default(),
+            None,
+        );
+
+        assert_eq!(body["system"], "Be terse");
+        assert_eq!(body["max_tokens"], ANTHROPIC_DEFAULT_MAX_TOKENS);
+        assert_eq!(body["messages"][1]["content"][0]["type"], "tool_use");
+        assert_eq!(
+            body["messages"][1]["content"][0]["input"]["location"],
+            "Paris"
+        );
+        assert_eq!(body["messages"][2]["role"], "user");
+        assert_eq!(body["messages"][2]["content"][0]["tool_use_id"], "call_1");
+        assert!(body["tools"][0]["input_schema"].is_object());
+
+        let response = br#"{
+            "role": "assistant",
+            "content": [
+                {"type": "text", "text": "Let me check."},
+                {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {"location": "Paris"}}
+            ]
+        }"#;
+        let message = provider.parse_chat_response(response).unwrap().message;
+        assert_eq!(message.content.as_deref(), Some("Let me check."));
+        let calls = message.tool_calls.unwrap();
+        assert_eq!(calls[0].id, "toolu_1");
+        assert_eq!(calls[0].function.arguments, r#"{"location":"Paris"}"#);
+    }
+}
@@ -0,0 +1,287 @@
+//! Read-only contract calls
+//!
+//! `view` and `pure` functions are exposed to the model as tools (see
+//! [`crate::tools::Tools::read_tools_from_contract`]). Rather than producing a transaction,
+//! calling one runs an `eth_call` against the configured chain and hands the
+//! decoded return values back to the model, so an agent can check balances,
+//! allowances or prices before it decides what to do.
+
+use crate::config::Config;
+use crate::contracts::Contract;
+use crate::encoding::{decode_function_output, encode_function_args, named_function_args};
+use crate::errors::AgentError;
+use crate::registry::{ContractTool, ToolRegistry};
+use crate::tools::{CustomToolHandler, Tool, ToolCall, ToolFuture};
+use alloy_json_abi::Function;
+use alloy_network::Ethereum;
+use alloy_primitives::{Address, Bytes};
+use alloy_provider::Provider;
+use alloy_rpc_types::{TransactionInput, TransactionRequest};
+use serde_json::Value;
+use std::str::FromStr;
+use wavs_wasi_utils::evm::new_evm_provider;
+use wstd::runtime::block_on;
+
+/// Executes read tools for a set of contracts over JSON-RPC
+#[derive(Debug, Clone)]
+pub struct ContractReader {
+    rpc_url: String,
+    /// Built once, so tool calls do not re-parse the ABIs
+    registry: ToolRegistry,
+}
+
+impl ContractReader {
+    /// Read from `contracts` through the JSON-RPC endpoint at `rpc_url`
+    pub fn new(rpc_url: impl Into<String>, contracts: Vec<Contract>) -> Self {
+        Self {
+            rpc_url: rpc_url.into(),
+            registry: ToolRegistry::new(&contracts),
+        }
+    }
+
+    /// Reader for the config's contracts, if it has an `rpc_url`
+    pub fn from_config(config: &Config) -> Option<Self> {
+        config
+            .rpc_url
+            .as_ref()
+            .map(|url| Self::new(url.clone(), config.contracts.clone()))
+    }
+
+    /// JSON-RPC endpoint calls are sent to
+    pub fn rpc_url(&self) -> &str {
+        &self.rpc_url
+    }
+
+    /// Read tools for every `view` and `pure` function of the contracts
+    pub fn tools(&self) -> Vec<Tool> {
+        self.registry.read_tools()
+    }
+
+    /// The contract and read-only function a tool name refers to
+    pub fn resolve(&self, tool_name: &str) -> Option<ContractTool> {
+        self.read_tool(tool_name).cloned()
+    }
+
+    fn read_tool(&self, tool_name: &str) -> Option<&ContractTool> {
+        self.registry
+            .resolve(tool_name)
+            .filter(|tool| tool.is_read_only())
+    }
+
+    /// Call a read-only function and decode its return values into JSON
+    ///
+    /// `args` is an object keyed by argument name, as the model sends it.
+    pub fn call(
+        &self,
+        contract: &Contract,
+        function: &Function,
+        args: &Value,
+    ) -> Result<Value, AgentError> {
+        let to = contract_address(contract)?;
+        let data = encode_call(function, args)?;
+        let output = block_on(eth_call(self.rpc_url.clone(), to, data))?;
+        decode_function_output(function, &output)
+    }
+
+    /// Async version of [`ContractReader::call`]
+    pub async fn call_async(
+        &self,
+        contract: &Contract,
+        function: &Function,
+        args: &Value,
+    ) -> Result<Value, AgentError> {
+        let to = contract_address(contract)?;
+        let data = encode_call(function, args)?;
+        let output = eth_call(self.rpc_url.clone(), to, data).await?;
+        decode_function_output(function, &output)
+    }
+
+    /// The read tool a call refers to and its parsed arguments
+    fn parse_tool_call(&self, tool_call: &ToolCall) -> Result<(&ContractTool, Value), String> {
+        let tool = self
+            .read_tool(&tool_call.function.name)
+            .ok_or_else(|| format!("Unknown read tool: {}", tool_call.function.name))?;
+        let args: Value = serde_json::from_str(&tool_call.function.arguments)
+            .map_err(|e| format!("Failed to parse function arguments: {}", e))?;
+        Ok((tool, args))
+    }
+}
+
+async fn eth_call(rpc_url: String, to: Address, data: Bytes) -> Result<Bytes, AgentError> {
+    if cfg!(not(target_arch = "wasm32")) {
+        return Err(AgentError::Configuration(
+            "eth_call is only available inside a WASI component".to_string(),
+        ));
+    }
+
+    let provider = new_evm_provider::<Ethereum>(rpc_url);
+    let tx = TransactionRequest {
+        to: Some(to.into()),
+        input: TransactionInput::new(data),
+        ..Default::default()
+    };
+    provider
+        .call(tx)
+        .await
+        .map_err(|e| AgentError::ExternalService(format!("eth_call to {} failed: {}", to, e)))
+}
+
+fn contract_address(contract: &Contract) -> Result<Address, AgentError> {
+    Address::from_str(&contract.address).map_err(|_| {
+        AgentError::Contract(format!(
+            "Invalid address for contract {}: {}",
+            contract.name, contract.address
+        ))
+    })
+}
+
+/// Calldata (selector and arguments) for a call with named JSON arguments
+fn encode_call(function: &Function, args: &Value) -> Result<Bytes, AgentError> {
+    let args = named_function_args(function, args)?;
+    let mut data = function.selector().to_vec();
+    data.extend(encode_function_args(function, &args)?);
+    Ok(data.into())
+}
+
+impl CustomToolHandler for ContractReader {
+    fn can_handle(&self, tool_name: &str) -> bool {
+        self.read_tool(tool_name).is_some()
+    }
+
+    fn execute(&self, tool_call: &ToolCall) -> Result<String, String> {
+        let (tool, args) = self.parse_tool_call(tool_call)?;
+        let result = self.call(&tool.contract, &tool.function, &args)?;
+        Ok(result.to_string())
+    }
+
+    fn execute_async<'a>(&'a self, tool_call: &'a ToolCall) -> ToolFuture<'a> {
+        Box::pin(async move {
+            let (tool, args) = self.parse_tool_call(tool_call)?;
+            let result = self
+                .call_async(&tool.contract, &tool.function, &args)
+                .await?;
+            Ok(result.to_string())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::sol_value_to_json;
+    use crate::tools::ToolCallFunction;
+    use alloy_dyn_abi::{DynSolValue, JsonAbiExt};
+    use alloy_primitives::U256;
+    use serde_json::json;
+
+    const VAULT_ABI: &str = r#"[
+        {"name": "balanceOf", "type": "function", "stateMutability": "view",
+         "inputs": [{"name": "account", "type": "address"}],
+         "outputs": [{"name": "", "type": "uint256"}]},
+        {"name": "position", "type": "function", "stateMutability": "view",
+         "inputs": [{"name": "owner", "type": "address"}, {"name": "", "type": "uint8"}],
+         "outputs": [
+            {"name": "size", "type": "uint256"},
+            {"name": "terms", "type": "tuple", "components": [
+                {"name": "unlockAt", "type": "uint64"},
+                {"name": "revocable", "type": "bool"}
+            ]}
+         ]},
+        {"name": "deposit", "type": "function", "stateMutability": "payable",
+         "inputs": [], "outputs": []}
+    ]"#;
+
+    const OWNER: &str = "0x1234567890123456789012345678901234567890";
+
+    fn reader() -> ContractReader {
+        ContractReader::new(
+            "http://localhost:8545",
+            vec![Contract::new(
+                "Vault",
+                "0xb7278a61aa25c888815afc32ad3cc52ff24fe575",
+                VAULT_ABI,
+            )],
+        )
+    }
+
+    #[test]
+    fn test_read_tools_and_resolution() {
+        let reader = reader();
+        let names: Vec<String> = reader
+            .tools()
+            .into_iter()
+            .map(|t| t.function.name)
+            .collect();
+        assert_eq!(
+            names,
+            ["contract_vault_balanceOf", "contract_vault_position"]
+        );
+        assert!(reader.tools()[0]
+            .function
+            .description
+            .as_ref()
+            .unwrap()
+            .starts_with("Read balanceOf from the Vault contract"));
+
+        assert!(reader.can_handle("contract_vault_balanceOf"));
+        // Writes stay with the transaction tools
+        assert!(!reader.can_handle("contract_vault_deposit"));
+        assert!(!reader.can_handle("contract_other_balanceOf"));
+    }
+
+    #[test]
+    fn test_encode_call() {
+        let function = reader()
+            .resolve("contract_vault_position")
+            .unwrap()
+            .function;
+        let data = encode_call(&function, &json!({"arg1": 3, "owner": OWNER})).unwrap();
+        assert_eq!(&data[..4], function.selector().as_slice());
+
+        let decoded = function.abi_decode_input(&data[4..]).unwrap();
+        assert_eq!(sol_value_to_json(&decoded[0]), json!(OWNER));
+        assert_eq!(decoded[1], DynSolValue::Uint(U256::from(3), 8));
+
+        let err = encode_call(&function, &json!({"owner": OWNER})).unwrap_err();
+        assert!(err.to_string().contains("Missing argument 'arg1'"));
+    }
+
+    #[test]
+    fn test_decode_output() {
+        let reader = reader();
+        let balance_of = reader.resolve("contract_vault_balanceOf").unwrap().function;
+        let data = DynSolValue::Uint(U256::from(1500), 256).abi_encode();
+        assert_eq!(
+            decode_function_output(&balance_of, &data).unwrap(),
+            json!("1500")
+        );
+
+        let position = reader.resolve("contract_vault_position").unwrap().function;
+        let data = DynSolValue::Tuple(vec![
+            DynSolValue::Uint(U256::from(7), 256),
+            DynSolValue::Tuple(vec![
+                DynSolValue::Uint(U256::from(1_700_000_000u64), 64),
+                DynSolValue::Bool(true),
+            ]),
+        ])
+        .abi_encode_params();
+        assert_eq!(
+            decode_function_output(&position, &data).unwrap(),
+            json!({"size": "7", "terms": {"unlockAt": "1700000000", "revocable": true}})
+        );
+    }
+
+    #[test]
+    fn test_execute_outside_component() {
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            tool_type: "function".to_string(),
+            function: ToolCallFunction {
+                name: "contract_vault_balanceOf".to_string(),
+                arguments: json!({"account": OWNER}).to_string(),
+            },
+        };
+        let err = reader().execute(&call).unwrap_err();
+        assert!(err.contains("only available inside a WASI component"));
+    }
+}
@@ -0,0 +1,317 @@
+//! Tool names for contract functions
+//!
+//! Every contract function offered to the model needs a tool name that
+//! providers accept (OpenAI and Anthropic allow at most 64 characters from
+//! `[a-zA-Z0-9_-]`) and that maps back to exactly one function, even when a
+//! contract name contains underscores or a function is overloaded. Names are
+//! derived from the contract and function alone, so the same contract always
+//! gets the same tool names regardless of what else is configured.
+
+use crate::contracts::{Contract, MethodDoc};
+use crate::errors::AgentError;
+use crate::tools::{Tool, Tools};
+use alloy_json_abi::{Function, StateMutability};
+use alloy_primitives::{keccak256, Selector};
+use std::collections::{BTreeMap, HashSet};
+
+/// Longest tool name providers accept
+pub const MAX_TOOL_NAME_LEN: usize = 64;
+
+/// A contract function exposed as a tool
+#[derive(Debug, Clone)]
+pub struct ContractTool {
+    /// Tool name shown to the model
+    pub name: String,
+    /// Contract the function belongs to
+    pub contract: Contract,
+    /// The function's ABI entry
+    pub function: Function,
+    /// Whether the contract has other functions with the same name
+    pub overloaded: bool,
+    /// NatSpec documentation, when the ABI is a compiler artifact
+    pub docs: Option<MethodDoc>,
+}
+
+impl ContractTool {
+    /// Selector identifying the function
+    pub fn selector(&self) -> Selector {
+        self.function.selector()
+    }
+
+    /// Whether calling the function cannot change state (`view` or `pure`)
+    pub fn is_read_only(&self) -> bool {
+        is_read_only(self.function.state_mutability)
+    }
+
+    /// Whether the function accepts ETH
+    pub fn is_payable(&self) -> bool {
+        self.function.state_mutability == StateMutability::Payable
+    }
+
+    /// Tool definition for the model
+    pub fn tool(&self) -> Result<Tool, AgentError> {
+        Tools::contract_function_tool(self)
+    }
+}
+
+/// Maps tool names to contract functions and back
+#[derive(Debug, Clone, Default)]
+pub struct ToolRegistry {
+    tools: BTreeMap<String, ContractTool>,
+    /// Registration order, so tool lists follow the configuration
+    order: Vec<String>,
+}
+
+impl ToolRegistry {
+    /// Register every function of every contract
+    ///
+    /// Contracts whose ABI cannot be parsed are skipped. A function whose
+    /// name collides with one registered earlier (e.g. contracts named
+    /// `Token` and `token`) is skipped too, so existing names never change.
+    pub fn new(contracts: &[Contract]) -> Self {
+        let mut registry = Self::default();
+        for contract in contracts {
+            registry.register(contract);
+        }
+        registry
+    }
+
+    /// Register the functions of one contract
+    pub fn register(&mut self, contract: &Contract) {
+        let (abi, natspec) = match contract.parse_artifact() {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                eprintln!("Skipping tools for contract {}: {}", contract.name, e);
+                return;
+            }
+        };
+
+        let overloaded: HashSet<&str> = abi
+            .functions
+            .iter()
+            .filter(|(_, overloads)| overloads.len() > 1)
+            .map(|(name, _)| name.as_str())
+            .collect();
+
+        for function in abi.functions() {
+            let is_overloaded = overloaded.contains(function.name.as_str());
+            let name = tool_name(&contract.name, function, is_overloaded);
+            if let Some(existing) = self.tools.get(&name) {
+                eprintln!(
+                    "Skipping {} on contract {}: tool name {} is taken by contract {}",
+                    function.signature(),
+                    contract.name,
+                    name,
+                    existing.contract.name
+                );
+                continue;
+            }
+
+            self.order.push(name.clone());
+            self.tools.insert(
+                name.clone(),
+                ContractTool {
+                    name,
+                    contract: contract.clone(),
+                    function: function.clone(),
+                    overloaded: is_overloaded,
+                    docs: natspec.method(&function.signature()).cloned(),
+                },
+            );
+        }
+    }
+
+    /// The contract function a tool name refers to
+    pub fn resolve(&self, tool_name: &str) -> Option<&ContractTool> {
+        self.tools.get(tool_name)
+    }
+
+    /// All registered functions in registration order
+    pub fn iter(&self) -> impl Iterator<Item = &ContractTool> {
+        self.order.iter().map(|name| &self.tools[name])
+    }
+
+    /// Tools for state-changing functions
+    pub fn write_tools(&self) -> Vec<Tool> {
+        self.tools_where(|t| !t.is_read_only())
+    }
+
+    /// Tools for `view` and `pure` functions
+    pub fn read_tools(&self) -> Vec<Tool> {
+        self.tools_where(ContractTool::is_read_only)
+    }
+
+    fn tools_where(&self, include: impl Fn(&ContractTool) -> bool) -> Vec<Tool> {
+        self.iter()
+            .filter(|t| include(t))
+            .filter_map(|t| match t.tool() {
+                Ok(tool) => Some(tool),
+                Err(e) => {
+                    eprintln!("Skipping tool {}: {}", t.name, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Number of registered functions
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Whether nothing is registered
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+}
+
+/// Whether calling a function with this mutability cannot change state
+pub(crate) fn is_read_only(mutability: StateMutability) -> bool {
+    matches!(mutability, StateMutability::View | StateMutability::Pure)
+}
+
+/// Provider-legal tool name for a contract function
+///
+/// `contract_{contract}_{function}`, with the contract name lowercased,
+/// overloads told apart by their selector and names that are too long
+/// shortened with a hash of the full name.
+pub fn tool_name(contract: &str, function: &Function, overloaded: bool) -> String {
+    let mut name = format!(
+        "contract_{}_{}",
+        sanitize(&contract.to_lowercase()),
+        sanitize(&function.name)
+    );
+    if overloaded {
+        name.push('_');
+        name.push_str(&hex::encode(function.selector()));
+    }
+
+    if name.len() > MAX_TOOL_NAME_LEN {
+        let hash = hex::encode(&keccak256(name.as_bytes())[..4]);
+        name.truncate(MAX_TOOL_NAME_LEN - hash.len() - 1);
+        name.push('_');
+        name.push_str(&hash);
+    }
+    name
+}
+
+fn sanitize(part: &str) -> String {
+    part.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "0x1234567890123456789012345678901234567890";
+
+    const NFT_ABI: &str = r#"[
+        {"name": "safeTransferFrom", "type": "function", "stateMutability": "nonpayable",
+         "inputs": [{"name": "from", "type": "address"}, {"name": "to", "type": "address"},
+                    {"name": "tokenId", "type": "uint256"}], "outputs": []},
+        {"name": "safeTransferFrom", "type": "function", "stateMutability": "nonpayable",
+         "inputs": [{"name": "from", "type": "address"}, {"name": "to", "type": "address"},
+                    {"name": "tokenId", "type": "uint256"}, {"name": "data", "type": "bytes"}],
+         "outputs": []},
+        {"name": "mint", "type": "function", "stateMutability": "payable",
+         "inputs": [], "outputs": []},
+        {"name": "ownerOf", "type": "function", "stateMutability": "view",
+         "inputs": [{"name": "tokenId", "type": "uint256"}],
+         "outputs": [{"name": "", "type": "address"}]}
+    ]"#;
+
+    fn is_legal(name: &str) -> bool {
+        !name.is_empty()
+            && name.len() <= MAX_TOOL_NAME_LEN
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    }
+
+    #[test]
+    fn test_names_and_resolution() {
+        let registry = ToolRegistry::new(&[Contract::new("Art_Collection", ADDRESS, NFT_ABI)]);
+        let names: Vec<&str> = registry.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "contract_art_collection_mint",
+                "contract_art_collection_ownerOf",
+                "contract_art_collection_safeTransferFrom_42842e0e",
+                "contract_art_collection_safeTransferFrom_b88d4fde",
+            ]
+        );
+        assert!(names.iter().all(|n| is_legal(n)));
+
+        // Names map back to the exact overload
+        let tool = registry
+            .resolve("contract_art_collection_safeTransferFrom_b88d4fde")
+            .unwrap();
+        assert_eq!(tool.contract.name, "Art_Collection");
+        assert_eq!(tool.function.inputs.len(), 4);
+        assert_eq!(hex::encode(tool.selector()), "b88d4fde");
+
+        // Mutability comes from each function's own ABI entry
+        assert!(registry
+            .resolve("contract_art_collection_mint")
+            .unwrap()
+            .is_payable());
+        assert!(!tool.is_payable());
+        assert!(registry
+            .resolve("contract_art_collection_ownerOf")
+            .unwrap()
+            .is_read_only());
+        assert_eq!(registry.write_tools().len(), 3);
+        assert_eq!(registry.read_tools().len(), 1);
+        assert!(registry.resolve("contract_art_mint").is_none());
+    }
+
+    #[test]
+    fn test_long_and_odd_names() {
+        let long_name = "A Very Long Contract Name For The Community Treasury Of A DAO";
+        let registry = ToolRegistry::new(&[Contract::new(long_name, ADDRESS, NFT_ABI)]);
+        for tool in registry.iter() {
+            assert!(is_legal(&tool.name), "{}", tool.name);
+            assert!(tool.name.starts_with("contract_a_very_long_contract_name"));
+            assert_eq!(
+                registry.resolve(&tool.name).unwrap().selector(),
+                tool.selector()
+            );
+        }
+        assert_eq!(registry.len(), 4);
+
+        // Stable across runs and configurations
+        let again = ToolRegistry::new(&[
+            Contract::new("Other", ADDRESS, NFT_ABI),
+            Contract::new(long_name, ADDRESS, NFT_ABI),
+        ]);
+        for tool in registry.iter() {
+            assert!(again.resolve(&tool.name).is_some());
+        }
+    }
+
+    #[test]
+    fn test_collisions_keep_first() {
+        let registry = ToolRegistry::new(&[
+            Contract::new("Token", ADDRESS, NFT_ABI),
+            Contract::new(
+                "token",
+                "0x0987654321098765432109876543210987654321",
+                NFT_ABI,
+            ),
+            Contract::new("Broken", ADDRESS, "not json"),
+        ]);
+        assert_eq!(registry.len(), 4);
+        assert!(registry
+            .iter()
+            .all(|t| t.contract.address == ADDRESS && t.contract.name == "Token"));
+    }
+}
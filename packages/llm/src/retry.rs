@@ -0,0 +1,498 @@
+//! Retrying failed requests
+//!
+//! Only errors that can succeed on a second attempt are retried (see
+//! [`LlmError::is_retryable`]): dropped connections, rate limits, overloaded
+//! or failing servers and empty responses. Invalid input, configuration and
+//! parse errors fail immediately.
+//!
+//! Between attempts the policy waits with exponential backoff and jitter, so
+//! operators hitting the same provider do not retry in lockstep. A
+//! `Retry-After` header on a 429 or 503 replaces the backoff when it asks for
+//! longer, and no wait runs past the policy's deadline.
+
+use crate::errors::LlmError;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How failed requests are retried
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt
+    pub max_retries: u32,
+    /// Wait before the first retry, in milliseconds
+    pub initial_backoff_ms: u64,
+    /// Longest wait between attempts, in milliseconds (`Retry-After` may ask for more)
+    pub max_backoff_ms: u64,
+    /// Factor the wait grows by after each retry
+    pub multiplier: f64,
+    /// Fraction of each wait that is randomized, from 0 (none) to 1 (all of it)
+    pub jitter: f64,
+    /// Time from the first attempt after which no retry starts, in milliseconds
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deadline_ms: Option<u64>,
+}
+
+impl Default for RetryPolicy {
+    /// No retries; with [`RetryPolicy::with_max_retries`], backoff from 500ms up to 30s
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            multiplier: 2.0,
+            jitter: 0.5,
+            deadline_ms: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The default policy: no retries
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Retry up to `max_retries` times
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Wait `initial` before the first retry, growing to at most `max`
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff_ms = initial.as_millis() as u64;
+        self.max_backoff_ms = max.as_millis() as u64;
+        self
+    }
+
+    /// Grow the wait by `multiplier` after each retry
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Randomize this fraction of each wait (clamped to 0..=1)
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Start no retry once `deadline` has passed since the first attempt
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline_ms = Some(deadline.as_millis() as u64);
+        self
+    }
+
+    /// Wait before retry number `retry` (from 1) after `error`
+    ///
+    /// `random` is a sample from `0.0..1.0` used for jitter.
+    pub fn delay(&self, retry: u32, error: &LlmError, random: f64) -> Duration {
+        let exponent = retry.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = (self.initial_backoff_ms as f64 * self.multiplier.powi(exponent))
+            .min(self.max_backoff_ms as f64);
+        let jittered = backoff * (1.0 - self.jitter.clamp(0.0, 1.0) * random.clamp(0.0, 1.0));
+        let delay = Duration::from_millis(jittered as u64);
+
+        match error {
+            LlmError::HttpStatus {
+                retry_after: Some(retry_after),
+                ..
+            } => delay.max(*retry_after),
+            _ => delay,
+        }
+    }
+
+    /// Run `attempt` until it succeeds, fails with a fatal error or the policy gives up
+    ///
+    /// Waits block the thread; inside an async runtime use [`RetryPolicy::run_async`].
+    pub fn run<R>(&self, attempt: impl FnMut() -> Result<R, LlmError>) -> Result<R, LlmError> {
+        self.run_with(&mut SystemClock::new(), attempt)
+    }
+
+    /// Await `attempt` until it succeeds, fails with a fatal error or the policy gives up
+    ///
+    /// Waits yield to the runtime, so other requests keep running.
+    pub async fn run_async<R, F>(&self, attempt: impl FnMut() -> F) -> Result<R, LlmError>
+    where
+        F: Future<Output = Result<R, LlmError>>,
+    {
+        self.run_async_with(&mut SystemClock::new(), attempt).await
+    }
+
+    pub(crate) fn run_with<R>(
+        &self,
+        clock: &mut dyn Clock,
+        mut attempt: impl FnMut() -> Result<R, LlmError>,
+    ) -> Result<R, LlmError> {
+        let start = clock.now();
+        let mut retry = 0;
+
+        loop {
+            let error = match attempt() {
+                Ok(response) => return Ok(response),
+                Err(e) => e,
+            };
+            retry += 1;
+            let delay = self.next_delay(clock, start, retry, error)?;
+            clock.sleep(delay);
+        }
+    }
+
+    pub(crate) async fn run_async_with<R, F>(
+        &self,
+        clock: &mut dyn Clock,
+        mut attempt: impl FnMut() -> F,
+    ) -> Result<R, LlmError>
+    where
+        F: Future<Output = Result<R, LlmError>>,
+    {
+        let start = clock.now();
+        let mut retry = 0;
+
+        loop {
+            let error = match attempt().await {
+                Ok(response) => return Ok(response),
+                Err(e) => e,
+            };
+            retry += 1;
+            let delay = self.next_delay(clock, start, retry, error)?;
+            clock.sleep_async(delay).await;
+        }
+    }
+
+    /// Wait before retry number `retry`, or the error back if it should not be retried
+    fn next_delay(
+        &self,
+        clock: &mut dyn Clock,
+        start: Duration,
+        retry: u32,
+        error: LlmError,
+    ) -> Result<Duration, LlmError> {
+        if !error.is_retryable() || retry > self.max_retries {
+            return Err(error);
+        }
+
+        let delay = self.delay(retry, &error, clock.random());
+        if let Some(deadline) = self.deadline_ms.map(Duration::from_millis) {
+            if clock.now().saturating_sub(start) + delay > deadline {
+                eprintln!(
+                    "Request failed and the retry deadline of {:?} leaves no time to retry: {}",
+                    deadline, error
+                );
+                return Err(error);
+            }
+        }
+
+        eprintln!(
+            "Request failed (attempt {}/{}), retrying in {:?}: {}",
+            retry,
+            self.max_retries.saturating_add(1),
+            delay,
+            error
+        );
+        Ok(delay)
+    }
+}
+
+/// Time and randomness for retries, replaced in tests
+pub(crate) trait Clock {
+    /// Time since some fixed point
+    fn now(&self) -> Duration;
+    /// Block for `duration`
+    fn sleep(&mut self, duration: Duration);
+    /// Wait `duration` without blocking the runtime
+    fn sleep_async(&mut self, duration: Duration) -> Pin<Box<dyn Future<Output = ()>>>;
+    /// A sample from `0.0..1.0`
+    fn random(&mut self) -> f64;
+}
+
+/// The real clock, with xorshift jitter seeded from the system time
+struct SystemClock {
+    start: Instant,
+    state: u64,
+}
+
+impl SystemClock {
+    fn new() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or_default();
+        Self {
+            start: Instant::now(),
+            // xorshift never leaves zero
+            state: nanos | 1,
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+
+    fn sleep_async(&mut self, duration: Duration) -> Pin<Box<dyn Future<Output = ()>>> {
+        let wait = wstd::task::sleep(duration.into());
+        Box::pin(async move {
+            wait.await;
+        })
+    }
+
+    fn random(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Parse a `Retry-After` header: delay seconds or an HTTP date
+///
+/// Dates in the past give a zero delay.
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = parse_http_date(value)?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+    Some(Duration::from_secs(at.saturating_sub(now)))
+}
+
+/// Seconds since the epoch for an IMF-fixdate (`Sun, 06 Nov 1994 08:49:37 GMT`)
+fn parse_http_date(value: &str) -> Option<u64> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    let [_, day, month, year, time, "GMT"] = parts.as_slice() else {
+        return None;
+    };
+    let day: u64 = day.parse().ok()?;
+    let month = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ]
+    .iter()
+    .position(|m| m == month)? as u64
+        + 1;
+    let year: u64 = year.parse().ok()?;
+    let mut clock = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (clock.next()??, clock.next()??, clock.next()??);
+    if year < 1970 || !(1..=31).contains(&day) || hours > 23 || minutes > 59 || seconds > 60 {
+        return None;
+    }
+
+    // Days since the epoch for a proleptic Gregorian date
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y / 400;
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146_097 + day_of_era).checked_sub(719_468)?;
+
+    Some(days * 86_400 + hours * 3_600 + minutes * 60 + seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    /// Advances only when slept, and always samples the middle of the jitter range
+    #[derive(Default)]
+    struct FakeClock {
+        now: Duration,
+        sleeps: Vec<Duration>,
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> Duration {
+            self.now
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.now += duration;
+            self.sleeps.push(duration);
+        }
+
+        fn sleep_async(&mut self, duration: Duration) -> Pin<Box<dyn Future<Output = ()>>> {
+            self.sleep(duration);
+            Box::pin(std::future::ready(()))
+        }
+
+        fn random(&mut self) -> f64 {
+            0.5
+        }
+    }
+
+    fn status(status: u16, retry_after: Option<u64>) -> LlmError {
+        LlmError::HttpStatus {
+            status,
+            retry_after: retry_after.map(Duration::from_secs),
+            body: "slow down".to_string(),
+        }
+    }
+
+    /// Fail with each error in turn, then succeed
+    fn run(policy: &RetryPolicy, errors: Vec<LlmError>) -> (Result<u32, LlmError>, u32, FakeClock) {
+        let mut clock = FakeClock::default();
+        let mut errors = errors.into_iter();
+        let mut attempts = 0;
+        let result = policy.run_with(&mut clock, || {
+            attempts += 1;
+            match errors.next() {
+                Some(e) => Err(e),
+                None => Ok(attempts),
+            }
+        });
+        (result, attempts, clock)
+    }
+
+    #[test]
+    fn test_classification() {
+        for error in [
+            LlmError::RequestError("connection reset".into()),
+            LlmError::ApiError("No content in response".into()),
+            status(429, None),
+            status(503, Some(1)),
+            status(500, None),
+            status(408, None),
+        ] {
+            assert!(error.is_retryable(), "{}", error);
+        }
+        for error in [
+            LlmError::InvalidInput("Messages cannot be empty".into()),
+            LlmError::ParseError("bad json".into()),
+            LlmError::ConfigError("no key".into()),
+            LlmError::ValidationError("$.score".into()),
+            LlmError::Incomplete("Maximum tool execution iterations reached".into()),
+            status(400, None),
+            status(401, None),
+            status(501, None),
+        ] {
+            assert!(!error.is_retryable(), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_backoff_with_jitter() {
+        let policy = RetryPolicy::new()
+            .with_max_retries(4)
+            .with_backoff(Duration::from_millis(100), Duration::from_millis(500))
+            .with_jitter(0.5);
+        let (result, attempts, clock) = run(
+            &policy,
+            (0..4)
+                .map(|_| LlmError::RequestError("reset".into()))
+                .collect(),
+        );
+        assert_eq!(result.unwrap(), 5);
+        assert_eq!(attempts, 5);
+        // 100, 200, 400, 500 (capped), each with a quarter taken off by jitter
+        assert_eq!(clock.sleeps, [75, 150, 300, 375].map(Duration::from_millis));
+    }
+
+    #[test]
+    fn test_fatal_errors_and_exhaustion() {
+        let policy = RetryPolicy::new().with_max_retries(3);
+        let (result, attempts, clock) = run(&policy, vec![LlmError::InvalidInput("empty".into())]);
+        assert!(matches!(result, Err(LlmError::InvalidInput(_))));
+        assert_eq!(attempts, 1);
+        assert!(clock.sleeps.is_empty());
+
+        let (result, attempts, _) = run(&policy, (0..5).map(|_| status(500, None)).collect());
+        assert!(matches!(
+            result,
+            Err(LlmError::HttpStatus { status: 500, .. })
+        ));
+        assert_eq!(attempts, 4);
+
+        let (result, attempts, _) = run(&RetryPolicy::new(), vec![status(503, None)]);
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_retry_after_and_deadline() {
+        let policy = RetryPolicy::new()
+            .with_max_retries(5)
+            .with_backoff(Duration::from_millis(100), Duration::from_secs(1))
+            .with_deadline(Duration::from_secs(10));
+
+        // The server's wait wins when it is longer than the backoff
+        let (result, _, clock) = run(&policy, vec![status(429, Some(3)), status(503, None)]);
+        assert!(result.is_ok());
+        assert_eq!(
+            clock.sleeps,
+            [Duration::from_secs(3), Duration::from_millis(150)]
+        );
+
+        // A wait that would pass the deadline is not started
+        let (result, attempts, clock) =
+            run(&policy, vec![status(429, Some(6)), status(429, Some(6))]);
+        assert!(matches!(
+            result,
+            Err(LlmError::HttpStatus { status: 429, .. })
+        ));
+        assert_eq!(attempts, 2);
+        assert_eq!(clock.now, Duration::from_secs(6));
+    }
+
+    #[test]
+    fn test_run_async() {
+        let policy = RetryPolicy::new()
+            .with_max_retries(2)
+            .with_backoff(Duration::from_millis(100), Duration::from_secs(1))
+            .with_jitter(0.0);
+        let mut clock = FakeClock::default();
+        let mut errors =
+            vec![status(503, None), LlmError::RequestError("reset".into())].into_iter();
+        let result = block_on(policy.run_async_with(&mut clock, || {
+            std::future::ready(errors.next().map_or(Ok("done"), Err))
+        }));
+        assert_eq!(result.unwrap(), "done");
+        assert_eq!(clock.sleeps, [100, 200].map(Duration::from_millis));
+
+        let result: Result<(), _> = block_on(policy.run_async_with(&mut clock, || {
+            std::future::ready(Err(LlmError::InvalidInput("empty".into())))
+        }));
+        assert!(matches!(result, Err(LlmError::InvalidInput(_))));
+        assert_eq!(clock.sleeps.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(784_111_777)
+        );
+        assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        assert_eq!(parse_retry_after("soon"), None);
+        assert_eq!(parse_retry_after("Sun, 06 Foo 1994 08:49:37 GMT"), None);
+    }
+
+    #[test]
+    fn test_policy_from_json() {
+        let policy: RetryPolicy =
+            serde_json::from_str(r#"{"max_retries": 3, "deadline_ms": 20000}"#).unwrap();
+        assert_eq!(
+            policy,
+            RetryPolicy::new()
+                .with_max_retries(3)
+                .with_deadline(Duration::from_secs(20))
+        );
+    }
+}
@@ -0,0 +1,466 @@
+//! JSON schema helpers for structured output
+//!
+//! Covers the subset of JSON Schema that `schemars` emits for Rust types:
+//! `type`, `properties`, `required`, `additionalProperties`, `items`,
+//! `prefixItems`, `enum`, `const`, `anyOf`/`oneOf`/`allOf`, numeric and
+//! length bounds, and local `$ref`s into `$defs`/`definitions`.
+
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// A single place where a value does not match its schema
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaViolation {
+    /// Location of the offending value, e.g. `$.tasks[0].id`
+    pub path: String,
+    /// What was expected there
+    pub message: String,
+}
+
+impl fmt::Display for SchemaViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Validate `value` against `schema`, collecting every violation
+pub fn validate(value: &Value, schema: &Value) -> Result<(), Vec<SchemaViolation>> {
+    let mut violations = Vec::new();
+    Validator { root: schema }.check(value, schema, "$", &mut violations);
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+/// Render violations as one line each, for error messages and repair prompts
+pub fn describe_violations(violations: &[SchemaViolation]) -> String {
+    violations
+        .iter()
+        .map(|v| format!("- {}", v))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A name for the schema usable where providers require an identifier
+///
+/// Uses the schema `title` when present, keeping only `[A-Za-z0-9_-]`.
+pub fn schema_name(schema: &Value) -> String {
+    let name: String = schema
+        .get("title")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_' || *c == '-')
+        .take(64)
+        .collect();
+
+    if name.is_empty() {
+        "response".to_string()
+    } else {
+        name
+    }
+}
+
+struct Validator<'a> {
+    root: &'a Value,
+}
+
+impl<'a> Validator<'a> {
+    fn check(&self, value: &Value, schema: &Value, path: &str, out: &mut Vec<SchemaViolation>) {
+        let schema = match schema {
+            // `true` accepts anything, `false` rejects everything
+            Value::Bool(true) => return,
+            Value::Bool(false) => {
+                out.push(violation(path, "no value is allowed here"));
+                return;
+            }
+            Value::Object(schema) => schema,
+            _ => return,
+        };
+
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            match self.resolve(reference) {
+                Some(target) => self.check(value, target, path, out),
+                None => out.push(violation(
+                    path,
+                    &format!("unresolvable schema reference {}", reference),
+                )),
+            }
+        }
+
+        if let Some(expected) = schema.get("type") {
+            let allowed: Vec<&str> = match expected {
+                Value::String(t) => vec![t.as_str()],
+                Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+                _ => Vec::new(),
+            };
+            if !allowed.is_empty() && !allowed.iter().any(|t| matches_type(value, t)) {
+                out.push(violation(
+                    path,
+                    &format!(
+                        "expected {}, got {}",
+                        allowed.join(" or "),
+                        type_name(value)
+                    ),
+                ));
+                // Nothing below is meaningful for a value of the wrong type
+                return;
+            }
+        }
+
+        if let Some(options) = schema.get("enum").and_then(Value::as_array) {
+            if !options.iter().any(|option| json_eq(option, value)) {
+                out.push(violation(
+                    path,
+                    &format!("expected one of {}", Value::Array(options.clone())),
+                ));
+            }
+        }
+
+        if let Some(constant) = schema.get("const") {
+            if !json_eq(constant, value) {
+                out.push(violation(path, &format!("expected {}", constant)));
+            }
+        }
+
+        if let Some(all) = schema.get("allOf").and_then(Value::as_array) {
+            for sub in all {
+                self.check(value, sub, path, out);
+            }
+        }
+
+        if let Some(any) = schema.get("anyOf").and_then(Value::as_array) {
+            if !any.iter().any(|sub| self.is_valid(value, sub)) {
+                out.push(self.no_variant_matched(value, any, path));
+            }
+        }
+
+        if let Some(one) = schema.get("oneOf").and_then(Value::as_array) {
+            match one.iter().filter(|sub| self.is_valid(value, sub)).count() {
+                1 => {}
+                0 => out.push(self.no_variant_matched(value, one, path)),
+                n => out.push(violation(
+                    path,
+                    &format!("matches {} alternatives, expected exactly one", n),
+                )),
+            }
+        }
+
+        match value {
+            Value::Object(object) => self.check_object(object, schema, path, out),
+            Value::Array(items) => self.check_array(items, schema, path, out),
+            Value::String(s) => check_string(s, schema, path, out),
+            Value::Number(_) => check_number(value, schema, path, out),
+            _ => {}
+        }
+    }
+
+    fn check_object(
+        &self,
+        object: &Map<String, Value>,
+        schema: &Map<String, Value>,
+        path: &str,
+        out: &mut Vec<SchemaViolation>,
+    ) {
+        let properties = schema.get("properties").and_then(Value::as_object);
+
+        if let Some(required) = schema.get("required").and_then(Value::as_array) {
+            for name in required.iter().filter_map(Value::as_str) {
+                if !object.contains_key(name) {
+                    out.push(violation(
+                        path,
+                        &format!("missing required property \"{}\"", name),
+                    ));
+                }
+            }
+        }
+
+        for (name, item) in object {
+            let item_path = format!("{}.{}", path, name);
+            match properties.and_then(|p| p.get(name)) {
+                Some(sub) => self.check(item, sub, &item_path, out),
+                None => match schema.get("additionalProperties") {
+                    Some(Value::Bool(false)) => {
+                        out.push(violation(&item_path, "unexpected property"))
+                    }
+                    Some(sub @ Value::Object(_)) => self.check(item, sub, &item_path, out),
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    fn check_array(
+        &self,
+        items: &[Value],
+        schema: &Map<String, Value>,
+        path: &str,
+        out: &mut Vec<SchemaViolation>,
+    ) {
+        if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+            if (items.len() as u64) < min {
+                out.push(violation(path, &format!("expected at least {} items", min)));
+            }
+        }
+        if let Some(max) = schema.get("maxItems").and_then(Value::as_u64) {
+            if items.len() as u64 > max {
+                out.push(violation(path, &format!("expected at most {} items", max)));
+            }
+        }
+
+        let prefix = schema
+            .get("prefixItems")
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        for (i, item) in items.iter().enumerate() {
+            let item_path = format!("{}[{}]", path, i);
+            match prefix.get(i) {
+                Some(sub) => self.check(item, sub, &item_path, out),
+                None => {
+                    if let Some(sub) = schema.get("items") {
+                        self.check(item, sub, &item_path, out);
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_valid(&self, value: &Value, schema: &Value) -> bool {
+        let mut scratch = Vec::new();
+        self.check(value, schema, "$", &mut scratch);
+        scratch.is_empty()
+    }
+
+    /// Report the closest alternative when none of `anyOf`/`oneOf` matched
+    fn no_variant_matched(&self, value: &Value, variants: &[Value], path: &str) -> SchemaViolation {
+        let closest = variants
+            .iter()
+            .map(|sub| {
+                let mut found = Vec::new();
+                self.check(value, sub, path, &mut found);
+                found
+            })
+            .filter(|found| !found.is_empty())
+            .min_by_key(Vec::len);
+
+        match closest {
+            Some(found) if found.len() == 1 => found.into_iter().next().unwrap(),
+            _ => violation(path, "does not match any of the allowed alternatives"),
+        }
+    }
+
+    fn resolve(&self, reference: &str) -> Option<&'a Value> {
+        let pointer = reference.strip_prefix('#')?;
+        self.root.pointer(pointer)
+    }
+}
+
+fn check_string(s: &str, schema: &Map<String, Value>, path: &str, out: &mut Vec<SchemaViolation>) {
+    let len = s.chars().count() as u64;
+    if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+        if len < min {
+            out.push(violation(
+                path,
+                &format!("expected at least {} characters", min),
+            ));
+        }
+    }
+    if let Some(max) = schema.get("maxLength").and_then(Value::as_u64) {
+        if len > max {
+            out.push(violation(
+                path,
+                &format!("expected at most {} characters", max),
+            ));
+        }
+    }
+}
+
+fn check_number(
+    value: &Value,
+    schema: &Map<String, Value>,
+    path: &str,
+    out: &mut Vec<SchemaViolation>,
+) {
+    let Some(n) = value.as_f64() else {
+        return;
+    };
+    let bound = |key: &str| schema.get(key).and_then(Value::as_f64);
+
+    if let Some(min) = bound("minimum") {
+        if n < min {
+            out.push(violation(path, &format!("expected a value >= {}", min)));
+        }
+    }
+    if let Some(max) = bound("maximum") {
+        if n > max {
+            out.push(violation(path, &format!("expected a value <= {}", max)));
+        }
+    }
+    if let Some(min) = bound("exclusiveMinimum") {
+        if n <= min {
+            out.push(violation(path, &format!("expected a value > {}", min)));
+        }
+    }
+    if let Some(max) = bound("exclusiveMaximum") {
+        if n >= max {
+            out.push(violation(path, &format!("expected a value < {}", max)));
+        }
+    }
+}
+
+fn matches_type(value: &Value, expected: &str) -> bool {
+    match expected {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => {
+            value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|n| n.fract() == 0.0)
+        }
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_i64() || n.is_u64() => "integer",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// JSON equality that treats `1` and `1.0` as the same number
+fn json_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(x), Value::Number(y)) => x.as_f64() == y.as_f64(),
+        _ => a == b,
+    }
+}
+
+fn violation(path: &str, message: &str) -> SchemaViolation {
+    SchemaViolation {
+        path: path.to_string(),
+        message: message.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use schemars::JsonSchema;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    #[allow(dead_code)]
+    #[derive(Deserialize, JsonSchema)]
+    struct Task {
+        id: u32,
+        description: String,
+        done: Option<bool>,
+        priority: Priority,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize, JsonSchema)]
+    enum Priority {
+        Low,
+        High,
+    }
+
+    #[allow(dead_code)]
+    #[derive(Deserialize, JsonSchema)]
+    struct TaskList {
+        title: String,
+        tasks: Vec<Task>,
+    }
+
+    fn task_list_schema() -> Value {
+        serde_json::to_value(schemars::schema_for!(TaskList)).unwrap()
+    }
+
+    #[test]
+    fn test_validate_accepts_matching_value() {
+        let value = json!({
+            "title": "Launch",
+            "tasks": [
+                {"id": 1, "description": "Ship", "done": null, "priority": "High"},
+                {"id": 2, "description": "Announce", "priority": "Low"}
+            ]
+        });
+        assert!(validate(&value, &task_list_schema()).is_ok());
+    }
+
+    #[test]
+    fn test_validate_reports_paths() {
+        let value = json!({
+            "tasks": [
+                {"id": -1, "description": 5, "priority": "Urgent"}
+            ]
+        });
+        let violations = validate(&value, &task_list_schema()).unwrap_err();
+        let paths: Vec<&str> = violations.iter().map(|v| v.path.as_str()).collect();
+
+        assert!(paths.contains(&"$"), "missing title: {:?}", violations);
+        assert!(paths.contains(&"$.tasks[0].id"), "{:?}", violations);
+        assert!(
+            paths.contains(&"$.tasks[0].description"),
+            "{:?}",
+            violations
+        );
+        assert!(paths.contains(&"$.tasks[0].priority"), "{:?}", violations);
+
+        let rendered = describe_violations(&violations);
+        assert!(rendered.contains("missing required property \"title\""));
+        assert!(rendered.contains("$.tasks[0].description: expected string, got integer"));
+    }
+
+    #[test]
+    fn test_validate_keywords() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "tags": {"type": "array", "items": {"type": "string", "maxLength": 3}, "maxItems": 2},
+                "pair": {"type": "array", "prefixItems": [{"type": "integer"}, {"type": "boolean"}]},
+                "kind": {"const": "vote"},
+                "score": {"type": "number", "exclusiveMaximum": 1}
+            },
+            "additionalProperties": false
+        });
+
+        assert!(validate(
+            &json!({"tags": ["a"], "pair": [1, true], "kind": "vote", "score": 0.5}),
+            &schema
+        )
+        .is_ok());
+
+        let violations = validate(
+            &json!({"tags": ["a", "b", "long"], "pair": [1.5, true], "kind": "veto", "score": 1, "extra": 1}),
+            &schema,
+        )
+        .unwrap_err();
+        let rendered = describe_violations(&violations);
+        assert!(rendered.contains("$.tags: expected at most 2 items"));
+        assert!(rendered.contains("$.tags[2]: expected at most 3 characters"));
+        assert!(rendered.contains("$.pair[0]: expected integer, got number"));
+        assert!(rendered.contains("$.kind: expected \"vote\""));
+        assert!(rendered.contains("$.score: expected a value < 1"));
+        assert!(rendered.contains("$.extra: unexpected property"));
+    }
+
+    #[test]
+    fn test_schema_name() {
+        assert_eq!(schema_name(&task_list_schema()), "TaskList");
+        assert_eq!(schema_name(&json!({"title": "Vec<Task>"})), "VecTask");
+        assert_eq!(schema_name(&json!({"type": "object"})), "response");
+    }
+}
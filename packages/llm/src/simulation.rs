@@ -0,0 +1,502 @@
+//! Transaction simulation
+//!
+//! A transaction the model proposes may still revert on-chain: a balance is
+//! too low, an allowance is missing, a deadline has passed. A [`Simulator`]
+//! dry-runs each transaction with `eth_call` and `eth_estimateGas` from the
+//! configured sender. Reverts are decoded with the contracts' custom errors
+//! and handed back to the model so it can correct the call; transactions
+//! that succeed get their gas estimate attached.
+//!
+//! For local testing, point the simulator at the anvil fork the backend
+//! starts (`task backend:start`).
+
+use crate::config::Config;
+use crate::contracts::{Contract, Transaction};
+use crate::encoding::sol_value_to_json;
+use alloy_dyn_abi::JsonAbiExt;
+use alloy_json_abi::JsonAbi;
+use alloy_network::Ethereum;
+use alloy_primitives::{Address, Bytes};
+use alloy_provider::transport::TransportError;
+use alloy_provider::Provider;
+use alloy_rpc_types::TransactionRequest;
+use alloy_sol_types::decode_revert_reason;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Arc;
+use thiserror::Error;
+use wavs_wasi_utils::evm::new_evm_provider;
+use wstd::runtime::block_on;
+
+/// Why a JSON-RPC call did not return a result
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RpcFailure {
+    /// Execution reverted with this data
+    Reverted(Bytes),
+    /// The node could not be reached or rejected the request
+    Unavailable(String),
+}
+
+/// Result of an async [`ChainRpc`] call
+pub type RpcFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, RpcFailure>> + 'a>>;
+
+/// The JSON-RPC calls a simulation needs
+pub trait ChainRpc {
+    /// `eth_call` against the latest block
+    fn call(&self, tx: &TransactionRequest) -> Result<Bytes, RpcFailure>;
+
+    /// `eth_estimateGas` against the latest block
+    fn estimate_gas(&self, tx: &TransactionRequest) -> Result<u64, RpcFailure>;
+
+    /// `eth_call` without blocking; defaults to [`ChainRpc::call`]
+    fn call_async<'a>(&'a self, tx: &'a TransactionRequest) -> RpcFuture<'a, Bytes> {
+        Box::pin(std::future::ready(self.call(tx)))
+    }
+
+    /// `eth_estimateGas` without blocking; defaults to [`ChainRpc::estimate_gas`]
+    fn estimate_gas_async<'a>(&'a self, tx: &'a TransactionRequest) -> RpcFuture<'a, u64> {
+        Box::pin(std::future::ready(self.estimate_gas(tx)))
+    }
+}
+
+/// A JSON-RPC endpoint reached through the WAVS host
+#[derive(Debug, Clone)]
+pub struct HttpRpc {
+    rpc_url: String,
+}
+
+impl HttpRpc {
+    /// Send calls to the endpoint at `rpc_url`
+    pub fn new(rpc_url: impl Into<String>) -> Self {
+        Self {
+            rpc_url: rpc_url.into(),
+        }
+    }
+
+    fn ensure_component() -> Result<(), RpcFailure> {
+        if cfg!(not(target_arch = "wasm32")) {
+            return Err(RpcFailure::Unavailable(
+                "JSON-RPC calls are only available inside a WASI component".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn eth_call(rpc_url: String, tx: TransactionRequest) -> Result<Bytes, RpcFailure> {
+        Self::ensure_component()?;
+        let provider = new_evm_provider::<Ethereum>(rpc_url);
+        provider.call(tx).await.map_err(rpc_failure)
+    }
+
+    async fn eth_estimate_gas(rpc_url: String, tx: TransactionRequest) -> Result<u64, RpcFailure> {
+        Self::ensure_component()?;
+        let provider = new_evm_provider::<Ethereum>(rpc_url);
+        provider.estimate_gas(tx).await.map_err(rpc_failure)
+    }
+}
+
+impl ChainRpc for HttpRpc {
+    fn call(&self, tx: &TransactionRequest) -> Result<Bytes, RpcFailure> {
+        block_on(Self::eth_call(self.rpc_url.clone(), tx.clone()))
+    }
+
+    fn estimate_gas(&self, tx: &TransactionRequest) -> Result<u64, RpcFailure> {
+        block_on(Self::eth_estimate_gas(self.rpc_url.clone(), tx.clone()))
+    }
+
+    fn call_async<'a>(&'a self, tx: &'a TransactionRequest) -> RpcFuture<'a, Bytes> {
+        Box::pin(Self::eth_call(self.rpc_url.clone(), tx.clone()))
+    }
+
+    fn estimate_gas_async<'a>(&'a self, tx: &'a TransactionRequest) -> RpcFuture<'a, u64> {
+        Box::pin(Self::eth_estimate_gas(self.rpc_url.clone(), tx.clone()))
+    }
+}
+
+/// Revert data if the node reported a revert, otherwise why the call failed
+fn rpc_failure(error: TransportError) -> RpcFailure {
+    match error.as_error_resp().and_then(|resp| resp.as_revert_data()) {
+        Some(data) => RpcFailure::Reverted(data),
+        None => RpcFailure::Unavailable(error.to_string()),
+    }
+}
+
+/// A transaction that would succeed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Simulation {
+    /// Data returned by `eth_call`
+    pub output: Bytes,
+    /// Gas `eth_estimateGas` expects the transaction to use
+    pub gas_estimate: u64,
+}
+
+/// Why a simulation failed
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SimulationError {
+    /// The transaction could not be turned into a call
+    #[error("Cannot simulate transaction: {0}")]
+    Invalid(String),
+
+    /// The transaction would revert
+    #[error("Transaction to {to} would revert: {reason}")]
+    Reverted {
+        to: String,
+        /// Decoded revert reason or custom error
+        reason: String,
+        /// Raw revert data
+        data: Bytes,
+    },
+
+    /// The chain could not be queried
+    #[error("Simulation unavailable: {0}")]
+    Unavailable(String),
+}
+
+/// Dry-runs transactions from a sender against a chain
+///
+/// Cloning is cheap and shares the RPC client.
+#[derive(Clone)]
+pub struct Simulator {
+    rpc: Arc<dyn ChainRpc + Send + Sync>,
+    from: Address,
+    contracts: Vec<Contract>,
+}
+
+impl std::fmt::Debug for Simulator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Simulator")
+            .field("from", &self.from)
+            .field("contracts", &self.contracts.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Simulator {
+    /// Simulate over the JSON-RPC endpoint at `rpc_url`, sending from `from`
+    ///
+    /// `contracts` supply the ABIs for encoding calls and decoding custom errors.
+    pub fn new(rpc_url: impl Into<String>, from: Address, contracts: Vec<Contract>) -> Self {
+        Self::with_rpc(HttpRpc::new(rpc_url), from, contracts)
+    }
+
+    /// Simulate through any [`ChainRpc`], e.g. a fake in tests
+    pub fn with_rpc(
+        rpc: impl ChainRpc + Send + Sync + 'static,
+        from: Address,
+        contracts: Vec<Contract>,
+    ) -> Self {
+        Self {
+            rpc: Arc::new(rpc),
+            from,
+            contracts,
+        }
+    }
+
+    /// Simulator for the config's contracts, if it has an `rpc_url` and a valid `sender`
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let rpc_url = config.rpc_url.as_ref()?;
+        let from = Address::from_str(config.sender.as_ref()?).ok()?;
+        Some(Self::new(rpc_url.clone(), from, config.contracts.clone()))
+    }
+
+    /// Address transactions are simulated from
+    pub fn sender(&self) -> Address {
+        self.from
+    }
+
+    /// Run `tx` with `eth_call` and estimate its gas
+    pub fn simulate(&self, tx: &Transaction) -> Result<Simulation, SimulationError> {
+        let request = self.request(tx)?;
+
+        let output = self
+            .rpc
+            .call(&request)
+            .map_err(|failure| self.simulation_error(tx, failure))?;
+        let gas_estimate = self
+            .rpc
+            .estimate_gas(&request)
+            .map_err(|failure| self.simulation_error(tx, failure))?;
+
+        Ok(Simulation {
+            output,
+            gas_estimate,
+        })
+    }
+
+    /// Async version of [`Simulator::simulate`]
+    pub async fn simulate_async(&self, tx: &Transaction) -> Result<Simulation, SimulationError> {
+        let request = self.request(tx)?;
+
+        let output = self
+            .rpc
+            .call_async(&request)
+            .await
+            .map_err(|failure| self.simulation_error(tx, failure))?;
+        let gas_estimate = self
+            .rpc
+            .estimate_gas_async(&request)
+            .await
+            .map_err(|failure| self.simulation_error(tx, failure))?;
+
+        Ok(Simulation {
+            output,
+            gas_estimate,
+        })
+    }
+
+    /// Simulate `tx` and attach its gas estimate
+    pub fn apply(&self, tx: &mut Transaction) -> Result<(), SimulationError> {
+        let simulation = self.simulate(tx)?;
+        tx.gas_estimate = Some(simulation.gas_estimate);
+        Ok(())
+    }
+
+    /// Async version of [`Simulator::apply`]
+    pub async fn apply_async(&self, tx: &mut Transaction) -> Result<(), SimulationError> {
+        let simulation = self.simulate_async(tx).await?;
+        tx.gas_estimate = Some(simulation.gas_estimate);
+        Ok(())
+    }
+
+    fn request(&self, tx: &Transaction) -> Result<TransactionRequest, SimulationError> {
+        Ok(tx
+            .to_transaction_request(&self.contracts)
+            .map_err(|e| SimulationError::Invalid(e.to_string()))?
+            .from(self.from))
+    }
+
+    fn simulation_error(&self, tx: &Transaction, failure: RpcFailure) -> SimulationError {
+        match failure {
+            RpcFailure::Reverted(data) => SimulationError::Reverted {
+                to: tx.to.clone(),
+                reason: self.revert_reason(&tx.to, &data),
+                data,
+            },
+            RpcFailure::Unavailable(e) => SimulationError::Unavailable(e),
+        }
+    }
+
+    /// Readable revert reason, trying the target's ABI before the other contracts'
+    fn revert_reason(&self, to: &str, data: &[u8]) -> String {
+        if data.is_empty() {
+            return "execution reverted without a reason".to_string();
+        }
+
+        let target = Address::from_str(to).ok();
+        let mut contracts: Vec<&Contract> = self.contracts.iter().collect();
+        contracts.sort_by_key(|c| Address::from_str(&c.address).ok() != target);
+
+        contracts
+            .into_iter()
+            .filter_map(|c| c.parse_artifact().ok())
+            .find_map(|(abi, _)| decode_custom_error(&abi, data))
+            .or_else(|| decode_revert_reason(data))
+            .unwrap_or_else(|| format!("unknown error {}", Bytes::copy_from_slice(data)))
+    }
+}
+
+/// `Name(arg: value, ...)` for revert data matching one of the ABI's custom errors
+pub fn decode_custom_error(abi: &JsonAbi, data: &[u8]) -> Option<String> {
+    let selector = data.get(..4)?;
+    let error = abi.errors().find(|e| e.selector().as_slice() == selector)?;
+    let values = error.abi_decode_input(&data[4..]).ok()?;
+
+    let args = error
+        .inputs
+        .iter()
+        .zip(&values)
+        .enumerate()
+        .map(|(i, (param, value))| {
+            let name = if param.name.is_empty() {
+                format!("arg{}", i)
+            } else {
+                param.name.clone()
+            };
+            let value = match sol_value_to_json(value) {
+                serde_json::Value::String(s) => s,
+                other => other.to_string(),
+            };
+            format!("{}: {}", name, value)
+        })
+        .collect::<Vec<_>>();
+    Some(format!("{}({})", error.name, args.join(", ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::ContractCall;
+    use crate::tools::{ToolCall, ToolCallFunction, Tools};
+    use alloy_dyn_abi::DynSolValue;
+    use alloy_primitives::{keccak256, U256};
+    use alloy_sol_types::{Revert, SolError};
+    use serde_json::json;
+    use std::sync::Mutex;
+
+    const VAULT: &str = "0xb7278a61aa25c888815afc32ad3cc52ff24fe575";
+    const SENDER: &str = "0x1234567890123456789012345678901234567890";
+
+    const VAULT_ABI: &str = r#"[
+        {"name": "withdraw", "type": "function", "stateMutability": "nonpayable",
+         "inputs": [{"name": "amount", "type": "uint256"}], "outputs": []},
+        {"name": "InsufficientBalance", "type": "error",
+         "inputs": [{"name": "available", "type": "uint256"}, {"name": "required", "type": "uint256"}]},
+        {"name": "Paused", "type": "error", "inputs": []}
+    ]"#;
+
+    /// Answers every call with a fixed result and records requests
+    struct FakeRpc {
+        call: Result<Bytes, RpcFailure>,
+        requests: Arc<Mutex<Vec<TransactionRequest>>>,
+    }
+
+    impl ChainRpc for FakeRpc {
+        fn call(&self, tx: &TransactionRequest) -> Result<Bytes, RpcFailure> {
+            self.requests.lock().unwrap().push(tx.clone());
+            self.call.clone()
+        }
+
+        fn estimate_gas(&self, _tx: &TransactionRequest) -> Result<u64, RpcFailure> {
+            self.call.clone().map(|_| 48_213)
+        }
+    }
+
+    fn simulator(
+        call: Result<Bytes, RpcFailure>,
+    ) -> (Simulator, Arc<Mutex<Vec<TransactionRequest>>>) {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let rpc = FakeRpc {
+            call,
+            requests: requests.clone(),
+        };
+        let sim = Simulator::with_rpc(
+            rpc,
+            SENDER.parse().unwrap(),
+            vec![Contract::new("Vault", VAULT, VAULT_ABI)],
+        );
+        (sim, requests)
+    }
+
+    fn withdraw(amount: u64) -> Transaction {
+        Transaction {
+            to: VAULT.to_string(),
+            value: "0".to_string(),
+            contract_call: Some(ContractCall {
+                function: "withdraw".to_string(),
+                args: vec![json!(amount.to_string())],
+            }),
+            data: "0x".to_string(),
+            description: "Withdraw from the vault".to_string(),
+            gas_estimate: None,
+        }
+    }
+
+    fn error_data(signature: &str, args: &[DynSolValue]) -> Bytes {
+        let mut data = keccak256(signature.as_bytes())[..4].to_vec();
+        data.extend(DynSolValue::Tuple(args.to_vec()).abi_encode_params());
+        data.into()
+    }
+
+    #[test]
+    fn test_successful_simulation() {
+        let (sim, requests) = simulator(Ok(Bytes::new()));
+        let mut tx = withdraw(5);
+        sim.apply(&mut tx).unwrap();
+        assert_eq!(tx.gas_estimate, Some(48_213));
+
+        let request = &requests.lock().unwrap()[0];
+        assert_eq!(request.from, Some(SENDER.parse().unwrap()));
+        assert_eq!(
+            request.input.input().unwrap(),
+            &tx.calldata(&sim.contracts).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_decodes_reverts() {
+        let insufficient = error_data(
+            "InsufficientBalance(uint256,uint256)",
+            &[
+                DynSolValue::Uint(U256::from(5), 256),
+                DynSolValue::Uint(U256::from(10), 256),
+            ],
+        );
+        let revert_string: Bytes = Revert::from("vault is locked").abi_encode().into();
+
+        for (data, expected) in [
+            (
+                insufficient,
+                "InsufficientBalance(available: 5, required: 10)",
+            ),
+            (error_data("Paused()", &[]), "Paused()"),
+            (revert_string, "revert: vault is locked"),
+            (Bytes::new(), "execution reverted without a reason"),
+            (
+                Bytes::from(vec![0xde, 0xad, 0xbe, 0xef]),
+                "unknown error 0xdeadbeef",
+            ),
+        ] {
+            let (sim, _) = simulator(Err(RpcFailure::Reverted(data.clone())));
+            let err = sim.simulate(&withdraw(10)).unwrap_err();
+            assert_eq!(
+                err,
+                SimulationError::Reverted {
+                    to: VAULT.to_string(),
+                    reason: expected.to_string(),
+                    data,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn test_tool_results_are_simulated() {
+        let call = ToolCall {
+            id: "call_1".to_string(),
+            tool_type: "function".to_string(),
+            function: ToolCallFunction {
+                name: "send_eth".to_string(),
+                arguments: json!({"to": SENDER, "value": "1000"}).to_string(),
+            },
+        };
+
+        let (sim, _) = simulator(Ok(Bytes::new()));
+        let result = Tools::execute_tool_call(&call, None, &[], Some(&sim), None).unwrap();
+        let tx: Transaction = serde_json::from_str(&result).unwrap();
+        assert_eq!(tx.gas_estimate, Some(48_213));
+
+        // The model gets the reason instead of a transaction
+        let revert = Revert::from("insufficient funds").abi_encode().into();
+        let (sim, _) = simulator(Err(RpcFailure::Reverted(revert)));
+        let err = Tools::execute_tool_call(&call, None, &[], Some(&sim), None).unwrap_err();
+        assert!(
+            err.ends_with("would revert: revert: insufficient funds"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_invalid_and_unavailable() {
+        let (sim, requests) = simulator(Ok(Bytes::new()));
+        let mut tx = withdraw(1);
+        tx.to = "0x0987654321098765432109876543210987654321".to_string();
+        assert!(matches!(
+            sim.simulate(&tx),
+            Err(SimulationError::Invalid(_))
+        ));
+        assert!(requests.lock().unwrap().is_empty());
+
+        // Outside a component the host's RPC is not available
+        let sim = Simulator::new(
+            "http://localhost:8545",
+            SENDER.parse().unwrap(),
+            vec![Contract::new("Vault", VAULT, VAULT_ABI)],
+        );
+        assert!(matches!(
+            sim.simulate(&withdraw(1)),
+            Err(SimulationError::Unavailable(_))
+        ));
+    }
+}
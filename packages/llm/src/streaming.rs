@@ -0,0 +1,539 @@
+//! Incremental decoding of streamed chat responses
+//!
+//! Ollama streams newline-delimited JSON objects, while OpenAI-compatible
+//! servers and Anthropic send server-sent events with `data:` lines.
+//! [`StreamDecoder`] turns raw body chunks from either format into
+//! [`StreamEvent`]s and assembles the final message as it goes.
+
+use crate::client::Message;
+use crate::errors::LlmError;
+use crate::providers::{ChatCompletion, ProviderKind, Usage};
+use crate::tools::{ToolCall, ToolCallFunction};
+use serde_json::Value;
+
+/// An incremental update from a streamed chat response
+#[derive(Debug, Clone)]
+pub enum StreamEvent {
+    /// A piece of assistant text
+    ContentDelta(String),
+    /// A tool call whose name and arguments are complete
+    ToolCall(ToolCall),
+    /// The provider reported token usage
+    Usage(Usage),
+}
+
+/// Returned by stream callbacks to keep reading or cut generation short
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamControl {
+    /// Keep reading the stream
+    Continue,
+    /// Stop reading and return what has been assembled so far
+    Stop,
+}
+
+/// A tool call still being assembled from OpenAI or Anthropic fragments
+#[derive(Debug, Default)]
+struct PartialToolCall {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+impl PartialToolCall {
+    fn finish(self) -> ToolCall {
+        ToolCall {
+            id: self.id,
+            tool_type: "function".to_string(),
+            function: ToolCallFunction {
+                name: self.name,
+                arguments: if self.arguments.is_empty() {
+                    "{}".to_string()
+                } else {
+                    self.arguments
+                },
+            },
+        }
+    }
+}
+
+/// Decodes a streamed chat body chunk by chunk
+pub struct StreamDecoder {
+    kind: ProviderKind,
+    buffer: Vec<u8>,
+    content: String,
+    tool_calls: Vec<ToolCall>,
+    partial_calls: Vec<(u64, PartialToolCall)>,
+    usage: Option<Usage>,
+    done: bool,
+}
+
+impl StreamDecoder {
+    /// Create a decoder for the given provider's stream format
+    pub fn new(kind: ProviderKind) -> Self {
+        Self {
+            kind,
+            buffer: Vec::new(),
+            content: String::new(),
+            tool_calls: Vec::new(),
+            partial_calls: Vec::new(),
+            usage: None,
+            done: false,
+        }
+    }
+
+    /// Whether the provider has signalled the end of the stream
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Text received so far
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    /// Feed a chunk of the response body and return the events it completes
+    ///
+    /// Chunks may split lines anywhere; incomplete lines are buffered.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<Vec<StreamEvent>, LlmError> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            let line = String::from_utf8_lossy(&line);
+            self.decode_line(line.trim(), &mut events)?;
+        }
+
+        Ok(events)
+    }
+
+    /// Flush any buffered line and return the assembled response
+    pub fn finish(mut self) -> Result<(ChatCompletion, Vec<StreamEvent>), LlmError> {
+        let mut events = Vec::new();
+        if !self.buffer.is_empty() {
+            let rest = std::mem::take(&mut self.buffer);
+            let line = String::from_utf8_lossy(&rest);
+            self.decode_line(line.trim(), &mut events)?;
+        }
+        self.flush_partial_calls(&mut events);
+
+        let mut message = Message::assistant(self.content);
+        if !self.tool_calls.is_empty() {
+            message.tool_calls = Some(self.tool_calls);
+        }
+
+        Ok((
+            ChatCompletion {
+                message,
+                usage: self.usage,
+            },
+            events,
+        ))
+    }
+
+    fn decode_line(&mut self, line: &str, events: &mut Vec<StreamEvent>) -> Result<(), LlmError> {
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        match self.kind {
+            ProviderKind::Ollama => {
+                let chunk = parse_chunk(line)?;
+                check_stream_error(&chunk)?;
+                self.decode_ollama(&chunk, events);
+            }
+            ProviderKind::OpenAi | ProviderKind::Anthropic => {
+                // Only `data:` lines carry payloads; `event:`, `id:` and comments are skipped
+                let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+                    return Ok(());
+                };
+                if data == "[DONE]" {
+                    self.done = true;
+                    self.flush_partial_calls(events);
+                    return Ok(());
+                }
+                let chunk = parse_chunk(data)?;
+                check_stream_error(&chunk)?;
+                if self.kind == ProviderKind::OpenAi {
+                    self.decode_openai(&chunk, events);
+                } else {
+                    self.decode_anthropic(&chunk, events);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn push_content(&mut self, text: &str, events: &mut Vec<StreamEvent>) {
+        if !text.is_empty() {
+            self.content.push_str(text);
+            events.push(StreamEvent::ContentDelta(text.to_string()));
+        }
+    }
+
+    fn push_tool_call(&mut self, tool_call: ToolCall, events: &mut Vec<StreamEvent>) {
+        self.tool_calls.push(tool_call.clone());
+        events.push(StreamEvent::ToolCall(tool_call));
+    }
+
+    fn set_usage(&mut self, usage: Usage, events: &mut Vec<StreamEvent>) {
+        self.usage = Some(usage);
+        events.push(StreamEvent::Usage(usage));
+    }
+
+    fn flush_partial_calls(&mut self, events: &mut Vec<StreamEvent>) {
+        let mut partial = std::mem::take(&mut self.partial_calls);
+        partial.sort_by_key(|(index, _)| *index);
+        for (_, call) in partial {
+            self.push_tool_call(call.finish(), events);
+        }
+    }
+
+    /// Ollama sends whole tool calls and the usage counts on the final `done` chunk
+    fn decode_ollama(&mut self, chunk: &Value, events: &mut Vec<StreamEvent>) {
+        if let Some(text) = chunk.pointer("/message/content").and_then(|c| c.as_str()) {
+            self.push_content(text, events);
+        }
+
+        if let Some(calls) = chunk.pointer("/message/tool_calls") {
+            if let Ok(calls) = serde_json::from_value::<Vec<ToolCall>>(calls.clone()) {
+                for call in calls {
+                    self.push_tool_call(call, events);
+                }
+            }
+        }
+
+        if chunk["done"].as_bool() == Some(true) {
+            self.done = true;
+            let count = |key: &str| chunk[key].as_u64().unwrap_or_default() as u32;
+            self.set_usage(
+                Usage {
+                    prompt_tokens: count("prompt_eval_count"),
+                    completion_tokens: count("eval_count"),
+                },
+                events,
+            );
+        }
+    }
+
+    /// OpenAI splits tool calls into fragments keyed by `index`
+    fn decode_openai(&mut self, chunk: &Value, events: &mut Vec<StreamEvent>) {
+        let delta = &chunk["choices"][0]["delta"];
+
+        if let Some(text) = delta["content"].as_str() {
+            self.push_content(text, events);
+        }
+
+        for fragment in delta["tool_calls"].as_array().into_iter().flatten() {
+            let index = fragment["index"].as_u64().unwrap_or_default();
+            let position = match self.partial_calls.iter().position(|(i, _)| *i == index) {
+                Some(position) => position,
+                None => {
+                    self.partial_calls.push((index, PartialToolCall::default()));
+                    self.partial_calls.len() - 1
+                }
+            };
+            let call = &mut self.partial_calls[position].1;
+            if let Some(id) = fragment["id"].as_str() {
+                call.id = id.to_string();
+            }
+            if let Some(name) = fragment.pointer("/function/name").and_then(|n| n.as_str()) {
+                call.name.push_str(name);
+            }
+            if let Some(args) = fragment
+                .pointer("/function/arguments")
+                .and_then(|a| a.as_str())
+            {
+                call.arguments.push_str(args);
+            }
+        }
+
+        if chunk["choices"][0]["finish_reason"].is_string() {
+            self.flush_partial_calls(events);
+        }
+
+        if let Some(usage) = chunk.get("usage").filter(|u| u.is_object()) {
+            let count = |key: &str| usage[key].as_u64().unwrap_or_default() as u32;
+            self.set_usage(
+                Usage {
+                    prompt_tokens: count("prompt_tokens"),
+                    completion_tokens: count("completion_tokens"),
+                },
+                events,
+            );
+        }
+    }
+
+    /// Anthropic streams content blocks: text deltas and tool inputs as partial JSON
+    fn decode_anthropic(&mut self, chunk: &Value, events: &mut Vec<StreamEvent>) {
+        let index = chunk["index"].as_u64().unwrap_or_default();
+
+        match chunk["type"].as_str() {
+            Some("message_start") => {
+                let input = chunk.pointer("/message/usage/input_tokens");
+                self.usage = Some(Usage {
+                    prompt_tokens: input.and_then(|v| v.as_u64()).unwrap_or_default() as u32,
+                    completion_tokens: 0,
+                });
+            }
+            Some("content_block_start") => {
+                let block = &chunk["content_block"];
+                if block["type"] == "tool_use" {
+                    self.partial_calls.push((
+                        index,
+                        PartialToolCall {
+                            id: block["id"].as_str().unwrap_or_default().to_string(),
+                            name: block["name"].as_str().unwrap_or_default().to_string(),
+                            arguments: String::new(),
+                        },
+                    ));
+                }
+            }
+            Some("content_block_delta") => {
+                let delta = &chunk["delta"];
+                match delta["type"].as_str() {
+                    Some("text_delta") => {
+                        self.push_content(delta["text"].as_str().unwrap_or_default(), events)
+                    }
+                    Some("input_json_delta") => {
+                        if let Some((_, call)) =
+                            self.partial_calls.iter_mut().find(|(i, _)| *i == index)
+                        {
+                            call.arguments
+                                .push_str(delta["partial_json"].as_str().unwrap_or_default());
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Some("content_block_stop") => {
+                if let Some(position) = self.partial_calls.iter().position(|(i, _)| *i == index) {
+                    let (_, call) = self.partial_calls.remove(position);
+                    self.push_tool_call(call.finish(), events);
+                }
+            }
+            Some("message_delta") => {
+                if let Some(output) = chunk
+                    .pointer("/usage/output_tokens")
+                    .and_then(|v| v.as_u64())
+                {
+                    let mut usage = self.usage.unwrap_or_default();
+                    usage.completion_tokens = output as u32;
+                    self.set_usage(usage, events);
+                }
+            }
+            Some("message_stop") => {
+                self.done = true;
+                self.flush_partial_calls(events);
+            }
+            _ => {}
+        }
+    }
+}
+
+fn parse_chunk(data: &str) -> Result<Value, LlmError> {
+    serde_json::from_str(data)
+        .map_err(|e| LlmError::ParseError(format!("Failed to parse stream chunk: {}", e)))
+}
+
+/// Fail on an error the provider sent in place of a chunk
+///
+/// Ollama sends `{"error": "..."}`, OpenAI `{"error": {"message": ...}}` and
+/// Anthropic an event of `"type": "error"` with the same `error` object.
+fn check_stream_error(chunk: &Value) -> Result<(), LlmError> {
+    let error = match chunk.get("error") {
+        Some(error) if !error.is_null() => error,
+        _ if chunk["type"] == "error" => &Value::Null,
+        _ => return Ok(()),
+    };
+    let message = error
+        .as_str()
+        .or_else(|| error["message"].as_str())
+        .map(str::to_string)
+        .unwrap_or_else(|| chunk.to_string());
+    Err(LlmError::ApiError(format!(
+        "Provider reported an error in the stream: {}",
+        message
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deltas(events: &[StreamEvent]) -> String {
+        events
+            .iter()
+            .filter_map(|e| match e {
+                StreamEvent::ContentDelta(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_ollama_ndjson() {
+        let mut decoder = StreamDecoder::new(ProviderKind::Ollama);
+        let body = concat!(
+            r#"{"message":{"role":"assistant","content":"Hel"},"done":false}"#,
+            "\n",
+            r#"{"message":{"role":"assistant","content":"lo"},"done":false}"#,
+            "\n",
+            r#"{"message":{"role":"assistant","content":""},"done":true,"prompt_eval_count":10,"eval_count":2}"#,
+            "\n",
+        )
+        .as_bytes();
+
+        // Split mid-line to check buffering
+        let mut events = decoder.feed(&body[..30]).unwrap();
+        events.extend(decoder.feed(&body[30..]).unwrap());
+
+        assert_eq!(deltas(&events), "Hello");
+        assert!(decoder.is_done());
+
+        let (completion, _) = decoder.finish().unwrap();
+        assert_eq!(completion.message.content.as_deref(), Some("Hello"));
+        assert_eq!(completion.usage.unwrap().total_tokens(), 12);
+    }
+
+    #[test]
+    fn test_openai_sse_with_tool_calls() {
+        let mut decoder = StreamDecoder::new(ProviderKind::OpenAi);
+        let body = concat!(
+            ": keep-alive\n\n",
+            r#"data: {"choices":[{"delta":{"content":"Checking"}}]}"#,
+            "\n\n",
+            r#"data: {"choices":[{"delta":{"tool_calls":[{"index":0,"id":"call_1","function":{"name":"get_weather","arguments":"{\"loc"}}]}}]}"#,
+            "\n\n",
+            r#"data: {"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"ation\":\"Paris\"}"}}]}}]}"#,
+            "\n\n",
+            r#"data: {"choices":[{"delta":{},"finish_reason":"tool_calls"}]}"#,
+            "\n\n",
+            r#"data: {"choices":[],"usage":{"prompt_tokens":30,"completion_tokens":8}}"#,
+            "\n\n",
+            "data: [DONE]\n\n",
+        );
+
+        let events = decoder.feed(body.as_bytes()).unwrap();
+        assert_eq!(deltas(&events), "Checking");
+
+        let calls: Vec<_> = events
+            .iter()
+            .filter_map(|e| match e {
+                StreamEvent::ToolCall(call) => Some(call),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].id, "call_1");
+        assert_eq!(calls[0].function.arguments, r#"{"location":"Paris"}"#);
+        assert!(decoder.is_done());
+
+        let (completion, _) = decoder.finish().unwrap();
+        assert_eq!(completion.message.tool_calls.unwrap().len(), 1);
+        assert_eq!(completion.usage.unwrap().prompt_tokens, 30);
+    }
+
+    #[test]
+    fn test_anthropic_sse() {
+        let mut decoder = StreamDecoder::new(ProviderKind::Anthropic);
+        let body = concat!(
+            "event: message_start\n",
+            r#"data: {"type":"message_start","message":{"usage":{"input_tokens":15}}}"#,
+            "\n\n",
+            "event: content_block_delta\n",
+            r#"data: {"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hi"}}"#,
+            "\n\n",
+            r#"data: {"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"toolu_1","name":"send_eth"}}"#,
+            "\n\n",
+            r#"data: {"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"{\"to\":"}}"#,
+            "\n\n",
+            r#"data: {"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"\"0x1\"}"}}"#,
+            "\n\n",
+            r#"data: {"type":"content_block_stop","index":1}"#,
+            "\n\n",
+            r#"data: {"type":"message_delta","usage":{"output_tokens":4}}"#,
+            "\n\n",
+            r#"data: {"type":"message_stop"}"#,
+            "\n\n",
+        );
+
+        let events = decoder.feed(body.as_bytes()).unwrap();
+        assert_eq!(deltas(&events), "Hi");
+        assert!(events.iter().any(|e| matches!(
+            e,
+            StreamEvent::ToolCall(call) if call.function.arguments == r#"{"to":"0x1"}"#
+        )));
+
+        let (completion, _) = decoder.finish().unwrap();
+        assert_eq!(
+            completion.usage,
+            Some(Usage {
+                prompt_tokens: 15,
+                completion_tokens: 4
+            })
+        );
+    }
+
+    #[test]
+    fn test_stream_errors() {
+        let cases = [
+            (
+                ProviderKind::Ollama,
+                concat!(
+                    r#"{"message":{"role":"assistant","content":"Hel"},"done":false}"#,
+                    "\n",
+                    r#"{"error":"model runner has unexpectedly stopped"}"#,
+                    "\n"
+                ),
+                "model runner has unexpectedly stopped",
+            ),
+            (
+                ProviderKind::OpenAi,
+                concat!(
+                    r#"data: {"choices":[{"index":0,"delta":{"content":"Hel"}}]}"#,
+                    "\n\n",
+                    r#"data: {"error":{"message":"Rate limit reached","type":"requests"}}"#,
+                    "\n\n"
+                ),
+                "Rate limit reached",
+            ),
+            (
+                ProviderKind::Anthropic,
+                concat!(
+                    "event: error\n",
+                    r#"data: {"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+                    "\n\n"
+                ),
+                "Overloaded",
+            ),
+        ];
+
+        for (kind, body, expected) in cases {
+            let mut decoder = StreamDecoder::new(kind);
+            match decoder.feed(body.as_bytes()) {
+                Err(LlmError::ApiError(message)) => {
+                    assert!(message.contains(expected), "{:?}: {}", kind, message)
+                }
+                other => panic!("{:?}: expected an API error, got {:?}", kind, other),
+            }
+        }
+
+        // An explicit null is not an error
+        let mut decoder = StreamDecoder::new(ProviderKind::OpenAi);
+        let events = decoder
+            .feed(b"data: {\"choices\":[{\"delta\":{\"content\":\"ok\"}}],\"error\":null}\n")
+            .unwrap();
+        assert_eq!(deltas(&events), "ok");
+    }
+
+    #[test]
+    fn test_invalid_chunk() {
+        let mut decoder = StreamDecoder::new(ProviderKind::Ollama);
+        assert!(matches!(
+            decoder.feed(b"not json\n"),
+            Err(LlmError::ParseError(_))
+        ));
+    }
+}
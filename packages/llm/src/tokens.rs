@@ -0,0 +1,305 @@
+//! Token estimation and context-window fitting
+//!
+//! Providers count tokens with the model's own tokenizer, which is not
+//! available inside a component. [`ApproxTokenizer`] gives a cheap estimate
+//! close to what byte-pair tokenizers produce for English text and code; plug
+//! in a real [`Tokenizer`] where exact counts matter.
+
+use crate::client::Message;
+use crate::config::LlmOptions;
+use crate::errors::LlmError;
+use crate::tools::Tool;
+
+/// Tokens a chat template adds around every message (role markers, separators)
+pub const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// Tokens assumed per attached image; providers charge roughly 500-1600
+/// depending on resolution
+pub const IMAGE_TOKENS: usize = 1000;
+
+/// Counts the tokens in a piece of text
+pub trait Tokenizer: Send + Sync {
+    /// Number of tokens `text` encodes to
+    fn count(&self, text: &str) -> usize;
+}
+
+/// Byte-pair approximation: one token per four bytes of a word or number,
+/// one per punctuation character, whitespace folded into the next token
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ApproxTokenizer;
+
+impl Tokenizer for ApproxTokenizer {
+    fn count(&self, text: &str) -> usize {
+        let mut tokens = 0;
+        let mut word_bytes = 0;
+
+        for ch in text.chars() {
+            if ch.is_alphanumeric() {
+                word_bytes += ch.len_utf8();
+                continue;
+            }
+            tokens += word_bytes.div_ceil(4);
+            word_bytes = 0;
+            if !ch.is_whitespace() {
+                tokens += 1;
+            }
+        }
+
+        tokens + word_bytes.div_ceil(4)
+    }
+}
+
+/// What to do with older messages when a conversation outgrows the context window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ContextStrategy {
+    /// Drop the oldest non-system messages
+    #[default]
+    Trim,
+    /// Replace the oldest non-system messages with a model-written summary
+    Summarize,
+}
+
+/// Estimated tokens for one message, including template overhead
+pub fn message_tokens(tokenizer: &dyn Tokenizer, message: &Message) -> usize {
+    let mut tokens = MESSAGE_OVERHEAD_TOKENS + tokenizer.count(&message.role);
+    if let Some(content) = &message.content {
+        tokens += tokenizer.count(content);
+    }
+    for call in message.tool_calls.iter().flatten() {
+        tokens += tokenizer.count(&call.function.name) + tokenizer.count(&call.function.arguments);
+    }
+    if let Some(name) = &message.name {
+        tokens += tokenizer.count(name);
+    }
+    tokens + message.images.len() * IMAGE_TOKENS
+}
+
+/// Estimated tokens for a whole prompt: messages plus tool definitions
+pub fn prompt_tokens(
+    tokenizer: &dyn Tokenizer,
+    messages: &[Message],
+    tools: Option<&[Tool]>,
+) -> usize {
+    let messages: usize = messages.iter().map(|m| message_tokens(tokenizer, m)).sum();
+    messages + tools_tokens(tokenizer, tools)
+}
+
+fn tools_tokens(tokenizer: &dyn Tokenizer, tools: Option<&[Tool]>) -> usize {
+    tools
+        .filter(|t| !t.is_empty())
+        .and_then(|t| serde_json::to_string(t).ok())
+        .map(|json| tokenizer.count(&json))
+        .unwrap_or_default()
+}
+
+/// Tokens available for the prompt: the context window minus the reply budget
+///
+/// `None` when no context window is configured, meaning nothing is enforced.
+pub fn prompt_budget(options: &LlmOptions) -> Option<usize> {
+    options
+        .context_window
+        .map(|window| window.saturating_sub(options.max_tokens.unwrap_or(0)) as usize)
+}
+
+/// Split a conversation into the messages that fit in `budget` and the older ones that do not
+///
+/// System messages and the latest turn are always kept. Older messages are
+/// dropped oldest first, and an assistant tool call is dropped together with
+/// its results so no tool result is left without the call that produced it.
+pub fn fit_to_budget(
+    tokenizer: &dyn Tokenizer,
+    messages: &[Message],
+    tools: Option<&[Tool]>,
+    budget: usize,
+) -> Result<(Vec<Message>, Vec<Message>), LlmError> {
+    let total = prompt_tokens(tokenizer, messages, tools);
+    if total <= budget {
+        return Ok((messages.to_vec(), Vec::new()));
+    }
+
+    // Indices of non-system messages, grouped so tool results stay with their call
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    for (i, message) in messages.iter().enumerate() {
+        match message.role.as_str() {
+            "system" => {}
+            "tool" if !groups.is_empty() => groups.last_mut().unwrap().push(i),
+            _ => groups.push(vec![i]),
+        }
+    }
+
+    let mut excess = total - budget;
+    let mut dropped = vec![false; messages.len()];
+    // Never drop the latest turn
+    for group in groups.iter().take(groups.len().saturating_sub(1)) {
+        if excess == 0 {
+            break;
+        }
+        for &i in group {
+            dropped[i] = true;
+            excess = excess.saturating_sub(message_tokens(tokenizer, &messages[i]));
+        }
+    }
+
+    if excess > 0 {
+        return Err(LlmError::InvalidInput(format!(
+            "Prompt needs about {} tokens but only {} fit in the context window",
+            budget + excess,
+            budget
+        )));
+    }
+
+    let (kept, removed): (Vec<_>, Vec<_>) = messages
+        .iter()
+        .cloned()
+        .zip(dropped)
+        .partition(|(_, dropped)| !dropped);
+    Ok((
+        kept.into_iter().map(|(m, _)| m).collect(),
+        removed.into_iter().map(|(m, _)| m).collect(),
+    ))
+}
+
+/// Plain-text transcript of messages, for asking the model to summarize them
+pub(crate) fn transcript(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .map(|m| {
+            let mut line = format!("{}: {}", m.role, m.content.as_deref().unwrap_or_default());
+            if !m.images.is_empty() {
+                line.push_str(&format!(" [{} image(s)]", m.images.len()));
+            }
+            for call in m.tool_calls.iter().flatten() {
+                line.push_str(&format!(
+                    "\n{} called {}({})",
+                    m.role, call.function.name, call.function.arguments
+                ));
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::{ToolCall, ToolCallFunction};
+
+    #[test]
+    fn test_approx_tokenizer() {
+        let tokenizer = ApproxTokenizer;
+        assert_eq!(tokenizer.count(""), 0);
+        assert_eq!(tokenizer.count("hello"), 2);
+        assert_eq!(tokenizer.count("a b c"), 3);
+        assert_eq!(tokenizer.count("transfer(0x1234);"), 7);
+        assert!(tokenizer.count(&"word ".repeat(100)) >= 100);
+    }
+
+    #[test]
+    fn test_prompt_budget() {
+        assert_eq!(prompt_budget(&LlmOptions::default()), None);
+        let options = LlmOptions::new()
+            .with_context_window(4096)
+            .with_max_tokens(1000);
+        assert_eq!(prompt_budget(&options), Some(3096));
+        let options = LlmOptions::new()
+            .with_context_window(100)
+            .with_max_tokens(500);
+        assert_eq!(prompt_budget(&options), Some(0));
+    }
+
+    fn tool_call_turn() -> Vec<Message> {
+        let mut call = Message::assistant("");
+        call.tool_calls = Some(vec![ToolCall {
+            id: "call_1".to_string(),
+            tool_type: "function".to_string(),
+            function: ToolCallFunction {
+                name: "get_balance".to_string(),
+                arguments: "{}".to_string(),
+            },
+        }]);
+        vec![
+            call,
+            Message::tool_result("call_1".into(), "get_balance".into(), "100".into()),
+        ]
+    }
+
+    #[test]
+    fn test_fit_to_budget_drops_oldest_turns() {
+        let tokenizer = ApproxTokenizer;
+        let filler = "lorem ipsum dolor sit amet ".repeat(20);
+        let mut messages = vec![
+            Message::system("You are a treasury agent"),
+            Message::user(&filler),
+        ];
+        messages.extend(tool_call_turn());
+        messages.push(Message::assistant(&filler));
+        messages.push(Message::user("What now?"));
+
+        let total = prompt_tokens(&tokenizer, &messages, None);
+        let (kept, dropped) = fit_to_budget(&tokenizer, &messages, None, total).unwrap();
+        assert_eq!(kept.len(), messages.len());
+        assert!(dropped.is_empty());
+
+        // Room for the system prompt and last turn only
+        let minimal = prompt_tokens(
+            &tokenizer,
+            &[messages[0].clone(), messages[5].clone()],
+            None,
+        );
+        let (kept, dropped) = fit_to_budget(&tokenizer, &messages, None, minimal).unwrap();
+        assert_eq!(kept.len(), 2);
+        assert_eq!(kept[0].role, "system");
+        assert_eq!(kept[1].content.as_deref(), Some("What now?"));
+        assert_eq!(dropped.len(), 4);
+
+        // Dropping the first user message is enough; the tool call survives intact
+        let budget = total - message_tokens(&tokenizer, &messages[1]);
+        let (kept, _) = fit_to_budget(&tokenizer, &messages, None, budget).unwrap();
+        assert_eq!(kept.len(), 5);
+        assert!(kept[1].tool_calls.is_some());
+        assert_eq!(kept[2].role, "tool");
+    }
+
+    #[test]
+    fn test_fit_to_budget_keeps_tool_results_with_call() {
+        let tokenizer = ApproxTokenizer;
+        let mut messages = tool_call_turn();
+        messages.push(Message::user("Next"));
+
+        let budget = prompt_tokens(&tokenizer, &messages, None) - 1;
+        let (kept, dropped) = fit_to_budget(&tokenizer, &messages, None, budget).unwrap();
+        assert_eq!(kept.len(), 1);
+        assert_eq!(dropped.len(), 2);
+        assert_eq!(dropped[1].role, "tool");
+    }
+
+    #[test]
+    fn test_fit_to_budget_overflow() {
+        let tokenizer = ApproxTokenizer;
+        let messages = vec![Message::user("this will never fit")];
+        let err = fit_to_budget(&tokenizer, &messages, None, 2).unwrap_err();
+        assert!(err.to_string().contains("only 2 fit"));
+    }
+
+    #[test]
+    fn test_transcript() {
+        let mut messages = vec![Message::user("Check the balance")];
+        messages.extend(tool_call_turn());
+        let text = transcript(&messages);
+        assert!(text.starts_with("user: Check the balance"));
+        assert!(text.contains("assistant called get_balance({})"));
+        assert!(text.ends_with("tool: 100"));
+
+        let image = crate::image::Image::from_base64("iVBORw0KGgo=", "image/png");
+        let message = Message::user("Rate this").with_image(image);
+        assert_eq!(
+            transcript(std::slice::from_ref(&message)),
+            "user: Rate this [1 image(s)]"
+        );
+        assert_eq!(
+            message_tokens(&ApproxTokenizer, &message),
+            message_tokens(&ApproxTokenizer, &Message::user("Rate this")) + IMAGE_TOKENS
+        );
+    }
+}
@@ -0,0 +1,299 @@
+//! Tools provided by other components
+//!
+//! The `wavs:llm/tool-provider` interface (`wit/tools.wit`) describes a
+//! `handler` resource that lists tool definitions and executes tool calls.
+//! Tool providers can be built as separate wasm components and composed
+//! into an operator component:
+//!
+//! - A provider implements [`ToolProvider`] and exports it with
+//!   [`export_tool_provider!`](crate::export_tool_provider).
+//! - The operator imports it as a [`ComponentToolHandler`], which is a
+//!   [`CustomToolHandler`] like any native handler.
+//!
+//! Native trait objects keep working unchanged, so the same handler can be
+//! tested natively and shipped as a component.
+
+use crate::bindings::wavs::llm::tool_provider as import;
+use crate::errors::LlmError;
+use crate::tools::{CustomToolHandler, Function, Tool, ToolCall, ToolCallFunction};
+
+/// Bindings for the `tool-provider-component` world, used by [`export_tool_provider!`](crate::export_tool_provider)
+#[doc(hidden)]
+pub mod exports {
+    wit_bindgen::generate!({
+        path: "wit",
+        world: "wavs:llm/tool-provider-component",
+        pub_export_macro: true,
+        export_macro_name: "export_tool_provider_world",
+        default_bindings_module: "wavs_llm::tool_provider::exports",
+    });
+}
+
+use exports::exports::wavs::llm::tool_provider as export;
+
+/// A [`CustomToolHandler`] that also describes the tools it handles
+pub trait ToolProvider: CustomToolHandler {
+    /// Tool definitions offered to the model
+    fn tools(&self) -> Vec<Tool>;
+}
+
+/// Export a [`ToolProvider`] as the `wavs:llm/tool-provider` interface
+///
+/// The type must implement `Default`, which runs when the importing
+/// component constructs the `handler` resource.
+///
+/// ```no_run
+/// use wavs_llm::{CustomToolHandler, Tool, ToolCall, ToolProvider};
+///
+/// #[derive(Default)]
+/// struct PriceTools;
+///
+/// impl CustomToolHandler for PriceTools {
+///     fn can_handle(&self, tool_name: &str) -> bool {
+///         tool_name == "get_price"
+///     }
+///
+///     fn execute(&self, tool_call: &ToolCall) -> Result<String, String> {
+///         Ok(r#"{"usd": 1.0}"#.to_string())
+///     }
+/// }
+///
+/// impl ToolProvider for PriceTools {
+///     fn tools(&self) -> Vec<Tool> {
+///         vec![/* the get_price definition */]
+///     }
+/// }
+///
+/// wavs_llm::export_tool_provider!(PriceTools);
+/// # fn main() {}
+/// ```
+#[macro_export]
+macro_rules! export_tool_provider {
+    ($provider:ty) => {
+        struct __WavsLlmToolProvider;
+
+        impl $crate::tool_provider::exports::exports::wavs::llm::tool_provider::Guest
+            for __WavsLlmToolProvider
+        {
+            type Handler = $crate::tool_provider::ExportedHandler<$provider>;
+        }
+
+        $crate::tool_provider::exports::export_tool_provider_world!(
+            __WavsLlmToolProvider with_types_in $crate::tool_provider::exports
+        );
+    };
+}
+
+/// A [`ToolProvider`] behind the exported `handler` resource
+pub struct ExportedHandler<P> {
+    provider: P,
+}
+
+impl<P: ToolProvider + Default + 'static> export::GuestHandler for ExportedHandler<P> {
+    fn new() -> Self {
+        Self {
+            provider: P::default(),
+        }
+    }
+
+    fn tools(&self) -> Vec<export::ToolDefinition> {
+        self.provider
+            .tools()
+            .iter()
+            .map(|tool| {
+                let (name, description, parameters) = definition_parts(tool);
+                export::ToolDefinition {
+                    name,
+                    description,
+                    parameters,
+                }
+            })
+            .collect()
+    }
+
+    fn can_handle(&self, name: String) -> bool {
+        self.provider.can_handle(&name)
+    }
+
+    fn execute(&self, call: export::ToolCall) -> Result<String, String> {
+        self.provider
+            .execute(&tool_call(call.id, call.name, call.arguments))
+    }
+}
+
+/// Tools from a component exporting `wavs:llm/tool-provider`
+pub struct ComponentToolHandler {
+    handler: import::Handler,
+}
+
+impl ComponentToolHandler {
+    /// Construct the imported provider's `handler`
+    pub fn connect() -> Result<Self, LlmError> {
+        if cfg!(not(target_arch = "wasm32")) {
+            return Err(LlmError::ConfigError(
+                "Component tool providers are only available inside a WASI component".to_string(),
+            ));
+        }
+        Ok(Self {
+            handler: import::Handler::new(),
+        })
+    }
+
+    /// Tool definitions the provider offers
+    pub fn tools(&self) -> Result<Vec<Tool>, LlmError> {
+        self.handler
+            .tools()
+            .into_iter()
+            .map(|definition| {
+                tool_from_parts(
+                    definition.name,
+                    definition.description,
+                    definition.parameters,
+                )
+            })
+            .collect()
+    }
+}
+
+impl CustomToolHandler for ComponentToolHandler {
+    fn can_handle(&self, tool_name: &str) -> bool {
+        self.handler.can_handle(tool_name)
+    }
+
+    fn execute(&self, tool_call: &ToolCall) -> Result<String, String> {
+        self.handler.execute(&import::ToolCall {
+            id: tool_call.id.clone(),
+            name: tool_call.function.name.clone(),
+            arguments: tool_call.function.arguments.clone(),
+        })
+    }
+}
+
+/// Name, description and JSON parameters of a tool, as the WIT record holds them
+fn definition_parts(tool: &Tool) -> (String, Option<String>, Option<String>) {
+    (
+        tool.function.name.clone(),
+        tool.function.description.clone(),
+        tool.function
+            .parameters
+            .as_ref()
+            .map(|parameters| parameters.to_string()),
+    )
+}
+
+fn tool_from_parts(
+    name: String,
+    description: Option<String>,
+    parameters: Option<String>,
+) -> Result<Tool, LlmError> {
+    let parameters = parameters
+        .map(|json| serde_json::from_str(&json))
+        .transpose()
+        .map_err(|e| {
+            LlmError::ParseError(format!("Tool {} has invalid parameters: {}", name, e))
+        })?;
+    Ok(Tool {
+        tool_type: "function".to_string(),
+        function: Function {
+            name,
+            description,
+            parameters,
+        },
+    })
+}
+
+fn tool_call(id: String, name: String, arguments: String) -> ToolCall {
+    ToolCall {
+        id,
+        tool_type: "function".to_string(),
+        function: ToolCallFunction { name, arguments },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use export::GuestHandler;
+    use serde_json::json;
+
+    /// Adds two numbers
+    #[derive(Default)]
+    struct Calculator;
+
+    impl CustomToolHandler for Calculator {
+        fn can_handle(&self, tool_name: &str) -> bool {
+            tool_name == "add"
+        }
+
+        fn execute(&self, tool_call: &ToolCall) -> Result<String, String> {
+            let args: serde_json::Value =
+                serde_json::from_str(&tool_call.function.arguments).map_err(|e| e.to_string())?;
+            match (args["a"].as_i64(), args["b"].as_i64()) {
+                (Some(a), Some(b)) => Ok((a + b).to_string()),
+                _ => Err("a and b must be integers".to_string()),
+            }
+        }
+    }
+
+    impl ToolProvider for Calculator {
+        fn tools(&self) -> Vec<Tool> {
+            vec![Tool {
+                tool_type: "function".to_string(),
+                function: Function {
+                    name: "add".to_string(),
+                    description: Some("Add two integers".to_string()),
+                    parameters: Some(json!({
+                        "type": "object",
+                        "properties": {"a": {"type": "integer"}, "b": {"type": "integer"}},
+                        "required": ["a", "b"]
+                    })),
+                },
+            }]
+        }
+    }
+
+    #[test]
+    fn test_exported_handler() {
+        let handler = ExportedHandler::<Calculator>::new();
+        let tools = handler.tools();
+        assert_eq!(tools.len(), 1);
+        assert_eq!(tools[0].name, "add");
+
+        // Definitions survive the trip through the interface
+        let tool = tool_from_parts(
+            tools[0].name.clone(),
+            tools[0].description.clone(),
+            tools[0].parameters.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            tool.function.parameters,
+            Calculator.tools()[0].function.parameters
+        );
+
+        assert!(handler.can_handle("add".to_string()));
+        assert!(!handler.can_handle("subtract".to_string()));
+        let call = |arguments: &str| export::ToolCall {
+            id: "call_1".to_string(),
+            name: "add".to_string(),
+            arguments: arguments.to_string(),
+        };
+        assert_eq!(
+            handler.execute(call(r#"{"a": 2, "b": 3}"#)),
+            Ok("5".to_string())
+        );
+        assert!(handler.execute(call(r#"{"a": "2"}"#)).is_err());
+    }
+
+    #[test]
+    fn test_invalid_definitions_and_native_import() {
+        let err = tool_from_parts("add".to_string(), None, Some("{".to_string())).unwrap_err();
+        assert!(err.to_string().contains("Tool add has invalid parameters"));
+        assert!(tool_from_parts("add".to_string(), None, None).is_ok());
+
+        assert!(matches!(
+            ComponentToolHandler::connect(),
+            Err(LlmError::ConfigError(_))
+        ));
+    }
+}
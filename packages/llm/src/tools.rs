@@ -1,7 +1,16 @@
-use crate::client::{LLMClient, Message};
-use crate::contracts::{Contract, ContractCall, Transaction};
+use crate::client::{extract_json, Message};
+use crate::contracts::{Contract, ContractCall, MethodDoc, Transaction};
+use crate::encoding::{argument_name, named_function_args, param_schema};
+use crate::errors::AgentError;
+use crate::policy::PolicyEngine;
+use crate::providers::ModelCapabilities;
+use crate::registry::{ContractTool, ToolRegistry};
+use crate::simulation::Simulator;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+use std::future::Future;
+use std::pin::Pin;
 
 /// Function parameter for tool calls
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,7 +42,7 @@ pub struct Tool {
 }
 
 /// Tool call for chat completions
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ToolCall {
     #[serde(default = "default_tool_id")]
     pub id: String,
@@ -44,7 +53,7 @@ pub struct ToolCall {
 }
 
 /// Function call details
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ToolCallFunction {
     pub name: String,
     #[serde(default)]
@@ -114,131 +123,103 @@ impl Tools {
     }
 
     /// Generate a tool from a smart contract's ABI
+    ///
+    /// One tool per state-changing function. Parameter schemas follow the
+    /// full ABI types (see [`crate::encoding::param_schema`]), and when the
+    /// contract's ABI is a compiler artifact its NatSpec `@notice`, `@dev` and
+    /// `@param` comments become the tool and parameter descriptions.
     pub fn tools_from_contract(contract: &Contract) -> Vec<Tool> {
-        let mut tools = Vec::new();
-        println!("Generating tools from contract: {}", contract.name);
-        // Parse the ABI
-        let abi_value: Result<serde_json::Value, _> = serde_json::from_str(&contract.abi);
-        if abi_value.is_err() {
-            println!("Failed to parse ABI: {:?}", abi_value.err());
-            return tools;
-        }
-
-        let abi = abi_value.unwrap();
-
-        println!("ABI: {:?}", abi);
-        // ABI can be either an array or an object with an "abi" field
-        let functions = if abi.is_array() {
-            abi.as_array().unwrap()
-        } else if abi.is_object() && abi.get("abi").is_some() && abi["abi"].is_array() {
-            abi["abi"].as_array().unwrap()
-        } else {
-            println!("Unexpected ABI format");
-            return tools;
-        };
-
-        println!("Functions: {:?}", functions);
-
-        // Process each function in the ABI
-        for func in functions {
-            // Skip if not a function or is not externally callable
-            // Handle both newer ABIs with stateMutability and older ABIs with constant field
-            if !func.is_object()
-                || func.get("type").is_none()
-                || func["type"] != "function"
-                || (func.get("stateMutability").is_none() && func.get("constant").is_none())
-                || (func.get("stateMutability").is_some()
-                    && func["stateMutability"] != "nonpayable"
-                    && func["stateMutability"] != "payable")
-                || (func.get("constant").is_some() && func["constant"] == true)
-            {
-                continue;
-            }
-
-            let name = match func.get("name") {
-                Some(n) if n.is_string() => n.as_str().unwrap(),
-                _ => continue, // Skip if no valid name
-            };
+        ToolRegistry::new(std::slice::from_ref(contract)).write_tools()
+    }
 
-            // Create properties for the function inputs
-            let mut properties = json!({});
-            let mut required = Vec::new();
+    /// Generate read tools for a contract's `view` and `pure` functions
+    ///
+    /// The tools are executed by a [`crate::reader::ContractReader`], which
+    /// runs an `eth_call` and returns the decoded result to the model.
+    pub fn read_tools_from_contract(contract: &Contract) -> Vec<Tool> {
+        ToolRegistry::new(std::slice::from_ref(contract)).read_tools()
+    }
 
-            // Add value field for payable functions
-            if func["stateMutability"] == "payable" {
-                properties["value"] = json!({
+    /// Build the tool for one registered contract function
+    pub(crate) fn contract_function_tool(tool: &ContractTool) -> Result<Tool, AgentError> {
+        let ContractTool {
+            contract,
+            function,
+            docs,
+            ..
+        } = tool;
+        let mut properties = serde_json::Map::new();
+        let mut required = Vec::new();
+
+        // Add value field for payable functions
+        if tool.is_payable() {
+            properties.insert(
+                "value".to_string(),
+                json!({
                     "type": "string",
-                    "description": "Amount of ETH to send with the call (in wei)"
-                });
-                required.push("value");
-            }
-
-            // Process function inputs
-            if let Some(inputs) = func.get("inputs").and_then(|i| i.as_array()) {
-                for input in inputs {
-                    if let (Some(param_name), Some(param_type)) = (
-                        input.get("name").and_then(|n| n.as_str()),
-                        input.get("type").and_then(|t| t.as_str()),
-                    ) {
-                        // Only skip empty param names
-                        if param_name.is_empty() {
-                            continue;
-                        }
-
-                        // Convert Solidity type to JSON Schema type
-                        let (json_type, format) = Self::solidity_type_to_json_schema(param_type);
-
-                        let mut param_schema = json!({
-                            "type": json_type,
-                            "description": format!("{} ({})", param_name, param_type)
-                        });
-
-                        // Add format if specified
-                        if let Some(fmt) = format {
-                            param_schema["format"] = json!(fmt);
-                        }
-
-                        properties[param_name] = param_schema;
-                        required.push(param_name);
-                    }
-                }
-            }
+                    "description": "Amount of ETH to send with the call (in wei)",
+                    "pattern": "^[0-9]+$"
+                }),
+            );
+            required.push("value".to_string());
+        }
 
-            // Create the tool for this function
-            let tool_name = format!("contract_{}_{}", contract.name.to_lowercase(), name);
-            let tool = Tool {
-                tool_type: "function".to_string(),
-                function: Function {
-                    name: tool_name.clone(),
-                    description: Some(format!(
-                        "Call the {} function on the {} contract at {}",
-                        name, contract.name, contract.address
-                    )),
-                    parameters: Some(json!({
-                        "type": "object",
-                        "properties": properties,
-                        "required": required
-                    })),
-                },
-            };
+        for (i, input) in function.inputs.iter().enumerate() {
+            let name = argument_name(input, i);
+            let description = docs
+                .as_ref()
+                .and_then(|d| d.params.get(&input.name))
+                .map(|doc| format!("{} ({})", doc.trim(), input.selector_type()))
+                .unwrap_or_else(|| format!("{} ({})", name, input.selector_type()));
 
-            tools.push(tool);
+            properties.insert(name.clone(), param_schema(input, Some(&description))?);
+            required.push(name);
         }
 
-        tools
-    }
+        // Let the model explain transactions unless the function has its own `description`
+        if !tool.is_read_only() && !properties.contains_key("description") {
+            properties.insert(
+                "description".to_string(),
+                json!({
+                    "type": "string",
+                    "description": "Why this transaction should be sent, in plain language"
+                }),
+            );
+        }
 
-    /// Convert Solidity type to JSON Schema type
-    fn solidity_type_to_json_schema(solidity_type: &str) -> (&'static str, Option<&'static str>) {
-        match solidity_type {
-            t if t.starts_with("uint") => ("string", None), // Use string for all integers to handle large numbers
-            t if t.starts_with("int") => ("string", None),
-            "address" => ("string", Some("ethereum-address")),
-            "bool" => ("boolean", None),
-            "string" => ("string", None),
-            t if t.starts_with("bytes") => ("string", Some("byte")),
-            _ => ("string", None), // Default to string for unknown types
+        // Overloads are told apart by their full signature
+        let function_name = if tool.overloaded {
+            function.signature()
+        } else {
+            function.name.clone()
+        };
+        let mut description = if tool.is_read_only() {
+            format!(
+                "Read {} from the {} contract at {}",
+                function_name, contract.name, contract.address
+            )
+        } else {
+            format!(
+                "Call the {} function on the {} contract at {}",
+                function_name, contract.name, contract.address
+            )
+        };
+        if let Some(doc) = docs.as_ref().and_then(MethodDoc::description) {
+            description = format!("{}: {}", description, doc);
         }
+
+        Ok(Tool {
+            tool_type: "function".to_string(),
+            function: Function {
+                name: tool.name.clone(),
+                description: Some(description),
+                parameters: Some(json!({
+                    "type": "object",
+                    "properties": properties,
+                    "required": required
+                })),
+            },
+        })
     }
 
     /// Create a custom tool with the specified name, description, and parameters
@@ -275,34 +256,133 @@ impl Tools {
     }
 
     /// Execute a tool call and return the result
+    ///
+    /// Custom handlers get the first chance. Built-in transaction tools look
+    /// contracts up in `contracts` (the request's configured contracts). When
+    /// `contracts` is not empty, any result that parses as a [`Transaction`],
+    /// including a custom handler's, must pass
+    /// [`Transaction::validate_transaction`] or is returned as an error.
+    /// With a `simulator`, transactions are dry-run first: one that would
+    /// revert becomes an error, one that succeeds gets its gas estimate.
+    /// With a `policy`, every result is checked against it, and a denial is
+    /// returned as the error so the model sees why.
     pub fn execute_tool_call(
         tool_call: &ToolCall,
         custom_handlers: Option<&[Box<dyn CustomToolHandler>]>,
+        contracts: &[Contract],
+        simulator: Option<&Simulator>,
+        policy: Option<&PolicyEngine>,
     ) -> Result<String, String> {
-        let function_name = &tool_call.function.name;
+        let mut result = match Self::find_handler(tool_call, custom_handlers) {
+            Some(handler) => handler.execute(tool_call)?,
+            None => Self::run_builtin_tool_call(tool_call, contracts)?,
+        };
+        Self::validate_result(&result, contracts)?;
+        if let Some(simulator) = simulator {
+            if let Ok(mut transaction) = serde_json::from_str::<Transaction>(&result) {
+                simulator
+                    .apply(&mut transaction)
+                    .map_err(|e| e.to_string())?;
+                result = Self::transaction_json(&transaction)?;
+            }
+        }
+        Self::check_policy(tool_call, result, contracts, policy)
+    }
 
-        // First, check if any custom handlers can handle this tool
-        if let Some(handlers) = custom_handlers {
-            for handler in handlers {
-                if handler.can_handle(function_name) {
-                    return handler.execute(tool_call);
-                }
+    /// Async version of [`Tools::execute_tool_call`]
+    ///
+    /// Handlers run through [`CustomToolHandler::execute_async`] and
+    /// transactions are simulated with [`Simulator::apply_async`].
+    pub async fn execute_tool_call_async(
+        tool_call: &ToolCall,
+        custom_handlers: Option<&[Box<dyn CustomToolHandler>]>,
+        contracts: &[Contract],
+        simulator: Option<&Simulator>,
+        policy: Option<&PolicyEngine>,
+    ) -> Result<String, String> {
+        let mut result = match Self::find_handler(tool_call, custom_handlers) {
+            Some(handler) => handler.execute_async(tool_call).await?,
+            None => Self::run_builtin_tool_call(tool_call, contracts)?,
+        };
+        Self::validate_result(&result, contracts)?;
+        if let Some(simulator) = simulator {
+            if let Ok(mut transaction) = serde_json::from_str::<Transaction>(&result) {
+                simulator
+                    .apply_async(&mut transaction)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                result = Self::transaction_json(&transaction)?;
             }
         }
+        Self::check_policy(tool_call, result, contracts, policy)
+    }
+
+    /// The first custom handler that handles this tool
+    fn find_handler<'h>(
+        tool_call: &ToolCall,
+        custom_handlers: Option<&'h [Box<dyn CustomToolHandler>]>,
+    ) -> Option<&'h dyn CustomToolHandler> {
+        custom_handlers?
+            .iter()
+            .find(|handler| handler.can_handle(&tool_call.function.name))
+            .map(|handler| handler.as_ref())
+    }
+
+    /// Validate a result against `contracts`, if it is a transaction and there are any
+    fn validate_result(result: &str, contracts: &[Contract]) -> Result<(), String> {
+        if contracts.is_empty() {
+            return Ok(());
+        }
+        match serde_json::from_str::<Transaction>(result) {
+            Ok(transaction) => Transaction::validate_transaction(&transaction, contracts)
+                .map_err(|e| e.to_string()),
+            Err(_) => Ok(()),
+        }
+    }
+
+    fn transaction_json(transaction: &Transaction) -> Result<String, String> {
+        serde_json::to_string(transaction)
+            .map_err(|e| format!("Failed to serialize transaction: {}", e))
+    }
+
+    fn check_policy(
+        tool_call: &ToolCall,
+        result: String,
+        contracts: &[Contract],
+        policy: Option<&PolicyEngine>,
+    ) -> Result<String, String> {
+        if let Some(policy) = policy {
+            policy
+                .evaluate(tool_call, &result, contracts)
+                .map_err(|denial| denial.to_string())?;
+        }
+        Ok(result)
+    }
+
+    /// Run a tool no custom handler claimed
+    fn run_builtin_tool_call(
+        tool_call: &ToolCall,
+        contracts: &[Contract],
+    ) -> Result<String, String> {
+        let function_name = &tool_call.function.name;
 
-        // If no custom handlers or none matched, use built-in handlers
         match function_name.as_str() {
-            "send_eth" => Self::parse_eth_transaction(tool_call),
+            "send_eth" => Self::parse_eth_transaction(tool_call, contracts),
             // Handle dynamically generated contract tools
             _ if function_name.starts_with("contract_") => {
-                Self::parse_contract_function_call(tool_call)
+                Self::parse_contract_function_call(tool_call, contracts)
             }
             _ => Err(format!("Unknown tool: {}", function_name)),
         }
     }
 
     /// Parse an ETH transaction from tool call
-    pub fn parse_eth_transaction(tool_call: &ToolCall) -> Result<String, String> {
+    ///
+    /// Calldata in `data` is validated against `contracts`.
+    pub fn parse_eth_transaction(
+        tool_call: &ToolCall,
+        contracts: &[Contract],
+    ) -> Result<String, String> {
         // Parse the tool call arguments
         let args: Value = serde_json::from_str(&tool_call.function.arguments)
             .map_err(|e| format!("Failed to parse transaction arguments: {}", e))?;
@@ -320,7 +400,9 @@ impl Tools {
                 .unwrap_or("ETH transfer")
                 .to_string(),
             contract_call: None,
+            gas_estimate: None,
         };
+        Transaction::validate_transaction(&transaction, contracts)?;
 
         // Serialize back to a string for passing between functions
         let tx_json = serde_json::to_string(&transaction)
@@ -330,70 +412,80 @@ impl Tools {
     }
 
     /// Parse a contract function call from a dynamic tool
-    fn parse_contract_function_call(tool_call: &ToolCall) -> Result<String, String> {
-        // Extract contract name and function from the tool name
-        // Format is "contract_{contract_name}_{function_name}"
-        let parts: Vec<&str> = tool_call.function.name.splitn(3, '_').collect();
-        if parts.len() < 3 {
+    fn parse_contract_function_call(
+        tool_call: &ToolCall,
+        contracts: &[Contract],
+    ) -> Result<String, String> {
+        let registry = ToolRegistry::new(contracts);
+        let tool = registry.resolve(&tool_call.function.name).ok_or_else(|| {
+            format!(
+                "Unknown contract tool {}; known contracts: {}",
+                tool_call.function.name,
+                contracts
+                    .iter()
+                    .map(|c| c.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })?;
+        if tool.is_read_only() {
             return Err(format!(
-                "Invalid contract tool name: {}",
+                "{} is read-only and needs a ContractReader to run it",
                 tool_call.function.name
             ));
         }
-
-        let contract_name = parts[1];
-        let function_name = parts[2];
+        let ContractTool {
+            contract, function, ..
+        } = tool;
 
         // Parse the arguments
         let args: Value = serde_json::from_str(&tool_call.function.arguments)
             .map_err(|e| format!("Failed to parse function arguments: {}", e))?;
 
-        // Get the contract from context to check ABI
-        let context = crate::config::Config::default();
-        let contract = context
-            .get_contract_by_name(contract_name)
-            .ok_or_else(|| format!("Unknown contract: {}", contract_name))?;
-
-        // Check if this function is payable by examining the ABI
-        let is_payable = contract
-            .abi
-            .contains(&format!("\"name\":\"{}\",", function_name))
-            && contract.abi.contains("\"stateMutability\":\"payable\"");
-
-        // Extract args for the function call
-        let mut function_args = Vec::new();
-        let mut value = "0".to_string();
-
-        // Collect all args except 'value' (for ETH transfers)
-        for (key, val) in args.as_object().unwrap() {
-            if key == "value" {
-                // For ERC20 transfers and other nonpayable functions, include "value"
-                // as a function argument but don't set ETH value
-                function_args.push(val.clone());
-
-                // Only set transaction ETH value for payable functions
-                if is_payable {
-                    value = val.as_str().unwrap_or("0").to_string();
-                }
-            } else {
-                function_args.push(val.clone());
+        // Arguments arrive keyed by name; the call needs them in ABI order
+        let function_args = named_function_args(function, &args)?;
+
+        // Only payable functions send ETH. For anything else a `value` key is
+        // the function's own argument (e.g. ERC20 `transfer(to, value)`).
+        let value = if tool.is_payable() {
+            match args.get("value") {
+                Some(Value::String(s)) => s.clone(),
+                Some(Value::Number(n)) => n.to_string(),
+                _ => "0".to_string(),
             }
-        }
+        } else {
+            "0".to_string()
+        };
 
-        // Create contract call
-        let contract_call = Some(ContractCall {
-            function: function_name.to_string(),
-            args: function_args,
-        });
+        // Overloads are named by signature so the call encodes the right one
+        let function_id = if tool.overloaded {
+            function.signature()
+        } else {
+            function.name.clone()
+        };
+        let data = contract.encode_function_call(&function_id, &function_args)?;
+
+        let has_description_arg = function.inputs.iter().any(|i| i.name == "description");
+        let description = args
+            .get("description")
+            .and_then(Value::as_str)
+            .filter(|d| !has_description_arg && !d.trim().is_empty())
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("Calling {} on {} contract", function_id, contract.name));
 
         // Create a Transaction targeting the contract
         let transaction = Transaction {
             to: contract.address.clone(),
             value,
-            data: "0x".to_string(), // Will be encoded by the execution layer
-            description: format!("Calling {} on {} contract", function_name, contract_name),
-            contract_call,
+            data: data.to_string(),
+            description,
+            contract_call: Some(ContractCall {
+                function: function_id,
+                args: function_args,
+            }),
+            gas_estimate: None,
         };
+        Transaction::validate_transaction(&transaction, contracts)?;
 
         // Serialize to JSON
         let tx_json = serde_json::to_string(&transaction)
@@ -402,96 +494,189 @@ impl Tools {
         Ok(tx_json)
     }
 
-    /// Process tool calls and generate a response
+    /// Execute the tool calls in an assistant turn and build the messages to
+    /// append to the conversation: the assistant turn itself, then one result per call
+    ///
+    /// The model's capabilities decide the shape: native tool callers get `tool`
+    /// role results linked by call ID, prompted models get plain user messages,
+    /// and models without parallel calls only have their first call executed.
+    /// Failed calls are reported to the model as error results rather than
+    /// aborting, so it can correct its arguments on the next turn.
     pub fn process_tool_calls(
-        client: &LLMClient,
-        initial_messages: Vec<Message>,
-        response: Message,
-        tool_calls: Vec<ToolCall>,
+        response: &Message,
+        capabilities: &ModelCapabilities,
         custom_handlers: Option<&[Box<dyn CustomToolHandler>]>,
-    ) -> Result<String, String> {
-        // No block_on needed - all operations are sync or handle their own async
-        println!("Processing tool calls...");
-
-        // Check if we're using Ollama based on the model name
-        let model = client.get_model();
-        // TODO: This is a hack and could be improved
-        let is_ollama =
-            model.starts_with("llama") || model.starts_with("mistral") || !model.contains("gpt");
+        contracts: &[Contract],
+        simulator: Option<&Simulator>,
+        policy: Option<&PolicyEngine>,
+    ) -> Vec<Message> {
+        let (turn, tool_calls) = Self::tool_turn(response, capabilities);
+        let mut messages = vec![turn];
+        for tool_call in &tool_calls {
+            let result =
+                Self::execute_tool_call(tool_call, custom_handlers, contracts, simulator, policy);
+            messages.push(Self::tool_result_message(capabilities, tool_call, result));
+        }
+        messages
+    }
 
-        // Process each tool call and collect the results
-        let mut tool_results = Vec::new();
+    /// Async version of [`Tools::process_tool_calls`]
+    pub async fn process_tool_calls_async(
+        response: &Message,
+        capabilities: &ModelCapabilities,
+        custom_handlers: Option<&[Box<dyn CustomToolHandler>]>,
+        contracts: &[Contract],
+        simulator: Option<&Simulator>,
+        policy: Option<&PolicyEngine>,
+    ) -> Vec<Message> {
+        let (turn, tool_calls) = Self::tool_turn(response, capabilities);
+        let mut messages = vec![turn];
         for tool_call in &tool_calls {
-            let tool_result = Self::execute_tool_call(tool_call, custom_handlers)?;
-            println!("Tool result: {}", tool_result);
-            tool_results.push(tool_result);
+            let result = Self::execute_tool_call_async(
+                tool_call,
+                custom_handlers,
+                contracts,
+                simulator,
+                policy,
+            )
+            .await;
+            messages.push(Self::tool_result_message(capabilities, tool_call, result));
         }
+        messages
+    }
 
-        if is_ollama {
-            // For Ollama: Don't make a second call, just use the tool result directly
-            println!("Using direct tool result handling for Ollama");
+    /// The assistant turn to keep in the conversation and the calls to execute
+    fn tool_turn(response: &Message, capabilities: &ModelCapabilities) -> (Message, Vec<ToolCall>) {
+        let mut tool_calls = response.tool_calls.clone().unwrap_or_default();
+        if !capabilities.parallel_tool_calls {
+            tool_calls.truncate(1);
+        }
 
-            if tool_results.len() == 1 {
-                Ok(tool_results[0].clone())
-            } else {
-                // For multiple tool calls, combine the results
-                Ok(tool_results.join("\n"))
+        let content = response.content.clone().unwrap_or_default();
+        let turn = if capabilities.native_tools {
+            // Providers need the original calls to match results to them
+            Message {
+                content: Some(content),
+                tool_calls: Some(tool_calls.clone()),
+                ..response.clone()
             }
         } else {
-            // For OpenAI: Use the standard tool calls protocol
-            println!("Using OpenAI-compatible tool call handling");
-            let mut tool_messages = initial_messages.clone();
-
-            // Add the assistant's response with tool calls, ensuring content is not null
-            // When we're sending tool calls, OpenAI requires content to be a string (even if empty)
-            // We MUST preserve the original tool_calls so OpenAI can match the tool responses
-            let sanitized_response = Message {
-                role: response.role,
-                content: Some(response.content.unwrap_or_default()),
-                tool_calls: Some(tool_calls.clone()), // Important: preserve the tool_calls!
-                tool_call_id: response.tool_call_id,
-                name: response.name,
-            };
-            tool_messages.push(sanitized_response);
-
-            // Process each tool call and add the results
-            for (i, tool_call) in tool_calls.iter().enumerate() {
-                tool_messages.push(Message::tool_result(
-                    tool_call.id.clone(),
-                    tool_call.function.name.clone(),
-                    tool_results[i].clone(),
-                ));
-            }
+            Message::assistant(content)
+        };
+        (turn, tool_calls)
+    }
 
-            // Call OpenAI to get final response, but we don't use it for parsing
-            // It's mainly for human readable confirmation
-            let final_response = client.chat(tool_messages.clone()).text();
-            println!(
-                "OpenAI final response (for logs only): {:?}",
-                final_response
-            );
+    /// The message reporting a tool's result (or error) back to the model
+    fn tool_result_message(
+        capabilities: &ModelCapabilities,
+        tool_call: &ToolCall,
+        result: Result<String, String>,
+    ) -> Message {
+        let result = match result {
+            Ok(result) => result,
+            Err(e) => format!("Error: {}", e),
+        };
+        println!("Tool {} result: {}", tool_call.function.name, result);
+
+        if capabilities.native_tools {
+            Message::tool_result(
+                tool_call.id.clone(),
+                tool_call.function.name.clone(),
+                result,
+            )
+        } else {
+            Message::user(format!(
+                "Result of tool {} (call {}):\n{}",
+                tool_call.function.name, tool_call.id, result
+            ))
+        }
+    }
 
-            // Return the original tool result which contains valid JSON
-            // Only handle the first tool result for now since we expect a single transaction
-            if !tool_results.is_empty() {
-                Ok(tool_results[0].clone())
-            } else {
-                Err("No tool results available".to_string())
-            }
+    /// System prompt describing tools to a model without native tool calling
+    pub fn prompted_tool_instructions(tools: &[Tool]) -> String {
+        let definitions = tools
+            .iter()
+            .map(|tool| json!(tool.function).to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        format!(
+            "You can call the following tools. To call tools, reply with only a JSON object \
+             of the form {{\"tool_calls\": [{{\"name\": \"<tool name>\", \"arguments\": {{...}}}}]}} \
+             and nothing else. Tool results will be sent back to you. \
+             When no tool is needed, answer normally.\n\nTools:\n{}",
+            definitions
+        )
+    }
+
+    /// Parse tool calls a prompted model wrote into its reply text
+    pub fn parse_prompted_tool_calls(content: &str) -> Option<Vec<ToolCall>> {
+        #[derive(Deserialize)]
+        struct PromptedCall {
+            name: String,
+            #[serde(default)]
+            arguments: Value,
+        }
+
+        #[derive(Deserialize)]
+        struct PromptedCalls {
+            tool_calls: Vec<PromptedCall>,
         }
+
+        let json = extract_json(content).ok()?;
+        let calls: PromptedCalls = serde_json::from_str(&json).ok()?;
+        if calls.tool_calls.is_empty() {
+            return None;
+        }
+
+        Some(
+            calls
+                .tool_calls
+                .into_iter()
+                .map(|call| ToolCall {
+                    id: default_tool_id(),
+                    tool_type: default_tool_type(),
+                    function: ToolCallFunction {
+                        name: call.name,
+                        arguments: match call.arguments {
+                            Value::Null => "{}".to_string(),
+                            Value::String(s) => s,
+                            other => other.to_string(),
+                        },
+                    },
+                })
+                .collect(),
+        )
     }
 }
 
-// TODO make WIT resource
 /// Handler for custom tool calls
+///
+/// Handlers can also live in another component; see
+/// [`crate::tool_provider`].
 pub trait CustomToolHandler {
     /// Returns true if this handler can handle the given tool name
     fn can_handle(&self, tool_name: &str) -> bool;
 
     /// Execute the tool call and return a result
     fn execute(&self, tool_call: &ToolCall) -> Result<String, String>;
+
+    /// Execute the tool call from inside the runtime
+    ///
+    /// Used by [`ChatRequest::run_agent_async`](crate::ChatRequest::run_agent_async)
+    /// and [`ChatRequest::execute_tools_async`](crate::ChatRequest::execute_tools_async);
+    /// the blocking tool loop calls [`CustomToolHandler::execute`] outside the
+    /// runtime instead. Defaults to `execute`, so handlers whose `execute` uses
+    /// `block_on` must override it to be used from the async loop: the runtime
+    /// cannot be started again from inside it.
+    fn execute_async<'a>(&'a self, tool_call: &'a ToolCall) -> ToolFuture<'a> {
+        Box::pin(std::future::ready(self.execute(tool_call)))
+    }
 }
 
+/// Result of [`CustomToolHandler::execute_async`]
+pub type ToolFuture<'a> = Pin<Box<dyn Future<Output = Result<String, String>> + 'a>>;
+
 /// Default function for tool ID
 fn default_tool_id() -> String {
     use std::sync::atomic::{AtomicU64, Ordering};
@@ -514,6 +699,8 @@ fn default_tool_type() -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::Config;
+    use crate::policy::Policy;
     use serde_json::json;
 
     #[test]
@@ -724,6 +911,326 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_tools_from_contract_artifact() {
+        let artifact = json!({
+            "abi": [
+                {
+                    "name": "deposit",
+                    "type": "function",
+                    "stateMutability": "payable",
+                    "inputs": [
+                        {"name": "recipients", "type": "address[]"},
+                        {"name": "terms", "type": "tuple", "components": [
+                            {"name": "unlockAt", "type": "uint64"},
+                            {"name": "revocable", "type": "bool"}
+                        ]}
+                    ],
+                    "outputs": []
+                },
+                {
+                    "name": "totalAssets",
+                    "type": "function",
+                    "stateMutability": "view",
+                    "inputs": [],
+                    "outputs": [{"name": "", "type": "uint256"}]
+                }
+            ],
+            "userdoc": {"methods": {
+                "deposit(address[],(uint64,bool))": {"notice": "Deposits ETH for the recipients."}
+            }},
+            "devdoc": {"methods": {
+                "deposit(address[],(uint64,bool))": {
+                    "params": {"recipients": "Accounts credited with the deposit"}
+                }
+            }}
+        });
+        let contract = Contract::new(
+            "Vault",
+            "0x1234567890123456789012345678901234567890",
+            &artifact.to_string(),
+        );
+
+        let tools = Tools::tools_from_contract(&contract);
+        assert_eq!(tools.len(), 1, "view functions do not become tools");
+
+        let deposit = &tools[0].function;
+        assert_eq!(deposit.name, "contract_vault_deposit");
+        assert!(deposit
+            .description
+            .as_ref()
+            .unwrap()
+            .ends_with(": Deposits ETH for the recipients."));
+
+        let params = deposit.parameters.as_ref().unwrap();
+        assert_eq!(params["required"], json!(["value", "recipients", "terms"]));
+        let recipients = &params["properties"]["recipients"];
+        assert_eq!(recipients["type"], "array");
+        assert_eq!(recipients["items"]["format"], "ethereum-address");
+        assert_eq!(
+            recipients["description"],
+            "Accounts credited with the deposit (address[])"
+        );
+        let terms = &params["properties"]["terms"];
+        assert_eq!(terms["type"], "object");
+        assert_eq!(terms["properties"]["revocable"]["type"], "boolean");
+        assert_eq!(terms["description"], "terms ((uint64,bool))");
+
+        let args = json!({
+            "value": "1000",
+            "recipients": ["0x1234567890123456789012345678901234567890"],
+            "terms": {"unlockAt": "1700000000", "revocable": false}
+        });
+        assert!(crate::schema::validate(&args, params).is_ok());
+    }
+
+    #[test]
+    fn test_contract_call_encodes_calldata() {
+        // A contract from a loaded config, not the default one; `value` is the
+        // ERC20 amount, not ETH
+        let contracts = vec![Contract::new(
+            "Stable_Coin",
+            "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48",
+            &Config::default().contracts[0].abi,
+        )];
+        let tool_call = ToolCall {
+            id: "call_1".to_string(),
+            tool_type: "function".to_string(),
+            function: ToolCallFunction {
+                name: "contract_stable_coin_transfer".to_string(),
+                arguments:
+                    r#"{"value": "1500000", "to": "0x0987654321098765432109876543210987654321"}"#
+                        .to_string(),
+            },
+        };
+
+        let result = Tools::execute_tool_call(&tool_call, None, &contracts, None, None).unwrap();
+        let tx: Transaction = serde_json::from_str(&result).unwrap();
+        assert_eq!(tx.to, contracts[0].address);
+        assert_eq!(tx.value, "0");
+        let call = tx.contract_call.as_ref().unwrap();
+        assert_eq!(
+            call.args,
+            vec![
+                json!("0x0987654321098765432109876543210987654321"),
+                json!("1500000")
+            ]
+        );
+        assert!(tx.data.starts_with("0xa9059cbb"));
+        assert_eq!(tx.data.len(), 2 + 2 * (4 + 32 * 2));
+
+        let err =
+            Tools::execute_tool_call(&tool_call, None, &Config::default().contracts, None, None)
+                .unwrap_err();
+        assert!(err.contains("Unknown contract tool contract_stable_coin_transfer"));
+
+        let invalid = ToolCall {
+            function: ToolCallFunction {
+                name: "contract_stable_coin_transfer".to_string(),
+                arguments: r#"{"to": "0x0987654321098765432109876543210987654321", "value": "-1"}"#
+                    .to_string(),
+            },
+            ..tool_call
+        };
+        let err = Tools::execute_tool_call(&invalid, None, &contracts, None, None).unwrap_err();
+        assert!(err.contains("Error converting argument 1"), "{}", err);
+    }
+
+    #[test]
+    fn test_policy_checks_tool_results() {
+        let contracts = vec![Contract::new(
+            "Stable_Coin",
+            "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48",
+            &Config::default().contracts[0].abi,
+        )];
+        let policy = PolicyEngine::new(
+            Policy::new()
+                .with_token_limit(&contracts[0].address, "1000000")
+                .with_require_description(true),
+        );
+        let transfer = |arguments: Value| ToolCall {
+            id: "call_1".to_string(),
+            tool_type: "function".to_string(),
+            function: ToolCallFunction {
+                name: "contract_stable_coin_transfer".to_string(),
+                arguments: arguments.to_string(),
+            },
+        };
+        let to = "0x0987654321098765432109876543210987654321";
+
+        let tool = Tools::tools_from_contract(&contracts[0])
+            .into_iter()
+            .find(|t| t.function.name == "contract_stable_coin_transfer")
+            .unwrap();
+        assert!(tool.function.parameters.unwrap()["properties"]["description"].is_object());
+
+        let approved = Tools::execute_tool_call(
+            &transfer(json!({"to": to, "value": "1000000", "description": "Pay the auditor"})),
+            None,
+            &contracts,
+            None,
+            Some(&policy),
+        )
+        .unwrap();
+        let tx: Transaction = serde_json::from_str(&approved).unwrap();
+        assert_eq!(tx.description, "Pay the auditor");
+
+        let err = Tools::execute_tool_call(
+            &transfer(json!({"to": to, "value": "1000001"})),
+            None,
+            &contracts,
+            None,
+            Some(&policy),
+        )
+        .unwrap_err();
+        let denial: Value = serde_json::from_str(&err).unwrap();
+        let rules: Vec<&str> = denial["violations"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| v["rule"].as_str().unwrap())
+            .collect();
+        assert_eq!(rules, ["token_limit", "missing_description"]);
+
+        // Results that are not transactions pass through
+        let handlers: Vec<Box<dyn CustomToolHandler>> = vec![Box::new(TestToolHandler)];
+        let call = ToolCall {
+            function: ToolCallFunction {
+                name: "test_tool".to_string(),
+                arguments: json!({"test_param": "hello"}).to_string(),
+            },
+            ..transfer(json!({}))
+        };
+        assert!(Tools::execute_tool_call(&call, Some(&handlers), &[], None, Some(&policy)).is_ok());
+    }
+
+    #[test]
+    fn test_custom_transactions_are_validated() {
+        struct Drainer;
+
+        impl CustomToolHandler for Drainer {
+            fn can_handle(&self, tool_name: &str) -> bool {
+                tool_name == "drain"
+            }
+
+            fn execute(&self, tool_call: &ToolCall) -> Result<String, String> {
+                let function: Value = serde_json::from_str(&tool_call.function.arguments)
+                    .map_err(|e| e.to_string())?;
+                Ok(json!({
+                    "to": "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48",
+                    "value": "0",
+                    "data": "0x",
+                    "description": "",
+                    "contract_call": {"function": function["function"], "args": ["0x0987654321098765432109876543210987654321", "5"]}
+                })
+                .to_string())
+            }
+        }
+
+        let contracts = vec![Contract::new(
+            "Stable_Coin",
+            "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48",
+            &Config::default().contracts[0].abi,
+        )];
+        let handlers: Vec<Box<dyn CustomToolHandler>> = vec![Box::new(Drainer)];
+        let call = |function: &str| ToolCall {
+            id: "call_1".to_string(),
+            tool_type: "function".to_string(),
+            function: ToolCallFunction {
+                name: "drain".to_string(),
+                arguments: json!({"function": function}).to_string(),
+            },
+        };
+
+        assert!(Tools::execute_tool_call(
+            &call("transfer"),
+            Some(&handlers),
+            &contracts,
+            None,
+            None
+        )
+        .is_ok());
+        let err = Tools::execute_tool_call(&call("sweep"), Some(&handlers), &contracts, None, None)
+            .unwrap_err();
+        assert!(err.contains("not found in ABI"), "{}", err);
+
+        // Without contracts there is nothing to validate against
+        assert!(Tools::execute_tool_call(&call("sweep"), Some(&handlers), &[], None, None).is_ok());
+    }
+
+    #[test]
+    fn test_overloaded_contract_call() {
+        let contracts = vec![Contract::new(
+            "Art",
+            "0x1234567890123456789012345678901234567890",
+            r#"[
+                {"name": "safeTransferFrom", "type": "function", "stateMutability": "nonpayable",
+                 "inputs": [{"name": "from", "type": "address"}, {"name": "to", "type": "address"},
+                            {"name": "tokenId", "type": "uint256"}], "outputs": []},
+                {"name": "safeTransferFrom", "type": "function", "stateMutability": "nonpayable",
+                 "inputs": [{"name": "from", "type": "address"}, {"name": "to", "type": "address"},
+                            {"name": "tokenId", "type": "uint256"}, {"name": "data", "type": "bytes"}],
+                 "outputs": []},
+                {"name": "ownerOf", "type": "function", "stateMutability": "view",
+                 "inputs": [{"name": "tokenId", "type": "uint256"}],
+                 "outputs": [{"name": "", "type": "address"}]}
+            ]"#,
+        )];
+
+        let tools = Tools::tools_from_contract(&contracts[0]);
+        let with_data = tools
+            .iter()
+            .find(|t| t.function.name == "contract_art_safeTransferFrom_b88d4fde")
+            .unwrap();
+        assert!(with_data
+            .function
+            .description
+            .as_ref()
+            .unwrap()
+            .contains("safeTransferFrom(address,address,uint256,bytes)"));
+
+        let call = |name: &str, arguments: Value| ToolCall {
+            id: "call_1".to_string(),
+            tool_type: "function".to_string(),
+            function: ToolCallFunction {
+                name: name.to_string(),
+                arguments: arguments.to_string(),
+            },
+        };
+        let args = json!({
+            "from": "0x1234567890123456789012345678901234567890",
+            "to": "0x0987654321098765432109876543210987654321",
+            "tokenId": "7",
+            "data": "0x"
+        });
+
+        let result = Tools::execute_tool_call(
+            &call("contract_art_safeTransferFrom_b88d4fde", args),
+            None,
+            &contracts,
+            None,
+            None,
+        )
+        .unwrap();
+        let tx: Transaction = serde_json::from_str(&result).unwrap();
+        assert!(tx.data.starts_with("0xb88d4fde"));
+        assert_eq!(
+            tx.contract_call.unwrap().function,
+            "safeTransferFrom(address,address,uint256,bytes)"
+        );
+
+        // Views are not transactions
+        let err = Tools::execute_tool_call(
+            &call("contract_art_ownerOf", json!({"tokenId": "7"})),
+            None,
+            &contracts,
+            None,
+            None,
+        )
+        .unwrap_err();
+        assert!(err.contains("read-only"));
+    }
+
     struct TestToolHandler;
 
     impl CustomToolHandler for TestToolHandler {
@@ -788,4 +1295,100 @@ mod tests {
         let result = handler.execute(&invalid_tool_call);
         assert!(result.is_err());
     }
+
+    fn test_tool_calls_message() -> Message {
+        let mut response = Message::assistant("");
+        response.tool_calls = Some(vec![
+            ToolCall {
+                id: "call_1".to_string(),
+                tool_type: "function".to_string(),
+                function: ToolCallFunction {
+                    name: "test_tool".to_string(),
+                    arguments: r#"{"test_param": "abc"}"#.to_string(),
+                },
+            },
+            ToolCall {
+                id: "call_2".to_string(),
+                tool_type: "function".to_string(),
+                function: ToolCallFunction {
+                    name: "missing_tool".to_string(),
+                    arguments: "{}".to_string(),
+                },
+            },
+        ]);
+        response
+    }
+
+    #[test]
+    fn test_process_tool_calls() {
+        let handlers: Vec<Box<dyn CustomToolHandler>> = vec![Box::new(TestToolHandler)];
+        let response = test_tool_calls_message();
+
+        let messages = Tools::process_tool_calls(
+            &response,
+            &ModelCapabilities::default(),
+            Some(&handlers),
+            &[],
+            None,
+            None,
+        );
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0].role, "assistant");
+        assert_eq!(messages[0].tool_calls.as_ref().unwrap().len(), 2);
+        assert_eq!(messages[1].role, "tool");
+        assert_eq!(messages[1].tool_call_id.as_deref(), Some("call_1"));
+        assert!(messages[1].content.as_ref().unwrap().contains("abc"));
+
+        // Failures are returned to the model instead of aborting the run
+        assert_eq!(messages[2].tool_call_id.as_deref(), Some("call_2"));
+        assert!(messages[2]
+            .content
+            .as_ref()
+            .unwrap()
+            .starts_with("Error: Unknown tool"));
+    }
+
+    #[test]
+    fn test_process_tool_calls_by_capability() {
+        let handlers: Vec<Box<dyn CustomToolHandler>> = vec![Box::new(TestToolHandler)];
+        let response = test_tool_calls_message();
+
+        // Only the first call runs when parallel calls are unsupported
+        let sequential = ModelCapabilities::default().with_parallel_tool_calls(false);
+        let messages =
+            Tools::process_tool_calls(&response, &sequential, Some(&handlers), &[], None, None);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].tool_calls.as_ref().unwrap().len(), 1);
+
+        // Prompted models get results back as plain user messages
+        let prompted = ModelCapabilities::default().with_native_tools(false);
+        let messages =
+            Tools::process_tool_calls(&response, &prompted, Some(&handlers), &[], None, None);
+        assert!(messages[0].tool_calls.is_none());
+        assert_eq!(messages[1].role, "user");
+        assert!(messages[1]
+            .content
+            .as_ref()
+            .unwrap()
+            .starts_with("Result of tool test_tool (call call_1)"));
+    }
+
+    #[test]
+    fn test_prompted_tool_calls() {
+        let instructions = Tools::prompted_tool_instructions(&[Tools::send_eth_tool()]);
+        assert!(instructions.contains("\"tool_calls\""));
+        assert!(instructions.contains("send_eth"));
+
+        let content = r#"```json
+{"tool_calls": [{"name": "send_eth", "arguments": {"to": "0xabc", "value": "1"}}]}
+```"#;
+        let calls = Tools::parse_prompted_tool_calls(content).unwrap();
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].function.name, "send_eth");
+        let args: Value = serde_json::from_str(&calls[0].function.arguments).unwrap();
+        assert_eq!(args["value"], "1");
+
+        assert!(Tools::parse_prompted_tool_calls("The answer is 4.").is_none());
+        assert!(Tools::parse_prompted_tool_calls(r#"{"tool_calls": []}"#).is_none());
+    }
 }
@@ -0,0 +1,350 @@
+//! Tools defined by typed Rust functions
+//!
+//! A [`TypedTool`] wraps a function whose argument type implements
+//! `JsonSchema + Deserialize`. The tool definition is generated from that
+//! type, calls are validated against it and deserialized before the function
+//! runs, and the return value is serialized back for the model.
+//!
+//! ```no_run
+//! use schemars::JsonSchema;
+//! use serde::Deserialize;
+//! use wavs_llm::{typed_tool, Toolset};
+//!
+//! /// Token to look up
+//! #[derive(Deserialize, JsonSchema)]
+//! struct PriceArgs {
+//!     /// Token symbol, e.g. ETH
+//!     symbol: String,
+//! }
+//!
+//! fn get_price(args: PriceArgs) -> Result<f64, String> {
+//!     match args.symbol.as_str() {
+//!         "ETH" => Ok(3000.0),
+//!         other => Err(format!("Unknown token {}", other)),
+//!     }
+//! }
+//!
+//! let toolset = Toolset::new().with_tool(typed_tool!(get_price, "Get a token's USD price"));
+//! ```
+
+use crate::schema;
+use crate::tool_provider::ToolProvider;
+use crate::tools::{CustomToolHandler, Tool, ToolCall, Tools};
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::fmt;
+
+type Handler = Box<dyn Fn(Value) -> Result<String, String>>;
+
+/// A tool backed by a function taking typed arguments
+pub struct TypedTool {
+    definition: Tool,
+    handler: Handler,
+}
+
+impl TypedTool {
+    /// Define a tool that calls `handler` with arguments of type `A`
+    ///
+    /// The parameters schema is `A`'s JSON schema, so `A` should be a struct
+    /// (providers expect an object). Field doc comments become parameter
+    /// descriptions. A result that serializes to a JSON string is returned
+    /// as is; anything else is returned as JSON.
+    pub fn new<A, R, E>(
+        name: &str,
+        description: &str,
+        handler: impl Fn(A) -> Result<R, E> + 'static,
+    ) -> Self
+    where
+        A: JsonSchema + DeserializeOwned,
+        R: Serialize,
+        E: fmt::Display,
+    {
+        let definition = Tools::custom_tool(name, description, parameters_schema::<A>());
+        let name = name.to_string();
+        let handler = move |args: Value| {
+            let args: A = serde_json::from_value(args)
+                .map_err(|e| format!("Invalid arguments for {}: {}", name, e))?;
+            let result = handler(args).map_err(|e| e.to_string())?;
+            match serde_json::to_value(result) {
+                Ok(Value::String(text)) => Ok(text),
+                Ok(value) => Ok(value.to_string()),
+                Err(e) => Err(format!("Failed to serialize result of {}: {}", name, e)),
+            }
+        };
+
+        Self {
+            definition,
+            handler: Box::new(handler),
+        }
+    }
+
+    /// Name the model calls the tool by
+    pub fn name(&self) -> &str {
+        &self.definition.function.name
+    }
+
+    /// Tool definition offered to the model
+    pub fn definition(&self) -> &Tool {
+        &self.definition
+    }
+
+    /// Validate the call's arguments against the schema and run the handler
+    fn call(&self, tool_call: &ToolCall) -> Result<String, String> {
+        let arguments = tool_call.function.arguments.trim();
+        let args: Value = if arguments.is_empty() {
+            Value::Object(Default::default())
+        } else {
+            serde_json::from_str(arguments)
+                .map_err(|e| format!("Failed to parse function arguments: {}", e))?
+        };
+
+        if let Some(parameters) = &self.definition.function.parameters {
+            schema::validate(&args, parameters).map_err(|violations| {
+                format!(
+                    "Invalid arguments for {}:\n{}",
+                    self.name(),
+                    schema::describe_violations(&violations)
+                )
+            })?;
+        }
+
+        (self.handler)(args)
+    }
+}
+
+impl CustomToolHandler for TypedTool {
+    fn can_handle(&self, tool_name: &str) -> bool {
+        self.name() == tool_name
+    }
+
+    fn execute(&self, tool_call: &ToolCall) -> Result<String, String> {
+        self.call(tool_call)
+    }
+}
+
+impl fmt::Debug for TypedTool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedTool")
+            .field("definition", &self.definition)
+            .finish_non_exhaustive()
+    }
+}
+
+/// JSON schema of `A`, without the document-level keys providers do not expect
+fn parameters_schema<A: JsonSchema>() -> Value {
+    let mut schema = schemars::schema_for!(A).to_value();
+    if let Some(object) = schema.as_object_mut() {
+        object.remove("$schema");
+        object.remove("title");
+    }
+    schema
+}
+
+/// Define a [`TypedTool`] named after a function
+///
+/// `typed_tool!(get_price, "Get a token's USD price")` is
+/// `TypedTool::new("get_price", "Get a token's USD price", get_price)`.
+#[macro_export]
+macro_rules! typed_tool {
+    ($handler:ident, $description:expr) => {
+        $crate::toolset::TypedTool::new(stringify!($handler), $description, $handler)
+    };
+}
+
+/// A set of typed tools, handled together
+///
+/// Pass it to [`ChatRequest::with_toolset`](crate::ChatRequest::with_toolset),
+/// or export it as a [`ToolProvider`].
+#[derive(Debug, Default)]
+pub struct Toolset {
+    tools: Vec<TypedTool>,
+}
+
+impl Toolset {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a tool, replacing any tool with the same name
+    pub fn with_tool(mut self, tool: TypedTool) -> Self {
+        self.tools.retain(|existing| existing.name() != tool.name());
+        self.tools.push(tool);
+        self
+    }
+
+    /// Add a tool defined by `handler`; see [`TypedTool::new`]
+    pub fn with_fn<A, R, E>(
+        self,
+        name: &str,
+        description: &str,
+        handler: impl Fn(A) -> Result<R, E> + 'static,
+    ) -> Self
+    where
+        A: JsonSchema + DeserializeOwned,
+        R: Serialize,
+        E: fmt::Display,
+    {
+        self.with_tool(TypedTool::new(name, description, handler))
+    }
+
+    /// Tool definitions offered to the model
+    pub fn tools(&self) -> Vec<Tool> {
+        self.tools
+            .iter()
+            .map(|tool| tool.definition.clone())
+            .collect()
+    }
+
+    fn find(&self, tool_name: &str) -> Option<&TypedTool> {
+        self.tools.iter().find(|tool| tool.name() == tool_name)
+    }
+}
+
+impl CustomToolHandler for Toolset {
+    fn can_handle(&self, tool_name: &str) -> bool {
+        self.find(tool_name).is_some()
+    }
+
+    fn execute(&self, tool_call: &ToolCall) -> Result<String, String> {
+        self.find(&tool_call.function.name)
+            .ok_or_else(|| format!("Unknown tool: {}", tool_call.function.name))?
+            .call(tool_call)
+    }
+}
+
+impl ToolProvider for Toolset {
+    fn tools(&self) -> Vec<Tool> {
+        Toolset::tools(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::ToolCallFunction;
+    use serde::Deserialize;
+    use serde_json::json;
+
+    /// Amount to move between accounts
+    #[derive(Deserialize, JsonSchema)]
+    struct TransferArgs {
+        /// Destination address
+        to: String,
+        /// Amount in wei
+        amount: u64,
+        memo: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    struct Receipt {
+        to: String,
+        amount: u64,
+        memo: Option<String>,
+    }
+
+    fn transfer(args: TransferArgs) -> Result<Receipt, String> {
+        if args.amount == 0 {
+            return Err("Amount must be positive".to_string());
+        }
+        Ok(Receipt {
+            to: args.to,
+            amount: args.amount,
+            memo: args.memo,
+        })
+    }
+
+    fn call(name: &str, arguments: &str) -> ToolCall {
+        ToolCall {
+            id: "call_1".to_string(),
+            tool_type: "function".to_string(),
+            function: ToolCallFunction {
+                name: name.to_string(),
+                arguments: arguments.to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn test_definition_from_args_type() {
+        let tool = typed_tool!(transfer, "Transfer funds");
+        assert_eq!(tool.name(), "transfer");
+
+        let function = &tool.definition().function;
+        assert_eq!(function.description.as_deref(), Some("Transfer funds"));
+        let parameters = function.parameters.as_ref().unwrap();
+        assert_eq!(parameters["type"], "object");
+        assert_eq!(parameters["required"], json!(["to", "amount"]));
+        assert_eq!(
+            parameters["properties"]["to"]["description"],
+            "Destination address"
+        );
+        assert!(parameters.get("$schema").is_none());
+        assert!(parameters.get("title").is_none());
+    }
+
+    #[test]
+    fn test_execute_validates_and_serializes() {
+        let tool = typed_tool!(transfer, "Transfer funds");
+
+        let result = tool
+            .execute(&call("transfer", r#"{"to": "0xabc", "amount": 5}"#))
+            .unwrap();
+        assert_eq!(
+            serde_json::from_str::<Value>(&result).unwrap(),
+            json!({"to": "0xabc", "amount": 5, "memo": null})
+        );
+
+        let err = tool
+            .execute(&call("transfer", r#"{"to": "0xabc", "amount": "5"}"#))
+            .unwrap_err();
+        assert!(err.starts_with("Invalid arguments for transfer:"));
+        assert!(err.contains("$.amount"));
+
+        let err = tool
+            .execute(&call("transfer", r#"{"to": "0xabc"}"#))
+            .unwrap_err();
+        assert!(err.contains("amount"));
+
+        let err = tool
+            .execute(&call("transfer", r#"{"to": "0xabc", "amount": 0}"#))
+            .unwrap_err();
+        assert_eq!(err, "Amount must be positive");
+    }
+
+    #[test]
+    fn test_toolset_dispatch() {
+        #[derive(Deserialize, JsonSchema)]
+        struct Greeting {
+            name: String,
+        }
+
+        let toolset = Toolset::new()
+            .with_tool(typed_tool!(transfer, "Transfer funds"))
+            .with_fn("greet", "Greet someone", |args: Greeting| {
+                Ok::<_, String>(format!("Hello, {}", args.name))
+            });
+
+        let names: Vec<_> = toolset
+            .tools()
+            .into_iter()
+            .map(|t| t.function.name)
+            .collect();
+        assert_eq!(names, ["transfer", "greet"]);
+        assert!(toolset.can_handle("greet"));
+        assert!(!toolset.can_handle("send_eth"));
+
+        // String results are returned without JSON quoting
+        let result = toolset
+            .execute(&call("greet", r#"{"name": "Ada"}"#))
+            .unwrap();
+        assert_eq!(result, "Hello, Ada");
+
+        let replaced = toolset.with_fn("greet", "Greet again", |_: Greeting| Ok::<_, String>("Hi"));
+        assert_eq!(replaced.tools().len(), 2);
+        assert_eq!(
+            replaced.tools()[1].function.description.as_deref(),
+            Some("Greet again")
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Vector utilities for embeddings
+//!
+//! Similarity and search accumulate in `f64` in a fixed order, so the same
+//! vectors give the same scores on every operator. [`quantize`] and
+//! [`commitment`] reduce a vector to bytes that can be committed on-chain.
+
+use crate::errors::LlmError;
+use alloy_primitives::{keccak256, B256};
+
+/// A candidate found by [`top_k`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Match {
+    /// Position of the candidate in the list searched
+    pub index: usize,
+    /// Cosine similarity to the query
+    pub score: f32,
+}
+
+/// Dot product of two vectors of the same length
+pub fn dot(a: &[f32], b: &[f32]) -> Result<f32, LlmError> {
+    check_lengths(a, b)?;
+    Ok(dot_f64(a, b) as f32)
+}
+
+/// Euclidean length of a vector
+pub fn norm(v: &[f32]) -> f32 {
+    dot_f64(v, v).sqrt() as f32
+}
+
+/// The vector scaled to length 1; a zero vector stays zero
+pub fn normalize(v: &[f32]) -> Vec<f32> {
+    let norm = dot_f64(v, v).sqrt();
+    if norm == 0.0 {
+        return v.to_vec();
+    }
+    v.iter().map(|&x| (x as f64 / norm) as f32).collect()
+}
+
+/// Cosine similarity from -1 to 1; 0 when either vector is zero
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> Result<f32, LlmError> {
+    check_lengths(a, b)?;
+    let norms = dot_f64(a, a).sqrt() * dot_f64(b, b).sqrt();
+    if norms == 0.0 {
+        return Ok(0.0);
+    }
+    Ok((dot_f64(a, b) / norms).clamp(-1.0, 1.0) as f32)
+}
+
+/// The `k` candidates most similar to `query`, best first
+///
+/// Equal scores are ordered by index, so results do not depend on the sort.
+pub fn top_k(query: &[f32], candidates: &[Vec<f32>], k: usize) -> Result<Vec<Match>, LlmError> {
+    let mut matches = candidates
+        .iter()
+        .enumerate()
+        .map(|(index, candidate)| {
+            Ok(Match {
+                index,
+                score: cosine_similarity(query, candidate)?,
+            })
+        })
+        .collect::<Result<Vec<_>, LlmError>>()?;
+    matches.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.index.cmp(&b.index)));
+    matches.truncate(k);
+    Ok(matches)
+}
+
+/// Normalize a vector and round each component to an `i8` in `-127..=127`
+///
+/// Rounding is half away from zero, so operators whose vectors agree to
+/// within rounding produce the same bytes.
+pub fn quantize(v: &[f32]) -> Result<Vec<i8>, LlmError> {
+    if let Some(i) = v.iter().position(|x| !x.is_finite()) {
+        return Err(LlmError::InvalidInput(format!(
+            "Cannot quantize: component {} is {}",
+            i, v[i]
+        )));
+    }
+    let norm = dot_f64(v, v).sqrt();
+    Ok(v.iter()
+        .map(|&x| {
+            let unit = if norm == 0.0 { 0.0 } else { x as f64 / norm };
+            (unit * 127.0).round().clamp(-127.0, 127.0) as i8
+        })
+        .collect())
+}
+
+/// keccak256 of the quantized components as two's complement bytes
+///
+/// Matches `keccak256(data)` in Solidity for `bytes data` holding one byte per component.
+pub fn commitment(quantized: &[i8]) -> B256 {
+    let bytes: Vec<u8> = quantized.iter().map(|&x| x as u8).collect();
+    keccak256(bytes)
+}
+
+fn dot_f64(a: &[f32], b: &[f32]) -> f64 {
+    a.iter().zip(b).map(|(&x, &y)| x as f64 * y as f64).sum()
+}
+
+fn check_lengths(a: &[f32], b: &[f32]) -> Result<(), LlmError> {
+    if a.len() != b.len() {
+        return Err(LlmError::InvalidInput(format!(
+            "Vectors have different dimensions: {} and {}",
+            a.len(),
+            b.len()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_similarity() {
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[2.0, 0.0]).unwrap(), 1.0);
+        assert_eq!(cosine_similarity(&[1.0, 0.0], &[0.0, 3.0]).unwrap(), 0.0);
+        assert_eq!(cosine_similarity(&[1.0, 1.0], &[-1.0, -1.0]).unwrap(), -1.0);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 1.0]).unwrap(), 0.0);
+        assert!(cosine_similarity(&[1.0], &[1.0, 0.0]).is_err());
+
+        assert_eq!(dot(&[1.0, 2.0], &[3.0, 4.0]).unwrap(), 11.0);
+        assert_eq!(norm(&[3.0, 4.0]), 5.0);
+        assert_eq!(normalize(&[3.0, 4.0]), [0.6, 0.8]);
+        assert_eq!(normalize(&[0.0, 0.0]), [0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_top_k() {
+        let candidates = vec![
+            vec![0.0, 1.0],
+            vec![1.0, 0.1],
+            vec![2.0, 0.2],
+            vec![-1.0, 0.0],
+        ];
+        let matches = top_k(&[1.0, 0.1], &candidates, 3).unwrap();
+        // Candidates 1 and 2 point the same way; the earlier one ranks first
+        assert_eq!(
+            matches.iter().map(|m| m.index).collect::<Vec<_>>(),
+            [1, 2, 0]
+        );
+        assert_eq!(matches[0].score, matches[1].score);
+        assert_eq!(top_k(&[1.0, 0.0], &candidates, 10).unwrap().len(), 4);
+        assert!(top_k(&[1.0], &candidates, 1).is_err());
+    }
+
+    #[test]
+    fn test_quantize_and_commit() {
+        assert_eq!(quantize(&[3.0, -4.0, 0.0]).unwrap(), [76, -102, 0]);
+        // Scale does not matter, only direction
+        assert_eq!(
+            quantize(&[0.3, -0.4, 0.0]).unwrap(),
+            quantize(&[30.0, -40.0, 0.0]).unwrap()
+        );
+        assert_eq!(quantize(&[1.0, 0.0]).unwrap(), [127, 0]);
+        assert_eq!(quantize(&[0.0, 0.0]).unwrap(), [0, 0]);
+        assert!(quantize(&[f32::NAN]).is_err());
+
+        assert_eq!(commitment(&[76, -102, 0]), keccak256([76u8, 154, 0]));
+        assert_ne!(commitment(&[76, -102, 0]), commitment(&[76, -102, 1]));
+    }
+}